@@ -1,66 +1,750 @@
 use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::process::ExitCode;
 
 use anyhow::anyhow;
-use anyhow::Result;
-use clap::{Parser, ValueEnum};
-use personalgreenbutton::{parse_xml, TimeSeries};
+use anyhow::{Context, Result};
+use chrono::{NaiveDate, NaiveTime};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use personalgreenbutton::{
+    parse_green_button, parse_weather_csv, AnomalyOptions, DegreeDayOptions, GreenButtonCsvDialect,
+    GreenButtonCsvOptions, HomeAssistantEnergyUnit, HomeAssistantOptions, InfluxdbMeasurement,
+    InfluxdbOptions, InfluxdbPrecision, LoadProfileColumns, LoadProfileStatistic,
+    OpenMetricsOptions, ParquetCompression, ParquetOptions, Period, SqlDialect, SqlLoadStatement,
+    SqlScriptOptions, TimeSeries, WeatherCsvOptions, WideCsvFill, WideCsvOptions,
+};
+use push::{push_influxdb, InfluxdbPushOptions};
+use serde_json::json;
+
+mod push;
+mod report;
+
+// Exit codes. Usage errors also exit with 2, from clap.
+const EXIT_INVALID: u8 = 1;
+const EXIT_ERROR: u8 = 2;
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 enum FileType {
     CSV,
     Influxdb,
     Parquet,
+    /// Directory of parquet files partitioned by title, commodity, year and month. Partitions
+    /// present in the input replace those already in the directory.
+    ParquetDataset,
+    /// Arrow IPC file format (Feather v2).
+    Arrow,
+    /// Arrow IPC streaming format.
+    ArrowStream,
+    /// Column oriented JSON with series metadata.
+    Json,
+    /// Newline delimited JSON, one series or reading per line.
+    Ndjson,
+    /// Normalized SQLite database. Existing databases are updated in place.
+    Sqlite,
+    /// Green Button (ESPI) XML.
+    Espi,
+    /// One row per interval start and one column per series, see --wide-column-name.
+    WideCsv,
+    /// Hour of day by --profile-columns matrix of values, as CSV.
+    LoadProfile,
+    /// Excel workbook with a sheet per series, a daily summary and metadata.
+    Xlsx,
+    /// OpenMetrics text with timestamps, for promtool tsdb create-blocks-from openmetrics.
+    Openmetrics,
+    /// Home Assistant hourly statistics, as recorder/import_statistics JSON.
+    HomeAssistantJson,
+    /// Home Assistant hourly statistics, as CSV for the import_statistics integration.
+    HomeAssistantCsv,
+    /// ENERGY STAR Portfolio Manager meter consumption, per month or --billing-dates period.
+    PortfolioManager,
+    /// SQL script that creates a table and loads the readings, see --sql-dialect.
+    Sql,
+    /// Frictionless data package: CSV plus datapackage.json, written into the --out directory.
+    DataPackage,
+    /// Utility style Green Button CSV, see --gb-csv-dialect.
+    GreenButtonCsv,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ProfileColumns {
+    Weekday,
+    Month,
+    Date,
+}
+
+impl From<ProfileColumns> for LoadProfileColumns {
+    fn from(x: ProfileColumns) -> Self {
+        return match x {
+            ProfileColumns::Weekday => LoadProfileColumns::Weekday,
+            ProfileColumns::Month => LoadProfileColumns::Month,
+            ProfileColumns::Date => LoadProfileColumns::Date,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum ProfileStatistic {
+    Mean,
+    Median,
+    P95,
+    Sum,
+}
+
+impl From<ProfileStatistic> for LoadProfileStatistic {
+    fn from(x: ProfileStatistic) -> Self {
+        return match x {
+            ProfileStatistic::Mean => LoadProfileStatistic::Mean,
+            ProfileStatistic::Median => LoadProfileStatistic::Median,
+            ProfileStatistic::P95 => LoadProfileStatistic::P95,
+            ProfileStatistic::Sum => LoadProfileStatistic::Sum,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Compression {
+    None,
+    Snappy,
+    Gzip,
+    Zstd,
+}
+
+impl From<Compression> for ParquetCompression {
+    fn from(x: Compression) -> Self {
+        return match x {
+            Compression::None => ParquetCompression::None,
+            Compression::Snappy => ParquetCompression::Snappy,
+            Compression::Gzip => ParquetCompression::Gzip,
+            Compression::Zstd => ParquetCompression::Zstd,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum EnergyUnit {
+    Wh,
+    Kwh,
+    Mwh,
+}
+
+impl From<EnergyUnit> for HomeAssistantEnergyUnit {
+    fn from(x: EnergyUnit) -> Self {
+        return match x {
+            EnergyUnit::Wh => HomeAssistantEnergyUnit::Wh,
+            EnergyUnit::Kwh => HomeAssistantEnergyUnit::KWh,
+            EnergyUnit::Mwh => HomeAssistantEnergyUnit::MWh,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Dialect {
+    Postgres,
+    Sqlite,
+    Duckdb,
+}
+
+impl From<Dialect> for SqlDialect {
+    fn from(x: Dialect) -> Self {
+        return match x {
+            Dialect::Postgres => SqlDialect::Postgres,
+            Dialect::Sqlite => SqlDialect::Sqlite,
+            Dialect::Duckdb => SqlDialect::Duckdb,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum CsvDialect {
+    /// ISO dates and 24 hour times, as PG&E.
+    Typed,
+    /// US dates and 12 hour times.
+    TypedUsDates,
+    /// "<start> to <end>" time periods, as SCE.
+    TimePeriod,
+}
+
+impl From<CsvDialect> for GreenButtonCsvDialect {
+    fn from(x: CsvDialect) -> Self {
+        return match x {
+            CsvDialect::Typed => GreenButtonCsvDialect::Typed,
+            CsvDialect::TypedUsDates => GreenButtonCsvDialect::TypedUsDates,
+            CsvDialect::TimePeriod => GreenButtonCsvDialect::TimePeriod,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Fill {
+    Empty,
+    Zero,
+    Previous,
+}
+
+impl From<Fill> for WideCsvFill {
+    fn from(x: Fill) -> Self {
+        return match x {
+            Fill::Empty => WideCsvFill::Empty,
+            Fill::Zero => WideCsvFill::Zero,
+            Fill::Previous => WideCsvFill::Previous,
+        };
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum Precision {
+    S,
+    Ms,
+    Us,
+    Ns,
+}
+
+impl From<Precision> for InfluxdbPrecision {
+    fn from(x: Precision) -> Self {
+        return match x {
+            Precision::S => InfluxdbPrecision::Seconds,
+            Precision::Ms => InfluxdbPrecision::Milliseconds,
+            Precision::Us => InfluxdbPrecision::Microseconds,
+            Precision::Ns => InfluxdbPrecision::Nanoseconds,
+        };
+    }
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// Hourly weather CSV to join, adding temperature and degree day columns.
+    #[arg(long)]
+    weather: Option<std::path::PathBuf>,
+    /// Offset from UTC in seconds, for weather files recorded in UTC (NOAA ISD, Meteostat). Only
+    /// used for readings without LocalTimeParameters, otherwise their DST rules are used.
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    weather_utc_offset: i64,
+    /// Base temperature in Celsius for heating and cooling degree days.
+    #[arg(long, default_value_t = 18.0, allow_hyphen_values = true)]
+    degree_day_base: f32,
+    /// Add an "anomaly" column flagging spikes, flatlines, zero runs and negative deltas.
+    #[arg(long)]
+    flag_anomalies: bool,
+    /// Paths of input files: Green Button XML or CSV, or csv or parquet exports from this tool.
+    paths: Vec<std::path::PathBuf>,
+}
+
+#[derive(Args)]
+struct InfluxArgs {
+    /// InfluxDB measurement name for every line. Defaults to the sanitized title.
+    #[arg(long)]
+    influx_measurement: Option<String>,
+    /// Use the title as is for the InfluxDB measurement, rather than sanitizing it.
+    #[arg(long, conflicts_with = "influx_measurement")]
+    influx_raw_title: bool,
+    /// Comma separated columns to write as InfluxDB tags, rather than fields.
+    #[arg(long, value_delimiter = ',')]
+    influx_tags: Option<Vec<String>>,
+    /// Extra KEY=VALUE tag added to every InfluxDB line. May be repeated.
+    #[arg(long, value_parser = parse_key_value)]
+    influx_tag: Vec<(String, String)>,
+    /// InfluxDB timestamp precision.
+    #[arg(long, value_enum, default_value = "ns")]
+    influx_precision: Precision,
+    /// Write tou and time_period_duration_seconds as InfluxDB integer fields rather than floats.
+    /// Existing databases with float fields will reject these writes.
+    #[arg(long)]
+    influx_integer_fields: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Convert the input files to another format.
+    Convert(ConvertArgs),
+    /// Print the feed structure and reading counts of each input file.
+    Inspect(ReportArgs),
+    /// Report spec violations in each input file. Exits with 1 if there are any.
+    Validate(ReportArgs),
+    /// Print the totals and date range of each series.
+    Summary(ReportArgs),
+    /// Like convert, but readings repeated across the input files are written once, from the
+    /// last file that has them. Readings repeated within a file, like the hour repeated when DST
+    /// ends, are all kept.
+    Merge(ConvertArgs),
+    /// Send the readings to a database, rather than writing a file.
+    #[command(subcommand)]
+    Push(PushTarget),
+}
+
+#[derive(Args)]
+struct ReportArgs {
+    /// Print JSON, rather than text.
+    #[arg(long)]
+    json: bool,
+    /// Paths of input files: Green Button XML or CSV, or csv or parquet exports from this tool.
+    #[arg(required = true)]
+    paths: Vec<std::path::PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum PushTarget {
+    /// POST line protocol to an InfluxDB v2 or v3 /api/v2/write endpoint.
+    Influxdb {
+        /// Base URL of the server, e.g. http://localhost:8086.
+        #[arg(long)]
+        url: String,
+        /// Organization. Ignored by InfluxDB 3.
+        #[arg(long, default_value = "")]
+        org: String,
+        /// Bucket, or database for InfluxDB 3.
+        #[arg(long)]
+        bucket: String,
+        /// API token.
+        #[arg(long, env = "INFLUX_TOKEN", hide_env_values = true)]
+        token: Option<String>,
+        /// Lines per request.
+        #[arg(long, default_value_t = 5000)]
+        batch_size: usize,
+        /// Retries per request on connection errors and 429 and 5xx responses.
+        #[arg(long, default_value_t = 5)]
+        max_retries: u32,
+        /// Print the result as JSON.
+        #[arg(long)]
+        json: bool,
+        #[command(flatten)]
+        input: InputArgs,
+        #[command(flatten)]
+        influx: InfluxArgs,
+    },
 }
 
 #[derive(Parser)]
+#[command(version, about = "Converts and checks Green Button data.")]
 struct Cli {
-    /// Input files.
-    #[arg(short, long, value_enum, value_name = "FILETYPE")]
+    #[command(subcommand)]
+    command: Command,
+}
+
+impl Cli {
+    fn json(&self) -> bool {
+        return match &self.command {
+            Command::Convert(x) | Command::Merge(x) => x.json,
+            Command::Inspect(x) | Command::Validate(x) | Command::Summary(x) => x.json,
+            Command::Push(PushTarget::Influxdb { json, .. }) => *json,
+        };
+    }
+}
+
+#[derive(Args)]
+struct ConvertArgs {
+    /// Output format.
+    #[arg(short, long, value_enum)]
     filetype: FileType,
-    /// Output file (optional, except for parquet).
+    /// Output file, or directory for parquet-dataset and data-package. Defaults to stdout.
     #[arg(short, long)]
     out: Option<std::path::PathBuf>,
-    /// Paths of input files.
-    paths: Vec<std::path::PathBuf>,
+    /// Columns of the load profile matrix.
+    #[arg(long, value_enum, default_value = "weekday")]
+    profile_columns: ProfileColumns,
+    /// Statistic used to summarize each load profile cell.
+    #[arg(long, value_enum, default_value = "mean")]
+    profile_statistic: ProfileStatistic,
+    /// Wide CSV column name template. {title}, {uom}, {kind} and the other reading type
+    /// columns are replaced.
+    #[arg(long, default_value_t = WideCsvOptions::default().column_name)]
+    wide_column_name: String,
+    /// How wide CSV cells without a reading are filled.
+    #[arg(long, value_enum, default_value = "empty")]
+    wide_fill: Fill,
+    /// Parquet compression codec.
+    #[arg(long, value_enum, default_value = "snappy")]
+    parquet_compression: Compression,
+    /// Plain encode parquet string columns, rather than dictionary encoding them.
+    #[arg(long)]
+    parquet_no_dictionary: bool,
+    /// Maximum rows per parquet row group.
+    #[arg(long, default_value_t = ParquetOptions::default().row_group_size)]
+    parquet_row_group_size: usize,
+    /// First part of OpenMetrics metric names.
+    #[arg(long, default_value_t = OpenMetricsOptions::default().prefix)]
+    openmetrics_prefix: String,
+    /// Home Assistant statistic id prefix.
+    #[arg(long, default_value_t = HomeAssistantOptions::default().source)]
+    ha_source: String,
+    /// Offset from UTC in seconds of the readings' local time, for Home Assistant JSON. Only used
+    /// for input without DST rules, e.g. Green Button CSV. XML uses its LocalTimeParameters.
+    #[arg(long, default_value_t = 0, allow_hyphen_values = true)]
+    ha_utc_offset: i64,
+    /// Home Assistant unit for electricity.
+    #[arg(long, value_enum, default_value = "kwh")]
+    ha_electricity_unit: EnergyUnit,
+    /// Convert gas volumes to kWh for Home Assistant, at this many kWh per m³.
+    #[arg(long)]
+    ha_gas_kwh_per_m3: Option<f64>,
+    /// Billing period boundaries as local dates, e.g. 2024-01-15,2024-02-14,2024-03-15. N + 1
+    /// dates describe N periods. Defaults to calendar months.
+    #[arg(long, value_delimiter = ',')]
+    billing_dates: Vec<NaiveDate>,
+    /// SQL dialect, for quoting and column types.
+    #[arg(long, value_enum, default_value = "postgres")]
+    sql_dialect: Dialect,
+    /// SQL table name.
+    #[arg(long, default_value_t = SqlScriptOptions::default().table)]
+    sql_table: String,
+    /// Make the SQL table a TimescaleDB hypertable. PostgreSQL only.
+    #[arg(long)]
+    sql_timescale: bool,
+    /// Load SQL rows with COPY FROM STDIN rather than INSERT. PostgreSQL only.
+    #[arg(long)]
+    sql_copy: bool,
+    /// Rows per SQL INSERT statement.
+    #[arg(long, default_value_t = SqlScriptOptions::default().batch_size)]
+    sql_batch_size: usize,
+    /// Green Button CSV layout.
+    #[arg(long, value_enum, default_value = "typed")]
+    gb_csv_dialect: CsvDialect,
+    /// Name in the Green Button CSV preamble.
+    #[arg(long, default_value = "")]
+    gb_csv_name: String,
+    /// Address in the Green Button CSV preamble.
+    #[arg(long, default_value = "")]
+    gb_csv_address: String,
+    /// Account number in the Green Button CSV preamble.
+    #[arg(long, default_value = "")]
+    gb_csv_account_number: String,
+    /// Print the number of readings and series written as JSON. Needs --out.
+    #[arg(long, requires = "out")]
+    json: bool,
+    #[command(flatten)]
+    input: InputArgs,
+    #[command(flatten)]
+    influx: InfluxArgs,
 }
 
-fn run() -> Result<()> {
-    let cli = Cli::parse();
+fn parse_key_value(s: &str) -> Result<(String, String)> {
+    let (key, value) = s
+        .split_once('=')
+        .ok_or(anyhow!("Expected KEY=VALUE, got {:?}", s))?;
+    return Ok((key.to_string(), value.to_string()));
+}
+
+fn influxdb_options(cli: &InfluxArgs) -> InfluxdbOptions {
+    let mut options = InfluxdbOptions::default();
+    if let Some(measurement) = &cli.influx_measurement {
+        options.measurement = InfluxdbMeasurement::Fixed(measurement.clone());
+    } else if cli.influx_raw_title {
+        options.measurement = InfluxdbMeasurement::Title;
+    }
+    if let Some(tags) = &cli.influx_tags {
+        options.tags = tags.clone();
+    }
+    options.extra_tags = cli.influx_tag.clone();
+    options.precision = cli.influx_precision.into();
+    options.integer_fields = cli.influx_integer_fields;
+    return options;
+}
 
+// Our own parquet exports, otherwise XML or CSV.
+fn parse_contents(contents: &[u8]) -> Result<TimeSeries> {
+    if contents.starts_with(b"PAR1") {
+        return TimeSeries::from_parquet(contents);
+    }
+    return parse_green_button(std::str::from_utf8(contents)?);
+}
+
+// With merge, readings which a later file also has are dropped. Returns how many were.
+fn read_files(paths: &[std::path::PathBuf], merge: bool) -> Result<(TimeSeries, usize)> {
     let mut timeseries = TimeSeries::default();
-    for path in &cli.paths {
-        let xml = fs::read_to_string(path).expect("Should have been able to read the file");
-        let result = parse_xml(&xml);
-        match result {
-            Ok(x) => timeseries.extend(x),
-            Err(x) => eprintln!("Failed to read file {} {}", path.to_str().unwrap(), x),
+    let mut readings = 0;
+    for path in paths {
+        let mut x = fs::read(path)
+            .map_err(|x| anyhow!(x))
+            .and_then(|x| parse_contents(&x))
+            .with_context(|| format!("Failed to read file {}", path.display()))?;
+        x.add_source_file(&path.to_string_lossy());
+        readings += x.value.len();
+        if merge {
+            timeseries.overlay(x);
+        } else {
+            timeseries.extend(x);
         }
     }
+    let replaced = readings - timeseries.value.len();
+    return Ok((timeseries, replaced));
+}
+
+fn load(input: &InputArgs, merge: bool) -> Result<(TimeSeries, usize)> {
+    let (mut timeseries, replaced) = read_files(&input.paths, merge)?;
+
+    if let Some(path) = &input.weather {
+        let csv = fs::read_to_string(path)?;
+        let weather = parse_weather_csv(
+            &csv,
+            &WeatherCsvOptions {
+                utc_offset_seconds: input.weather_utc_offset,
+                local_time: timeseries.provenance.local_time,
+            },
+        )?;
+        timeseries
+            .join_weather(
+                &weather,
+                &DegreeDayOptions {
+                    heating_base_c: input.degree_day_base,
+                    cooling_base_c: input.degree_day_base,
+                },
+            )
+            .map_err(|x| anyhow!(x))?;
+    }
+
+    if input.flag_anomalies {
+        timeseries
+            .add_anomaly_column(&AnomalyOptions::default())
+            .map_err(|x| anyhow!(x))?;
+    }
+    return Ok((timeseries, replaced));
+}
+
+fn push(target: &PushTarget) -> Result<()> {
+    match target {
+        PushTarget::Influxdb {
+            url,
+            org,
+            bucket,
+            token,
+            batch_size,
+            max_retries,
+            json,
+            input,
+            influx,
+        } => {
+            let (timeseries, _) = load(input, false)?;
+            let lines = push_influxdb(
+                &timeseries,
+                &influxdb_options(influx),
+                &InfluxdbPushOptions {
+                    url: url.clone(),
+                    org: org.clone(),
+                    bucket: bucket.clone(),
+                    token: token.clone(),
+                    batch_size: *batch_size,
+                    max_retries: *max_retries,
+                    initial_backoff: std::time::Duration::from_secs(1),
+                },
+            )?;
+            if *json {
+                report::print(&json!({ "bucket": bucket, "lines": lines }), true);
+            } else {
+                eprintln!("Wrote {} lines to {}.", lines, bucket);
+            }
+        }
+    }
+    return Ok(());
+}
 
-    let mut str_out: Option<String> = None;
-    match cli.filetype {
-        FileType::CSV => str_out = Some(timeseries.as_csv().map_err(|x| anyhow!(x))?),
-        FileType::Influxdb => str_out = Some(timeseries.as_influxdb()),
-        FileType::Parquet => {
-            let buf = timeseries.as_parquet().map_err(|x| anyhow!(x))?;
-            match &cli.out {
-                Some(path) => std::fs::write(path, &buf).unwrap(),
-                None => panic!("outfile required with parquet."),
+fn parquet_options(args: &ConvertArgs) -> ParquetOptions {
+    return ParquetOptions {
+        compression: args.parquet_compression.into(),
+        dictionary: !args.parquet_no_dictionary,
+        row_group_size: args.parquet_row_group_size,
+    };
+}
+
+fn home_assistant_options(args: &ConvertArgs) -> HomeAssistantOptions {
+    return HomeAssistantOptions {
+        source: args.ha_source.clone(),
+        utc_offset_seconds: args.ha_utc_offset,
+        electricity_unit: args.ha_electricity_unit.into(),
+        gas_kwh_per_m3: args.ha_gas_kwh_per_m3,
+    };
+}
+
+fn period(args: &ConvertArgs) -> Period {
+    if args.billing_dates.is_empty() {
+        return Period::Month;
+    }
+    let mut boundaries: Vec<i64> = args
+        .billing_dates
+        .iter()
+        .map(|x| x.and_time(NaiveTime::MIN).and_utc().timestamp())
+        .collect();
+    boundaries.sort();
+    return Period::Billing(boundaries);
+}
+
+fn sql_script_options(args: &ConvertArgs) -> SqlScriptOptions {
+    return SqlScriptOptions {
+        dialect: args.sql_dialect.into(),
+        table: args.sql_table.clone(),
+        timescale: args.sql_timescale,
+        load: if args.sql_copy {
+            SqlLoadStatement::Copy
+        } else {
+            SqlLoadStatement::Insert
+        },
+        batch_size: args.sql_batch_size,
+    };
+}
+
+fn write(timeseries: &TimeSeries, args: &ConvertArgs) -> Result<()> {
+    let out = || output(args.out.as_deref());
+    match args.filetype {
+        FileType::CSV => timeseries.write_csv(out()?).map_err(|x| anyhow!(x))?,
+        FileType::Influxdb => timeseries
+            .write_influxdb(out()?, &influxdb_options(&args.influx))
+            .map_err(|x| anyhow!(x))?,
+        FileType::Parquet => timeseries
+            .write_parquet(out()?, &parquet_options(args))
+            .map_err(|x| anyhow!(x))?,
+        FileType::Openmetrics => {
+            let options = OpenMetricsOptions {
+                prefix: args.openmetrics_prefix.clone(),
+            };
+            timeseries
+                .write_openmetrics(out()?, &options)
+                .map_err(|x| anyhow!(x))?
+        }
+        FileType::HomeAssistantJson => write_all(
+            out()?,
+            timeseries
+                .as_home_assistant_json(&home_assistant_options(args))
+                .map_err(|x| anyhow!(x))?,
+        )?,
+        FileType::HomeAssistantCsv => write_all(
+            out()?,
+            timeseries
+                .as_home_assistant_csv(&home_assistant_options(args))
+                .map_err(|x| anyhow!(x))?,
+        )?,
+        FileType::PortfolioManager => write_all(
+            out()?,
+            timeseries
+                .as_portfolio_manager_csv(&period(args))
+                .map_err(|x| anyhow!(x))?,
+        )?,
+        FileType::Sql => timeseries
+            .write_sql_script(out()?, &sql_script_options(args))
+            .map_err(|x| anyhow!(x))?,
+        FileType::GreenButtonCsv => {
+            let options = GreenButtonCsvOptions {
+                dialect: args.gb_csv_dialect.into(),
+                name: args.gb_csv_name.clone(),
+                address: args.gb_csv_address.clone(),
+                account_number: args.gb_csv_account_number.clone(),
             };
+            let csv = timeseries
+                .as_green_button_csv_with_options(&options)
+                .map_err(|x| anyhow!(x))?;
+            write_all(out()?, csv)?
         }
+        FileType::WideCsv => {
+            let options = WideCsvOptions {
+                column_name: args.wide_column_name.clone(),
+                fill: args.wide_fill.into(),
+            };
+            let csv = timeseries
+                .as_wide_csv_with_options(&options)
+                .map_err(|x| anyhow!(x))?;
+            write_all(out()?, csv)?
+        }
+        FileType::Espi => write_all(out()?, timeseries.as_espi_xml().map_err(|x| anyhow!(x))?)?,
+        FileType::Json => write_all(out()?, timeseries.as_json().map_err(|x| anyhow!(x))?)?,
+        FileType::Ndjson => write_all(out()?, timeseries.as_ndjson().map_err(|x| anyhow!(x))?)?,
+        FileType::LoadProfile => {
+            let profile =
+                timeseries.load_profile(args.profile_columns.into(), args.profile_statistic.into());
+            write_all(out()?, profile.as_csv().map_err(|x| anyhow!(x))?)?
+        }
+        FileType::Xlsx => write_all(out()?, timeseries.as_xlsx().map_err(|x| anyhow!(x))?)?,
+        FileType::Arrow => write_all(out()?, timeseries.as_arrow_ipc().map_err(|x| anyhow!(x))?)?,
+        FileType::ArrowStream => write_all(
+            out()?,
+            timeseries.as_arrow_ipc_stream().map_err(|x| anyhow!(x))?,
+        )?,
+        FileType::ParquetDataset => match &args.out {
+            Some(path) => {
+                timeseries.write_parquet_dataset(path, &parquet_options(args))?;
+            }
+            None => return Err(anyhow!("--out is required for parquet-dataset.")),
+        },
+        FileType::DataPackage => match &args.out {
+            Some(path) => timeseries.write_data_package(path)?,
+            None => return Err(anyhow!("--out is required for data-package.")),
+        },
+        FileType::Sqlite => match &args.out {
+            Some(path) => timeseries.write_sqlite(path)?,
+            None => return Err(anyhow!("--out is required for sqlite.")),
+        },
     }
-    if let Some(str_out) = str_out {
-        match &cli.out {
-            Some(path) => std::fs::write(path, &str_out).unwrap(),
-            None => println!("{}", str_out),
+    return Ok(());
+}
+
+fn convert(args: &ConvertArgs, merge: bool) -> Result<()> {
+    let (timeseries, replaced) = load(&args.input, merge)?;
+    write(&timeseries, args)?;
+    if args.json {
+        let filetype = args.filetype.to_possible_value().unwrap();
+        let mut result = json!({
+            "filetype": filetype.get_name(),
+            "out": args.out.as_ref().map(|x| x.to_string_lossy()),
+            "readings": timeseries.value.len(),
+            "series": report::series_count(&timeseries),
+        });
+        if merge {
+            result["duplicates"] = json!(replaced);
         }
+        report::print(&result, true);
     }
     return Ok(());
 }
 
-fn main() {
-    match run() {
-        Ok(_) => {}
-        Err(x) => panic!("{:?}", x),
+fn run(cli: &Cli) -> Result<ExitCode> {
+    match &cli.command {
+        Command::Convert(args) => convert(args, false)?,
+        Command::Merge(args) => convert(args, true)?,
+        Command::Push(target) => push(target)?,
+        Command::Inspect(args) => {
+            let (result, ok) = report::inspect(&args.paths)?;
+            report::print(&result, args.json);
+            if !ok {
+                return Ok(ExitCode::from(EXIT_ERROR));
+            }
+        }
+        Command::Validate(args) => {
+            let (result, valid) = report::validate(&args.paths)?;
+            report::print(&result, args.json);
+            if !valid {
+                return Ok(ExitCode::from(EXIT_INVALID));
+            }
+        }
+        Command::Summary(args) => {
+            let (timeseries, _) = read_files(&args.paths, false)?;
+            report::print(&report::summary(&timeseries), args.json);
+        }
     }
+    return Ok(ExitCode::SUCCESS);
+}
+
+// --out if given, otherwise stdout.
+fn output(path: Option<&Path>) -> Result<Box<dyn Write + Send>> {
+    return Ok(match path {
+        Some(path) => Box::new(BufWriter::new(fs::File::create(path)?)),
+        None => Box::new(BufWriter::new(io::stdout())),
+    });
+}
+
+fn write_all(mut out: Box<dyn Write + Send>, contents: impl AsRef<[u8]>) -> Result<()> {
+    out.write_all(contents.as_ref())?;
+    out.flush()?;
+    return Ok(());
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    return match run(&cli) {
+        Ok(x) => x,
+        Err(x) => {
+            if cli.json() {
+                report::print(&json!({ "error": format!("{:#}", x) }), true);
+            } else {
+                eprintln!("Error: {:#}", x);
+            }
+            ExitCode::from(EXIT_ERROR)
+        }
+    };
 }
@@ -0,0 +1,263 @@
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use flate2::{write::GzEncoder, Compression};
+use personalgreenbutton::{InfluxdbOptions, TimeSeries};
+
+// Writes line protocol to the InfluxDB v2 write API, which InfluxDB 3 also serves. Lines are
+// streamed into gzipped batches, each sent once full, and a batch is retried with exponential
+// backoff on 429 and 5xx responses (or connection errors), honoring Retry-After when the server
+// sends it.
+
+pub struct InfluxdbPushOptions {
+    // e.g. http://localhost:8086. /api/v2/write is appended.
+    pub url: String,
+    pub org: String,
+    pub bucket: String,
+    pub token: Option<String>,
+    // Lines per request. InfluxDB recommends 5000.
+    pub batch_size: usize,
+    pub max_retries: u32,
+    // Doubled after every retry.
+    pub initial_backoff: Duration,
+}
+
+fn gzip(x: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(x)?;
+    return Ok(encoder.finish()?);
+}
+
+fn retry_after(response: &ureq::Response) -> Option<Duration> {
+    let seconds: u64 = response.header("Retry-After")?.trim().parse().ok()?;
+    return Some(Duration::from_secs(seconds));
+}
+
+fn post_batch(request: &ureq::Request, body: &[u8], options: &InfluxdbPushOptions) -> Result<()> {
+    let mut backoff = options.initial_backoff;
+    let mut retries = 0;
+    loop {
+        let error = match request.clone().send_bytes(body) {
+            Ok(_) => return Ok(()),
+            Err(x) => x,
+        };
+        let retryable = match &error {
+            ureq::Error::Status(status, _) => *status == 429 || *status >= 500,
+            ureq::Error::Transport(_) => true,
+        };
+        if !retryable || retries >= options.max_retries {
+            return Err(match error {
+                ureq::Error::Status(status, response) => anyhow!(
+                    "InfluxDB write failed with {}: {}",
+                    status,
+                    response.into_string().unwrap_or_default()
+                ),
+                ureq::Error::Transport(x) => anyhow!("InfluxDB write failed: {}", x),
+            });
+        }
+        let wait = match &error {
+            ureq::Error::Status(_, response) => retry_after(response).unwrap_or(backoff),
+            ureq::Error::Transport(_) => backoff,
+        };
+        eprintln!("InfluxDB write failed ({}), retrying in {:?}.", error, wait);
+        thread::sleep(wait);
+        retries += 1;
+        backoff *= 2;
+    }
+}
+
+// Collects written lines, posting a batch every batch_size lines.
+struct BatchWriter<'a> {
+    request: &'a ureq::Request,
+    options: &'a InfluxdbPushOptions,
+    batch: Vec<u8>,
+    batch_lines: usize,
+    lines: usize,
+}
+
+impl BatchWriter<'_> {
+    fn send(&mut self) -> Result<()> {
+        if self.batch.is_empty() {
+            return Ok(());
+        }
+        post_batch(self.request, &gzip(&self.batch)?, self.options)?;
+        self.batch.clear();
+        self.batch_lines = 0;
+        return Ok(());
+    }
+}
+
+impl Write for BatchWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        for part in buf.split_inclusive(|x| *x == b'\n') {
+            self.batch.extend_from_slice(part);
+            if part.ends_with(b"\n") {
+                self.lines += 1;
+                self.batch_lines += 1;
+                if self.batch_lines >= self.options.batch_size.max(1) {
+                    self.send()
+                        .map_err(|x| std::io::Error::other(x.to_string()))?;
+                }
+            }
+        }
+        return Ok(buf.len());
+    }
+
+    // Sends any partial batch.
+    fn flush(&mut self) -> std::io::Result<()> {
+        return self
+            .send()
+            .map_err(|x| std::io::Error::other(x.to_string()));
+    }
+}
+
+/// Returns the number of lines written.
+pub fn push_influxdb(
+    timeseries: &TimeSeries,
+    influxdb_options: &InfluxdbOptions,
+    options: &InfluxdbPushOptions,
+) -> Result<usize> {
+    let mut request = ureq::post(&format!(
+        "{}/api/v2/write",
+        options.url.trim_end_matches('/')
+    ))
+    .query("org", &options.org)
+    .query("bucket", &options.bucket)
+    .query("precision", influxdb_options.precision.as_str())
+    .set("Content-Type", "text/plain; charset=utf-8")
+    .set("Content-Encoding", "gzip");
+    if let Some(token) = &options.token {
+        request = request.set("Authorization", &format!("Token {}", token));
+    }
+
+    let mut writer = BatchWriter {
+        request: &request,
+        options,
+        batch: vec![],
+        batch_lines: 0,
+        lines: 0,
+    };
+    timeseries
+        .write_influxdb(&mut writer, influxdb_options)
+        .map_err(|x| anyhow!(x))?;
+    writer.flush()?;
+    return Ok(writer.lines);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use flate2::read::GzDecoder;
+    use personalgreenbutton::{InfluxdbOptions, TimeSeries};
+
+    use super::{push_influxdb, InfluxdbPushOptions};
+
+    // Answers each request with the next status, then reports the request line, headers and
+    // decompressed body.
+    fn stub_server(statuses: Vec<u16>) -> (String, mpsc::Receiver<(String, String)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            for status in statuses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(x) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = x.trim().parse().unwrap();
+                    }
+                    head += &line;
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let mut decompressed = String::new();
+                GzDecoder::new(&body[..])
+                    .read_to_string(&mut decompressed)
+                    .unwrap();
+                write!(
+                    reader.get_mut(),
+                    "HTTP/1.1 {} Stub\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                    status
+                )
+                .unwrap();
+                sender.send((head, decompressed)).unwrap();
+            }
+        });
+        return (url, receiver);
+    }
+
+    fn options(url: String) -> InfluxdbPushOptions {
+        return InfluxdbPushOptions {
+            url,
+            org: "home".to_string(),
+            bucket: "energy".to_string(),
+            token: Some("secret".to_string()),
+            batch_size: 2,
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(1),
+        };
+    }
+
+    fn timeseries() -> TimeSeries {
+        let mut ts = TimeSeries::default();
+        for i in 0..3 {
+            ts.title.push("a".to_string());
+            ts.cost.push(f32::NAN);
+            ts.quality.push("");
+            ts.value.push(i as f32);
+            ts.tou.push(0);
+            ts.time_period_start_unix.push(i * 3600);
+            ts.time_period_duration_seconds.push(3600);
+            ts.accumulation_behaviour.push("deltaData");
+            ts.commodity.push("electricity SecondaryMetered");
+            ts.currency.push("CAD");
+            ts.data_qualifier.push("normal");
+            ts.flow_direction.push("forward");
+            ts.kind.push("energy");
+            ts.phase.push("none");
+            ts.uom.push("Wh");
+        }
+        return ts;
+    }
+
+    #[test]
+    fn batches_and_retries() {
+        let (url, requests) = stub_server(vec![503, 204, 429, 204]);
+        let written =
+            push_influxdb(&timeseries(), &InfluxdbOptions::default(), &options(url)).unwrap();
+        assert_eq!(written, 3);
+
+        let requests: Vec<(String, String)> = requests.iter().collect();
+        assert_eq!(requests.len(), 4);
+        let (head, body) = &requests[0];
+        assert!(head.starts_with("POST /api/v2/write?org=home&bucket=energy&precision=ns "));
+        assert!(head.contains("Authorization: Token secret\r\n"));
+        assert!(head.contains("Content-Encoding: gzip\r\n"));
+        assert_eq!(body.lines().count(), 2);
+        // Retried with the same batch.
+        assert_eq!(requests[1].1, *body);
+        assert_eq!(requests[3].1.lines().count(), 1);
+    }
+
+    #[test]
+    fn gives_up_on_client_errors() {
+        let (url, requests) = stub_server(vec![400]);
+        let result = push_influxdb(&timeseries(), &InfluxdbOptions::default(), &options(url));
+        assert!(result.unwrap_err().to_string().contains("400"));
+        assert_eq!(requests.iter().count(), 1);
+    }
+}
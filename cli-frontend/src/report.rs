@@ -0,0 +1,607 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use chrono::DateTime;
+use personalgreenbutton::{
+    f32_to_f64, is_cumulative, round6, AnomalyOptions, TimeSeries, READING_TYPE_FIELDS,
+};
+use serde_json::{json, Map, Value};
+
+use crate::parse_contents;
+
+// The inspect, validate and summary subcommands. Each builds a JSON value, which --json prints
+// as is, and which is otherwise printed as indented "key: value" text.
+
+// Written for enum values which aren't in the ESPI schema.
+const UNKNOWN_ENUM: &str = "Missing app info";
+
+fn reading_type_json(reading_type: &[&str; 8]) -> Value {
+    let map: Map<String, Value> = READING_TYPE_FIELDS
+        .iter()
+        .zip(reading_type)
+        .map(|(field, value)| (field.to_string(), json!(value)))
+        .collect();
+    return Value::Object(map);
+}
+
+// Rows of each (title, reading type), in time order.
+fn series(timeseries: &TimeSeries) -> BTreeMap<(&str, [&'static str; 8]), Vec<usize>> {
+    let mut series = BTreeMap::<(&str, [&str; 8]), Vec<usize>>::new();
+    for i in 0..timeseries.value.len() {
+        series
+            .entry((&timeseries.title[i], timeseries.reading_type_values(i)))
+            .or_default()
+            .push(i);
+    }
+    for rows in series.values_mut() {
+        rows.sort_by_key(|i| timeseries.time_period_start_unix[*i]);
+    }
+    return series;
+}
+
+pub fn series_count(timeseries: &TimeSeries) -> usize {
+    return series(timeseries).len();
+}
+
+// Timestamps are local time, stored as if UTC.
+fn local_time(unix: i64) -> Value {
+    return match DateTime::from_timestamp(unix, 0) {
+        Some(x) => json!(x.naive_utc().format("%Y-%m-%dT%H:%M:%S").to_string()),
+        None => Value::Null,
+    };
+}
+
+fn format_name(contents: &[u8]) -> &'static str {
+    if contents.starts_with(b"PAR1") {
+        return "parquet";
+    }
+    let text = String::from_utf8_lossy(&contents[..contents.len().min(64)]);
+    let trimmed = text.trim_start_matches('\u{feff}').trim_start();
+    if trimmed.starts_with('<') {
+        return "Green Button XML";
+    }
+    if trimmed.starts_with("title,cost,quality,value,") {
+        return "csv";
+    }
+    return "Green Button CSV";
+}
+
+// Entry counts by content type, and the number of interval readings.
+fn feed_structure(xml: &str) -> Result<Value> {
+    let doc = roxmltree::Document::parse(xml)?;
+    let mut entries = Map::new();
+    let mut total = 0;
+    for entry in doc
+        .root_element()
+        .children()
+        .filter(|x| x.tag_name().name() == "entry")
+    {
+        total += 1;
+        let content_type = entry
+            .children()
+            .find(|x| x.tag_name().name() == "content")
+            .and_then(|x| x.children().find(|x| x.is_element()))
+            .map(|x| x.tag_name().name())
+            .unwrap_or("none");
+        let count = entries.entry(content_type).or_insert(json!(0));
+        *count = json!(count.as_u64().unwrap() + 1);
+    }
+    entries.sort_keys();
+    let interval_readings = doc
+        .descendants()
+        .filter(|x| x.tag_name().name() == "IntervalReading")
+        .count();
+    return Ok(json!({
+        "entries": total,
+        "entries_by_type": entries,
+        "interval_readings": interval_readings,
+    }));
+}
+
+fn read(path: &Path) -> Result<(Vec<u8>, Result<TimeSeries>)> {
+    let contents =
+        fs::read(path).with_context(|| format!("Failed to read file {}", path.display()))?;
+    let timeseries = parse_contents(&contents);
+    return Ok((contents, timeseries));
+}
+
+/// Feed structure and counts of each file. The bool is false if any file failed to parse.
+pub fn inspect(paths: &[PathBuf]) -> Result<(Value, bool)> {
+    let mut ok = true;
+    let mut files = vec![];
+    for path in paths {
+        let (contents, timeseries) = read(path)?;
+        let mut file = Map::new();
+        file.insert("path".to_string(), json!(path.to_string_lossy()));
+        file.insert("format".to_string(), json!(format_name(&contents)));
+        if format_name(&contents) == "Green Button XML" {
+            match feed_structure(&String::from_utf8_lossy(&contents)) {
+                Ok(Value::Object(x)) => file.extend(x),
+                Ok(_) => unreachable!(),
+                Err(x) => {
+                    file.insert("error".to_string(), json!(x.to_string()));
+                }
+            }
+        }
+        match timeseries {
+            Ok(timeseries) => {
+                let series: Vec<Value> = series(&timeseries)
+                    .into_iter()
+                    .map(|((title, reading_type), rows)| {
+                        json!({
+                            "title": title,
+                            "reading_type": reading_type_json(&reading_type),
+                            "readings": rows.len(),
+                        })
+                    })
+                    .collect();
+                let extra_columns: Vec<&str> = timeseries
+                    .extra_columns
+                    .iter()
+                    .map(|x| x.name.as_str())
+                    .collect();
+                file.insert("readings".to_string(), json!(timeseries.value.len()));
+                file.insert("series".to_string(), json!(series));
+                file.insert("extra_columns".to_string(), json!(extra_columns));
+                file.insert(
+                    "provider_hrefs".to_string(),
+                    json!(timeseries.provenance.provider_hrefs),
+                );
+                file.insert("quirks".to_string(), json!(timeseries.provenance.quirks));
+            }
+            Err(x) => {
+                ok = false;
+                file.insert("error".to_string(), json!(format!("{:#}", x)));
+            }
+        }
+        files.push(Value::Object(file));
+    }
+    return Ok((json!({ "files": files }), ok));
+}
+
+fn problem(check: &str, count: usize, message: String) -> Value {
+    return json!({ "check": check, "count": count, "message": message });
+}
+
+#[derive(Default)]
+struct IntervalProblems {
+    duplicates: usize,
+    overlaps: usize,
+    // Second readings at a start, which may be the hour repeated when DST ends, but there are no
+    // DST rules (e.g. in CSV input) to tell.
+    possible_dst_repeats: usize,
+}
+
+// Within a series and time of use tier, intervals shouldn't repeat or overlap. Local times in
+// the hour repeated when DST ends hold two readings each.
+fn interval_problems(timeseries: &TimeSeries) -> IntervalProblems {
+    let local_time = timeseries.provenance.local_time;
+    let mut problems = IntervalProblems::default();
+    for rows in series(timeseries).values() {
+        let mut by_tou = BTreeMap::<i32, Vec<usize>>::new();
+        for i in rows {
+            by_tou.entry(timeseries.tou[*i]).or_default().push(*i);
+        }
+        for rows in by_tou.values() {
+            let mut previous: Option<(i64, i64)> = None;
+            // Earlier readings with the same start.
+            let mut repeats = 0;
+            for i in rows {
+                let start = timeseries.time_period_start_unix[*i];
+                let end = start + timeseries.time_period_duration_seconds[*i].max(0) as i64;
+                match previous {
+                    Some((previous_start, _)) if previous_start == start => {
+                        repeats += 1;
+                        match local_time.map(|x| x.is_repeated(start)) {
+                            Some(true) if repeats == 1 => {}
+                            None if repeats == 1 => problems.possible_dst_repeats += 1,
+                            _ => problems.duplicates += 1,
+                        }
+                    }
+                    Some((_, previous_end)) if start < previous_end => {
+                        repeats = 0;
+                        problems.overlaps += 1;
+                    }
+                    _ => repeats = 0,
+                }
+                let end = previous.map_or(end, |(_, previous_end)| end.max(previous_end));
+                previous = Some((start, end));
+            }
+        }
+    }
+    return problems;
+}
+
+// Spec violations in a parsed file.
+fn violations(timeseries: &TimeSeries) -> Vec<Value> {
+    let mut errors = vec![];
+    let enum_columns: [(&str, &[&str]); 9] = [
+        ("quality", &timeseries.quality),
+        ("accumulation_behaviour", &timeseries.accumulation_behaviour),
+        ("commodity", &timeseries.commodity),
+        ("currency", &timeseries.currency),
+        ("data_qualifier", &timeseries.data_qualifier),
+        ("flow_direction", &timeseries.flow_direction),
+        ("kind", &timeseries.kind),
+        ("phase", &timeseries.phase),
+        ("uom", &timeseries.uom),
+    ];
+    for (column, values) in enum_columns {
+        let count = values.iter().filter(|x| **x == UNKNOWN_ENUM).count();
+        if count > 0 {
+            errors.push(problem(
+                "unknown_enum",
+                count,
+                format!(
+                    "{} readings have a {} not in the ESPI schema.",
+                    count, column
+                ),
+            ));
+        }
+    }
+
+    let count = timeseries
+        .time_period_duration_seconds
+        .iter()
+        .zip(&timeseries.accumulation_behaviour)
+        .filter(|(duration, accumulation)| **duration <= 0 && !is_cumulative(accumulation))
+        .count();
+    if count > 0 {
+        errors.push(problem(
+            "non_positive_duration",
+            count,
+            format!("{} interval readings have no duration.", count),
+        ));
+    }
+
+    let IntervalProblems {
+        duplicates,
+        overlaps,
+        ..
+    } = interval_problems(timeseries);
+    if duplicates > 0 {
+        errors.push(problem(
+            "duplicate_interval",
+            duplicates,
+            format!("{} readings repeat an earlier interval start.", duplicates),
+        ));
+    }
+    if overlaps > 0 {
+        errors.push(problem(
+            "overlapping_interval",
+            overlaps,
+            format!(
+                "{} readings start before the previous interval ends.",
+                overlaps
+            ),
+        ));
+    }
+    return errors;
+}
+
+// Suspicious but valid readings, see detect_anomalies.
+fn warnings(timeseries: &TimeSeries) -> Vec<Value> {
+    let mut warnings = vec![];
+    let count = timeseries.value.iter().filter(|x| !x.is_finite()).count();
+    if count > 0 {
+        warnings.push(problem(
+            "missing_value",
+            count,
+            format!("{} readings have no value.", count),
+        ));
+    }
+    let count = interval_problems(timeseries).possible_dst_repeats;
+    if count > 0 {
+        warnings.push(problem(
+            "repeated_interval",
+            count,
+            format!(
+                "{} readings repeat an earlier interval start. That's expected in the hour \
+repeated when DST ends, but the input has no DST rules to check against.",
+                count
+            ),
+        ));
+    }
+    let mut anomalies = BTreeMap::new();
+    for anomaly in timeseries.detect_anomalies(&AnomalyOptions::default()) {
+        *anomalies.entry(anomaly.reason).or_insert(0) += 1;
+    }
+    for (reason, count) in anomalies {
+        warnings.push(problem(
+            reason.as_str(),
+            count,
+            format!(
+                "{} readings look like a {}.",
+                count,
+                reason.as_str().replace('_', " ")
+            ),
+        ));
+    }
+    return warnings;
+}
+
+/// Spec violations (errors) and anomalies (warnings) of each file. The bool is false if there
+/// are any errors.
+pub fn validate(paths: &[PathBuf]) -> Result<(Value, bool)> {
+    let mut valid = true;
+    let mut files = vec![];
+    for path in paths {
+        let (_, timeseries) = read(path)?;
+        let (errors, warnings) = match timeseries {
+            Ok(timeseries) => (violations(&timeseries), warnings(&timeseries)),
+            Err(x) => (vec![problem("parse", 1, format!("{:#}", x))], vec![]),
+        };
+        valid &= errors.is_empty();
+        files.push(json!({
+            "path": path.to_string_lossy(),
+            "valid": errors.is_empty(),
+            "errors": errors,
+            "warnings": warnings,
+        }));
+    }
+    return Ok((json!({ "valid": valid, "files": files }), valid));
+}
+
+/// Totals and date range of each series, across all the given readings. Interval readings are
+/// summed, and cumulative registers give the change from their first to last reading.
+pub fn summary(timeseries: &TimeSeries) -> Value {
+    let mut result = vec![];
+    for ((title, reading_type), rows) in series(timeseries) {
+        let values: Vec<f64> = rows
+            .iter()
+            .map(|i| timeseries.value[*i])
+            .filter(|x| x.is_finite())
+            .map(f32_to_f64)
+            .collect();
+        let total = if is_cumulative(reading_type[0]) {
+            match (values.first(), values.last()) {
+                (Some(first), Some(last)) => Some(last - first),
+                _ => None,
+            }
+        } else if values.is_empty() {
+            None
+        } else {
+            Some(values.iter().sum())
+        };
+        let costs: Vec<f64> = rows
+            .iter()
+            .map(|i| timeseries.cost[*i])
+            .filter(|x| x.is_finite())
+            .map(f32_to_f64)
+            .collect();
+        let cost = if costs.is_empty() {
+            None
+        } else {
+            Some(round6(costs.iter().sum()))
+        };
+        let start = timeseries.time_period_start_unix[rows[0]];
+        let end = rows
+            .iter()
+            .map(|i| {
+                timeseries.time_period_start_unix[*i]
+                    + timeseries.time_period_duration_seconds[*i] as i64
+            })
+            .max()
+            .unwrap();
+        result.push(json!({
+            "title": title,
+            "reading_type": reading_type_json(&reading_type),
+            "readings": rows.len(),
+            "start": local_time(start),
+            "end": local_time(end),
+            "total": total.map(round6),
+            "cost": cost,
+        }));
+    }
+    return json!({ "series": result });
+}
+
+fn is_inline(value: &Value) -> bool {
+    return match value {
+        Value::Object(x) => x.is_empty(),
+        Value::Array(x) => x.iter().all(|x| !x.is_object() && !x.is_array()),
+        _ => true,
+    };
+}
+
+fn inline_text(value: &Value) -> String {
+    return match value {
+        Value::Null => "-".to_string(),
+        Value::String(x) => x.clone(),
+        Value::Array(x) if x.is_empty() => "none".to_string(),
+        Value::Array(x) => x.iter().map(inline_text).collect::<Vec<_>>().join(", "),
+        Value::Object(_) => "none".to_string(),
+        x => x.to_string(),
+    };
+}
+
+fn write_text(out: &mut String, value: &Value, indent: usize) {
+    let pad = "  ".repeat(indent);
+    match value {
+        Value::Object(map) => {
+            for (key, x) in map {
+                if is_inline(x) {
+                    out.push_str(&format!("{}{}: {}\n", pad, key, inline_text(x)));
+                } else {
+                    out.push_str(&format!("{}{}:\n", pad, key));
+                    write_text(out, x, indent + 1);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for x in items {
+                // "- " in place of the first line's indent, as in YAML.
+                let mut item = String::new();
+                write_text(&mut item, x, indent + 1);
+                match item.get(pad.len() + 2..) {
+                    Some(rest) if !rest.is_empty() => out.push_str(&format!("{}- {}", pad, rest)),
+                    _ => out.push_str(&format!("{}- {}\n", pad, inline_text(x))),
+                }
+            }
+        }
+        x => out.push_str(&format!("{}{}\n", pad, inline_text(x))),
+    }
+}
+
+/// Prints value as JSON, or as indented text for reading rather than parsing.
+pub fn print(value: &Value, json: bool) {
+    if json {
+        println!("{}", serde_json::to_string_pretty(value).unwrap());
+        return;
+    }
+    let mut out = String::new();
+    write_text(&mut out, value, 0);
+    print!("{}", out);
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeDelta;
+    use personalgreenbutton::{LocalTimeParametersSingle, TimeSeries};
+    use serde_json::json;
+
+    use super::{summary, violations, warnings, write_text};
+
+    // 2024-11-02 00:00 local time.
+    const NOVEMBER_2: i64 = 1730505600;
+
+    fn hourly(starts: &[i64], values: &[f32]) -> TimeSeries {
+        let len = starts.len();
+        return TimeSeries {
+            title: vec!["house".to_string(); len],
+            cost: vec![f32::NAN; len],
+            quality: vec!["valid"; len],
+            value: values.to_vec(),
+            tou: vec![0; len],
+            time_period_start_unix: starts.to_vec(),
+            time_period_duration_seconds: vec![3600; len],
+            accumulation_behaviour: vec!["deltaData"; len],
+            commodity: vec!["electricity SecondaryMetered"; len],
+            currency: vec!["USD"; len],
+            data_qualifier: vec!["normal"; len],
+            flow_direction: vec!["forward"; len],
+            kind: vec!["energy"; len],
+            phase: vec!["none"; len],
+            uom: vec!["Wh"; len],
+            extra_columns: vec![],
+            provenance: Default::default(),
+        };
+    }
+
+    // US Eastern: UTC-5, with an hour of DST from March to November.
+    fn eastern() -> LocalTimeParametersSingle {
+        return LocalTimeParametersSingle {
+            dst_start_rule: 0x360E2000,
+            dst_end_rule: 0xB40E2000,
+            dst_offset: TimeDelta::seconds(3600),
+            tz_offset: TimeDelta::seconds(-5 * 3600),
+        };
+    }
+
+    fn checks(problems: &[serde_json::Value]) -> Vec<(&str, u64)> {
+        return problems
+            .iter()
+            .map(|x| (x["check"].as_str().unwrap(), x["count"].as_u64().unwrap()))
+            .collect();
+    }
+
+    #[test]
+    fn dst_fall_back() {
+        let repeated = (NOVEMBER_2..)
+            .step_by(3600)
+            .find(|x| eastern().is_repeated(*x))
+            .unwrap();
+        let starts = [repeated - 3600, repeated, repeated, repeated + 3600];
+        let mut ts = hourly(&starts, &[1.0, 2.0, 3.0, 4.0]);
+        ts.provenance.local_time = Some(eastern());
+        assert_eq!(checks(&violations(&ts)), vec![]);
+
+        // Without DST rules, the repeat can't be checked.
+        ts.provenance.local_time = None;
+        assert_eq!(checks(&violations(&ts)), vec![]);
+        assert_eq!(checks(&warnings(&ts)), vec![("repeated_interval", 1)]);
+
+        // A third reading is a duplicate either way.
+        ts.provenance.local_time = Some(eastern());
+        ts.extend(hourly(&[repeated], &[5.0]));
+        assert_eq!(checks(&violations(&ts)), vec![("duplicate_interval", 1)]);
+    }
+
+    #[test]
+    fn spec_violations() {
+        let mut ts = hourly(
+            &[NOVEMBER_2, NOVEMBER_2, NOVEMBER_2 + 1800],
+            &[1.0, 2.0, 3.0],
+        );
+        ts.quality[0] = "Missing app info";
+        ts.time_period_duration_seconds[2] = 0;
+        ts.provenance.local_time = Some(eastern());
+        assert_eq!(
+            checks(&violations(&ts)),
+            vec![
+                ("unknown_enum", 1),
+                ("non_positive_duration", 1),
+                ("duplicate_interval", 1),
+                ("overlapping_interval", 1),
+            ]
+        );
+    }
+
+    #[test]
+    fn summary_totals() {
+        let mut ts = hourly(&[NOVEMBER_2, NOVEMBER_2 + 3600], &[0.1, 0.2]);
+        ts.cost = vec![0.05, f32::NAN];
+        let mut register = hourly(
+            &[NOVEMBER_2, NOVEMBER_2 + 3600, NOVEMBER_2 + 7200],
+            &[100.0, 103.5, 107.0],
+        );
+        register.accumulation_behaviour = vec!["cumulative"; 3];
+        ts.extend(register);
+
+        let series = &summary(&ts)["series"];
+        assert_eq!(
+            series[0]["reading_type"]["accumulation_behaviour"],
+            "cumulative"
+        );
+        assert_eq!(series[0]["total"], json!(7.0));
+        assert_eq!(series[0]["cost"], json!(null));
+        assert_eq!(series[1]["total"], json!(0.3));
+        assert_eq!(series[1]["cost"], json!(0.05));
+        assert_eq!(series[1]["readings"], json!(2));
+        assert_eq!(series[1]["start"], json!("2024-11-02T00:00:00"));
+        assert_eq!(series[1]["end"], json!("2024-11-02T02:00:00"));
+    }
+
+    #[test]
+    fn text() {
+        let value = json!({
+            "valid": false,
+            "files": [
+                {
+                    "path": "a.xml",
+                    "errors": [{ "check": "parse", "count": 1 }],
+                    "warnings": [],
+                },
+            ],
+            "quirks": ["a", "b"],
+            "total": null,
+        });
+        let mut out = String::new();
+        write_text(&mut out, &value, 0);
+        assert_eq!(
+            out,
+            "valid: false
+files:
+  - path: a.xml
+    errors:
+      - check: parse
+        count: 1
+    warnings: none
+quirks: a, b
+total: -
+"
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+const HEADER: &str = "title,cost,quality,value,tou,time_period_start_unix,\
+time_period_duration_seconds,accumulation_behaviour,commodity,currency,data_qualifier,\
+flow_direction,kind,phase,uom";
+
+// 2024-11-03 01:00 local time, the hour repeated when US DST ends.
+const REPEATED_HOUR: i64 = 1730595600;
+
+// An as_csv file of hourly readings, (start, value).
+fn write_csv(dir: &Path, name: &str, readings: &[(i64, f32)]) -> PathBuf {
+    let mut csv = format!("{}\n", HEADER);
+    for (start, value) in readings {
+        csv += &format!(
+            "house,NaN,valid,{},0,{},3600,deltaData,electricity SecondaryMetered,USD,normal,\
+forward,energy,none,Wh\n",
+            value, start
+        );
+    }
+    let path = dir.join(name);
+    fs::write(&path, csv).unwrap();
+    return path;
+}
+
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("greenbutton_cli_{}_{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    return dir;
+}
+
+fn run(args: &[&str]) -> Output {
+    return Command::new(env!("CARGO_BIN_EXE_greenbutton-cli"))
+        .args(args)
+        .output()
+        .unwrap();
+}
+
+fn stdout_json(output: &Output) -> serde_json::Value {
+    return serde_json::from_slice(&output.stdout).unwrap();
+}
+
+#[test]
+fn validate_exit_codes() {
+    let dir = temp_dir("validate");
+    let valid = write_csv(&dir, "valid.csv", &[(0, 1.0), (3600, 2.0)]);
+    let overlapping = write_csv(&dir, "overlapping.csv", &[(0, 1.0), (1800, 2.0)]);
+    let missing = dir.join("missing.csv");
+
+    let output = run(&["validate", valid.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(0));
+
+    let output = run(&["validate", "--json", overlapping.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let json = stdout_json(&output);
+    assert_eq!(json["valid"], false);
+    assert_eq!(
+        json["files"][0]["errors"][0]["check"],
+        "overlapping_interval"
+    );
+
+    let output = run(&["validate", "--json", missing.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(2));
+    let error = stdout_json(&output)["error"].as_str().unwrap().to_string();
+    assert!(error.starts_with("Failed to read file"), "{}", error);
+
+    let output = run(&["validate", "--no-such-flag"]);
+    assert_eq!(output.status.code(), Some(2));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn merge_keeps_dst_fall_back() {
+    let dir = temp_dir("merge");
+    let old = write_csv(
+        &dir,
+        "old.csv",
+        &[(REPEATED_HOUR - 3600, 1.0), (REPEATED_HOUR, 2.0)],
+    );
+    // Both readings of the repeated hour.
+    let new = write_csv(
+        &dir,
+        "new.csv",
+        &[
+            (REPEATED_HOUR, 3.0),
+            (REPEATED_HOUR, 4.0),
+            (REPEATED_HOUR + 3600, 5.0),
+        ],
+    );
+    let out = dir.join("merged.csv");
+
+    let output = run(&[
+        "merge",
+        "--filetype=csv",
+        "--json",
+        &format!("--out={}", out.display()),
+        old.to_str().unwrap(),
+        new.to_str().unwrap(),
+    ]);
+    assert_eq!(output.status.code(), Some(0));
+    let json = stdout_json(&output);
+    assert_eq!(json["readings"], 4);
+    assert_eq!(json["duplicates"], 1);
+
+    let merged = fs::read_to_string(&out).unwrap();
+    let values: Vec<&str> = merged
+        .lines()
+        .skip(1)
+        .map(|x| x.split(',').nth(3).unwrap())
+        .collect();
+    assert_eq!(values, ["1", "3", "4", "5"]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
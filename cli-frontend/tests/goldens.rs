@@ -12,6 +12,7 @@ fn run_on_test_files(filetype: &str) {
     let mut args: Vec<String> = vec![
         "run".into(),
         "--".into(),
+        "convert".into(),
         format!("--filetype={}", filetype).into(),
         format!("--out={}", TMP_OUTPUT_PATH).into(),
     ];
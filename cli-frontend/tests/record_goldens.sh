@@ -1,5 +1,5 @@
 #!/bin/bash
 
-cargo run -- --filetype=csv ../../test_files/* --out=goldens/golden.csv
-cargo run -- --filetype=influxdb ../../test_files/* --out=goldens/golden.influxdb
-cargo run -- --filetype=parquet ../../test_files/* --out=goldens/golden.parquet
\ No newline at end of file
+cargo run -- convert --filetype=csv ../../test_files/* --out=goldens/golden.csv
+cargo run -- convert --filetype=influxdb ../../test_files/* --out=goldens/golden.influxdb
+cargo run -- convert --filetype=parquet ../../test_files/* --out=goldens/golden.parquet
\ No newline at end of file
@@ -0,0 +1,279 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Datelike, Timelike};
+
+use crate::{
+    extra_columns::ExtraColumnValues, reading_type::is_cumulative, stats::median, TimeSeries,
+};
+
+// Flags readings which are likely estimated or broken, so they can be filtered out of dashboards.
+// Readings already marked as estimated by the provider are skipped.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnomalyOptions {
+    // Robust z-scores above this, relative to the same hour and weekday, are spikes.
+    pub spike_threshold: f32,
+    // Minimum number of readings in an hour/weekday bucket before spikes are flagged.
+    pub min_bucket_size: usize,
+    // Minimum length of a run of identical (or zero) values to flag.
+    pub min_run_length: usize,
+    pub skip_estimated: bool,
+}
+
+impl Default for AnomalyOptions {
+    fn default() -> Self {
+        return AnomalyOptions {
+            spike_threshold: 3.5,
+            min_bucket_size: 4,
+            min_run_length: 6,
+            skip_estimated: true,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AnomalyReason {
+    // Ordered by priority, when a reading matches several.
+    NegativeDelta,
+    ZeroRun,
+    Flatline,
+    Spike,
+}
+
+impl AnomalyReason {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            AnomalyReason::NegativeDelta => "negative_delta",
+            AnomalyReason::ZeroRun => "zero_run",
+            AnomalyReason::Flatline => "flatline",
+            AnomalyReason::Spike => "spike",
+        };
+    }
+}
+
+impl std::fmt::Display for AnomalyReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.as_str());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Anomaly {
+    pub row_index: usize,
+    pub reason: AnomalyReason,
+}
+
+impl TimeSeries {
+    /// Scans each series (title and reading type) in time order. The series doesn't need to be
+    /// sorted, row indices refer to the current row order.
+    pub fn detect_anomalies(&self, options: &AnomalyOptions) -> Vec<Anomaly> {
+        let mut order: Vec<usize> = (0..self.value.len())
+            .filter(|i| !(options.skip_estimated && self.quality[*i].contains("estimated")))
+            .collect();
+        order.sort_by(|i, j| {
+            self.title[*i]
+                .cmp(&self.title[*j])
+                .then(
+                    self.reading_type_values(*i)
+                        .cmp(&self.reading_type_values(*j)),
+                )
+                .then(self.time_period_start_unix[*i].cmp(&self.time_period_start_unix[*j]))
+        });
+
+        let mut reasons: Vec<Option<AnomalyReason>> = vec![None; self.value.len()];
+        let mut flag = |i: usize, reason: AnomalyReason| {
+            reasons[i] = Some(reasons[i].map_or(reason, |existing| existing.min(reason)));
+        };
+
+        for series in order.chunk_by(|i, j| {
+            self.title[*i] == self.title[*j]
+                && self.reading_type_values(*i) == self.reading_type_values(*j)
+        }) {
+            for (i, reason) in self.negative_deltas(series) {
+                flag(i, reason);
+            }
+            for (i, reason) in self.runs(series, options) {
+                flag(i, reason);
+            }
+            for (i, reason) in self.spikes(series, options) {
+                flag(i, reason);
+            }
+        }
+
+        return reasons
+            .into_iter()
+            .enumerate()
+            .filter_map(|(row_index, reason)| reason.map(|reason| Anomaly { row_index, reason }))
+            .collect();
+    }
+
+    /// Adds an "anomaly" column holding the reason, or "" for normal readings.
+    pub fn add_anomaly_column(&mut self, options: &AnomalyOptions) -> Result<(), String> {
+        let mut column = vec![String::new(); self.value.len()];
+        for anomaly in self.detect_anomalies(options) {
+            column[anomaly.row_index] = anomaly.reason.to_string();
+        }
+        return self.set_extra_column("anomaly", ExtraColumnValues::Str(column));
+    }
+
+    // Cumulative registers should never go down, and interval deltas should never be negative
+    // unless they're net of generation.
+    fn negative_deltas(&self, series: &[usize]) -> Vec<(usize, AnomalyReason)> {
+        let mut result = vec![];
+        for (position, i) in series.iter().enumerate() {
+            let negative = if is_cumulative(self.accumulation_behaviour[*i]) {
+                position > 0 && self.value[*i] < self.value[series[position - 1]]
+            } else {
+                self.flow_direction[*i] != "net" && self.value[*i] < 0.0
+            };
+            if negative {
+                result.push((*i, AnomalyReason::NegativeDelta));
+            }
+        }
+        return result;
+    }
+
+    fn runs(&self, series: &[usize], options: &AnomalyOptions) -> Vec<(usize, AnomalyReason)> {
+        let mut result = vec![];
+        for run in series.chunk_by(|i, j| self.value[*i] == self.value[*j]) {
+            if run.len() < options.min_run_length.max(2) {
+                continue;
+            }
+            let reason = if self.value[run[0]] == 0.0 {
+                AnomalyReason::ZeroRun
+            } else {
+                AnomalyReason::Flatline
+            };
+            result.extend(run.iter().map(|i| (*i, reason)));
+        }
+        return result;
+    }
+
+    // Robust z-score using the median absolute deviation of readings at the same local hour
+    // and weekday.
+    fn spikes(&self, series: &[usize], options: &AnomalyOptions) -> Vec<(usize, AnomalyReason)> {
+        let mut buckets = HashMap::<(u32, u32), Vec<usize>>::new();
+        for i in series {
+            let datetime = DateTime::from_timestamp(self.time_period_start_unix[*i], 0)
+                .unwrap()
+                .naive_utc();
+            buckets
+                .entry((datetime.weekday().num_days_from_monday(), datetime.hour()))
+                .or_default()
+                .push(*i);
+        }
+
+        let mut result = vec![];
+        for bucket in buckets.values() {
+            if bucket.len() < options.min_bucket_size {
+                continue;
+            }
+            let bucket_median =
+                median(&mut bucket.iter().map(|i| self.value[*i]).collect::<Vec<_>>());
+            let mad = median(
+                &mut bucket
+                    .iter()
+                    .map(|i| (self.value[*i] - bucket_median).abs())
+                    .collect::<Vec<_>>(),
+            );
+            if mad == 0.0 {
+                continue;
+            }
+            for i in bucket {
+                let z = 0.6745 * (self.value[*i] - bucket_median) / mad;
+                if z.abs() > options.spike_threshold {
+                    result.push((*i, AnomalyReason::Spike));
+                }
+            }
+        }
+        return result;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnomalyOptions, AnomalyReason};
+    use crate::{extra_columns::ExtraColumnValues, test_util::evenly_spaced_timeseries};
+
+    // Monday 2024-01-01 00:00:00.
+    const START: i64 = 1704067200;
+    const WEEK: i32 = 7 * 24 * 3600;
+
+    #[test]
+    fn spike() {
+        // Weekly readings, so they all share an hour and weekday.
+        let ts = evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            START,
+            WEEK,
+            &[10.0, 11.0, 9.0, 10.0, 100.0, 10.5],
+        );
+        let anomalies = ts.detect_anomalies(&AnomalyOptions::default());
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].row_index, 4);
+        assert_eq!(anomalies[0].reason, AnomalyReason::Spike);
+    }
+
+    #[test]
+    fn runs_and_negative_deltas() {
+        let mut ts = evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            START,
+            3600,
+            &[1.0, 0.0, 0.0, 0.0, 2.0, 2.0, 2.0, -1.0],
+        );
+        ts.quality[4] = "estimated using reference day";
+        let options = AnomalyOptions {
+            min_run_length: 3,
+            ..AnomalyOptions::default()
+        };
+        ts.add_anomaly_column(&options).unwrap();
+        assert_eq!(
+            ts.extra_column("anomaly").unwrap(),
+            &ExtraColumnValues::Str(
+                [
+                    "",
+                    "zero_run",
+                    "zero_run",
+                    "zero_run",
+                    "",
+                    "",
+                    "",
+                    "negative_delta"
+                ]
+                .map(String::from)
+                .to_vec()
+            )
+        );
+    }
+
+    #[test]
+    fn reading_types_kept_apart() {
+        // A new register and its interval deltas under one title, interleaved in time. Compared
+        // with the deltas, the register would look like it goes down.
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &[1.0, 2.0, 3.0]);
+        ts.accumulation_behaviour = vec!["cumulative"; 3];
+        ts.extend(evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            START,
+            3600,
+            &[5.0, 5.0, 5.0],
+        ));
+        assert_eq!(ts.detect_anomalies(&AnomalyOptions::default()), vec![]);
+    }
+
+    #[test]
+    fn cumulative_decrease() {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &[1.0, 2.0, 1.5]);
+        ts.accumulation_behaviour = vec!["cumulative"; 3];
+        let anomalies = ts.detect_anomalies(&AnomalyOptions::default());
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!(anomalies[0].row_index, 2);
+    }
+}
@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use arrow_array::{
+    ArrayRef, Float32Array, Int32Array, RecordBatch, StringArray, TimestampMillisecondArray,
+};
+use arrow_ipc::writer::{FileWriter, StreamWriter};
+use arrow_schema::{DataType, Field, Schema, TimeUnit};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{extra_columns::ExtraColumnValues, TimeSeries};
+
+// Arrow IPC (Feather v2) export. The schema matches as_parquet, so files can be used
+// interchangeably from pandas, polars or DuckDB.
+
+fn str_array(values: &[&str]) -> ArrayRef {
+    return Arc::new(StringArray::from(values.to_vec()));
+}
+
+impl TimeSeries {
+    fn as_record_batch(&self) -> Result<RecordBatch, String> {
+        let mut fields = vec![
+            Field::new("title", DataType::Utf8, false),
+            Field::new("cost", DataType::Float32, false),
+            Field::new("quality", DataType::Utf8, false),
+            Field::new("value", DataType::Float32, false),
+            Field::new("tou", DataType::Int32, false),
+            Field::new(
+                "time_period_start_unix",
+                DataType::Timestamp(TimeUnit::Millisecond, None),
+                false,
+            ),
+            Field::new("time_period_duration_seconds", DataType::Int32, false),
+            Field::new("accumulation_behaviour", DataType::Utf8, false),
+            Field::new("commodity", DataType::Utf8, false),
+            Field::new("currency", DataType::Utf8, false),
+            Field::new("data_qualifier", DataType::Utf8, false),
+            Field::new("flow_direction", DataType::Utf8, false),
+            Field::new("kind", DataType::Utf8, false),
+            Field::new("phase", DataType::Utf8, false),
+            Field::new("uom", DataType::Utf8, false),
+        ];
+        // Order must match the schema.
+        let mut columns: Vec<ArrayRef> = vec![
+            Arc::new(StringArray::from_iter_values(self.title.iter())),
+            Arc::new(Float32Array::from(self.cost.clone())),
+            str_array(&self.quality),
+            Arc::new(Float32Array::from(self.value.clone())),
+            Arc::new(Int32Array::from(self.tou.clone())),
+            Arc::new(TimestampMillisecondArray::from_iter_values(
+                self.time_period_start_unix.iter().map(|x| x * 1000),
+            )),
+            Arc::new(Int32Array::from(self.time_period_duration_seconds.clone())),
+            str_array(&self.accumulation_behaviour),
+            str_array(&self.commodity),
+            str_array(&self.currency),
+            str_array(&self.data_qualifier),
+            str_array(&self.flow_direction),
+            str_array(&self.kind),
+            str_array(&self.phase),
+            str_array(&self.uom),
+        ];
+        for column in &self.extra_columns {
+            match &column.values {
+                ExtraColumnValues::F32(x) => {
+                    fields.push(Field::new(&column.name, DataType::Float32, false));
+                    columns.push(Arc::new(Float32Array::from(x.clone())));
+                }
+                ExtraColumnValues::Str(x) => {
+                    fields.push(Field::new(&column.name, DataType::Utf8, false));
+                    columns.push(Arc::new(StringArray::from(x.clone())));
+                }
+            }
+        }
+        return RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+            .map_err(|x| x.to_string());
+    }
+}
+
+#[wasm_bindgen]
+impl TimeSeries {
+    /// Arrow IPC file format, which supports random access and memory mapping.
+    #[wasm_bindgen(js_name = "asArrowIpc")]
+    pub fn as_arrow_ipc(&self) -> Result<Vec<u8>, String> {
+        let batch = self.as_record_batch()?;
+        let mut writer = FileWriter::try_new(vec![], &batch.schema()).map_err(|x| x.to_string())?;
+        writer.write(&batch).map_err(|x| x.to_string())?;
+        writer.finish().map_err(|x| x.to_string())?;
+        return writer.into_inner().map_err(|x| x.to_string());
+    }
+
+    /// Arrow IPC streaming format, for consumers which read sequentially.
+    #[wasm_bindgen(js_name = "asArrowIpcStream")]
+    pub fn as_arrow_ipc_stream(&self) -> Result<Vec<u8>, String> {
+        let batch = self.as_record_batch()?;
+        let mut writer =
+            StreamWriter::try_new(vec![], &batch.schema()).map_err(|x| x.to_string())?;
+        writer.write(&batch).map_err(|x| x.to_string())?;
+        writer.finish().map_err(|x| x.to_string())?;
+        return writer.into_inner().map_err(|x| x.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use arrow_array::{Float32Array, StringArray};
+    use arrow_ipc::reader::{FileReader, StreamReader};
+
+    use crate::{extra_columns::ExtraColumnValues, test_util::evenly_spaced_timeseries};
+
+    #[test]
+    fn file_and_stream_round_trip() {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0, 2.0]);
+        ts.set_extra_column("flag", ExtraColumnValues::Str(vec!["".into(), "x".into()]))
+            .unwrap();
+
+        let file = ts.as_arrow_ipc().unwrap();
+        let batches: Vec<_> = FileReader::try_new(Cursor::new(file), None)
+            .unwrap()
+            .map(|x| x.unwrap())
+            .collect();
+        let stream = ts.as_arrow_ipc_stream().unwrap();
+        let stream_batches: Vec<_> = StreamReader::try_new(Cursor::new(stream), None)
+            .unwrap()
+            .map(|x| x.unwrap())
+            .collect();
+        assert_eq!(batches, stream_batches);
+
+        let batch = &batches[0];
+        assert_eq!(batch.num_rows(), 2);
+        assert_eq!(batch.num_columns(), 16);
+        let value = batch
+            .column_by_name("value")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Float32Array>()
+            .unwrap();
+        assert_eq!(value.values(), &[1.0, 2.0]);
+        let flag = batch
+            .column_by_name("flag")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<StringArray>()
+            .unwrap();
+        assert_eq!(flag.value(1), "x");
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, Timelike};
+
+use crate::{
+    periods::Period,
+    stats::{linear_regression_slope, mean, percentile},
+    TimeSeries,
+};
+
+// Baseload is the always-on part of a home's load (fridges, routers, standby power).
+// Readings are first resampled to hourly average kW, so 15 minute and hourly data
+// give comparable estimates. Readings longer than an hour are ignored.
+
+const SECONDS_PER_HOUR: i64 = 3600;
+const SECONDS_PER_YEAR: f64 = 365.25 * 24.0 * 3600.0;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseloadOptions {
+    // Local hours [night_start_hour, night_end_hour) used for the nightly minimum. The window
+    // wraps midnight when night_start_hour > night_end_hour, e.g. 22 to 5.
+    pub night_start_hour: u32,
+    pub night_end_hour: u32,
+    // Percentile of hourly demand used as the percentile based baseload.
+    pub percentile: f32,
+}
+
+impl Default for BaseloadOptions {
+    fn default() -> Self {
+        return BaseloadOptions {
+            night_start_hour: 0,
+            night_end_hour: 5,
+            percentile: 10.0,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseloadEstimate {
+    pub title: String,
+    // Start of the day or month.
+    pub period_start_unix: i64,
+    // NaN if no hours fell in the night window.
+    pub nightly_minimum_kw: f32,
+    pub percentile_kw: f32,
+    pub consumption_kwh: f32,
+    // Share of consumption explained by running percentile_kw for every hour of the period.
+    // NaN if there was no consumption.
+    pub always_on_share: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseloadTrend {
+    pub title: String,
+    // Slope of the monthly percentile baseload.
+    pub kw_per_year: f32,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BaseloadReport {
+    pub daily: Vec<BaseloadEstimate>,
+    pub monthly: Vec<BaseloadEstimate>,
+    pub trend: Vec<BaseloadTrend>,
+}
+
+impl BaseloadOptions {
+    fn is_night(&self, hour: u32) -> bool {
+        if self.night_start_hour <= self.night_end_hour {
+            return self.night_start_hour <= hour && hour < self.night_end_hour;
+        }
+        return hour >= self.night_start_hour || hour < self.night_end_hour;
+    }
+}
+
+// NaN rather than inf when there was no consumption.
+fn share(kwh: f32, consumption_kwh: f32) -> f32 {
+    if consumption_kwh == 0.0 {
+        return f32::NAN;
+    }
+    return kwh / consumption_kwh;
+}
+
+struct HourlyDemand {
+    hour_start_unix: i64,
+    kw: f32,
+    kwh: f32,
+}
+
+impl TimeSeries {
+    /// Hourly average demand for each title. Partially covered hours are averaged over the
+    /// covered part.
+    fn hourly_demand(&self) -> BTreeMap<&str, Vec<HourlyDemand>> {
+        let demand_kw = self.demand_kw();
+        // Title => hour start => (kWh, covered seconds).
+        let mut hours = BTreeMap::<&str, BTreeMap<i64, (f32, i64)>>::new();
+        for (i, demand) in demand_kw.into_iter().enumerate() {
+            let duration_seconds = self.time_period_duration_seconds[i] as i64;
+            if demand.is_nan() || duration_seconds <= 0 || duration_seconds > SECONDS_PER_HOUR {
+                continue;
+            }
+            let start = self.time_period_start_unix[i];
+            let hour = hours
+                .entry(&self.title[i])
+                .or_default()
+                .entry(start - start.rem_euclid(SECONDS_PER_HOUR))
+                .or_default();
+            hour.0 += demand * duration_seconds as f32 / SECONDS_PER_HOUR as f32;
+            hour.1 += duration_seconds;
+        }
+
+        return hours
+            .into_iter()
+            .map(|(title, hours)| {
+                let hourly = hours
+                    .into_iter()
+                    .map(|(hour_start_unix, (kwh, covered_seconds))| HourlyDemand {
+                        hour_start_unix,
+                        kw: kwh * SECONDS_PER_HOUR as f32 / covered_seconds as f32,
+                        kwh,
+                    })
+                    .collect();
+                (title, hourly)
+            })
+            .collect();
+    }
+
+    /// Estimates baseload per day and per month for each title, along with the trend of the
+    /// monthly estimates. Only energy or demand readings in Wh or W are considered.
+    pub fn baseload(&self, options: &BaseloadOptions) -> BaseloadReport {
+        let mut report = BaseloadReport::default();
+        for (title, hourly) in self.hourly_demand() {
+            let daily = daily_baseload(title, &hourly, options);
+            let monthly = monthly_baseload(title, &daily);
+
+            let x: Vec<f64> = monthly
+                .iter()
+                .map(|x| x.period_start_unix as f64 / SECONDS_PER_YEAR)
+                .collect();
+            let y: Vec<f64> = monthly.iter().map(|x| x.percentile_kw as f64).collect();
+            report.trend.push(BaseloadTrend {
+                title: title.to_string(),
+                kw_per_year: linear_regression_slope(&x, &y) as f32,
+            });
+            report.daily.extend(daily);
+            report.monthly.extend(monthly);
+        }
+        return report;
+    }
+}
+
+fn daily_baseload(
+    title: &str,
+    hourly: &[HourlyDemand],
+    options: &BaseloadOptions,
+) -> Vec<BaseloadEstimate> {
+    let mut days = BTreeMap::<i64, Vec<&HourlyDemand>>::new();
+    for hour in hourly {
+        let day_start = hour.hour_start_unix - hour.hour_start_unix.rem_euclid(24 * 3600);
+        days.entry(day_start).or_default().push(hour);
+    }
+
+    return days
+        .into_iter()
+        .map(|(day_start, hours)| {
+            let nightly_minimum_kw = hours
+                .iter()
+                .filter(|x| {
+                    let hour = DateTime::from_timestamp(x.hour_start_unix, 0)
+                        .unwrap()
+                        .hour();
+                    options.is_night(hour)
+                })
+                .map(|x| x.kw)
+                .reduce(f32::min)
+                .unwrap_or(f32::NAN);
+            let percentile_kw = percentile(
+                &mut hours.iter().map(|x| x.kw).collect::<Vec<_>>(),
+                options.percentile,
+            );
+            let consumption_kwh: f32 = hours.iter().map(|x| x.kwh).sum();
+            BaseloadEstimate {
+                title: title.to_string(),
+                period_start_unix: day_start,
+                nightly_minimum_kw,
+                percentile_kw,
+                consumption_kwh,
+                always_on_share: share(percentile_kw * hours.len() as f32, consumption_kwh),
+            }
+        })
+        .collect();
+}
+
+// Monthly estimates average the daily estimates, weighting each day equally.
+fn monthly_baseload(title: &str, daily: &[BaseloadEstimate]) -> Vec<BaseloadEstimate> {
+    let mut months = BTreeMap::<i64, Vec<&BaseloadEstimate>>::new();
+    for day in daily {
+        if let Some(month_start) = Period::Month.start(day.period_start_unix) {
+            months.entry(month_start).or_default().push(day);
+        }
+    }
+
+    return months
+        .into_iter()
+        .map(|(month_start, days)| {
+            let nightly_minimums: Vec<f32> = days
+                .iter()
+                .map(|x| x.nightly_minimum_kw)
+                .filter(|x| !x.is_nan())
+                .collect();
+            let percentile_kw = mean(&days.iter().map(|x| x.percentile_kw).collect::<Vec<_>>());
+            let consumption_kwh: f32 = days.iter().map(|x| x.consumption_kwh).sum();
+            // Days without a share had no consumption, so add nothing.
+            let always_on_kwh: f32 = days
+                .iter()
+                .filter(|x| x.always_on_share.is_finite())
+                .map(|x| x.always_on_share * x.consumption_kwh)
+                .sum();
+            BaseloadEstimate {
+                title: title.to_string(),
+                period_start_unix: month_start,
+                nightly_minimum_kw: mean(&nightly_minimums),
+                percentile_kw,
+                consumption_kwh,
+                always_on_share: share(always_on_kwh, consumption_kwh),
+            }
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BaseloadOptions;
+    use crate::test_util::evenly_spaced_timeseries;
+
+    // 2024-01-01 00:00:00.
+    const START: i64 = 1704067200;
+
+    // A day of load which sits at 200W overnight and 1kW during the day.
+    fn day_values(samples_per_hour: usize) -> Vec<f32> {
+        return (0..24 * samples_per_hour)
+            .map(|i| {
+                let hour = i / samples_per_hour;
+                let watts = if hour < 6 { 200.0 } else { 1000.0 };
+                watts / samples_per_hour as f32
+            })
+            .collect();
+    }
+
+    #[test]
+    fn hourly_and_fifteen_minute_agree() {
+        let hourly = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &day_values(1));
+        let quarter_hourly =
+            evenly_spaced_timeseries("a", "energy", "Wh", START, 900, &day_values(4));
+        let hourly = hourly.baseload(&BaseloadOptions::default());
+        let quarter_hourly = quarter_hourly.baseload(&BaseloadOptions::default());
+        assert_eq!(hourly.daily, quarter_hourly.daily);
+
+        let day = &hourly.daily[0];
+        assert_eq!(day.nightly_minimum_kw, 0.2);
+        assert_eq!(day.percentile_kw, 0.2);
+        assert_eq!(day.consumption_kwh, 19.2);
+        assert_eq!(day.always_on_share, 0.25);
+    }
+
+    #[test]
+    fn monthly_trend() {
+        // Baseload doubles from January to February.
+        let values = day_values(1);
+        let february = evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            START + 31 * 24 * 3600,
+            3600,
+            &values.iter().map(|x| x * 2.0).collect::<Vec<_>>(),
+        );
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &values);
+        ts.extend(february);
+
+        let report = ts.baseload(&BaseloadOptions::default());
+        assert_eq!(report.monthly.len(), 2);
+        assert_eq!(report.monthly[1].percentile_kw, 0.4);
+        assert!(report.trend[0].kw_per_year > 0.0);
+    }
+
+    #[test]
+    fn night_window_wraps_midnight() {
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &day_values(1));
+        let options = BaseloadOptions {
+            night_start_hour: 22,
+            night_end_hour: 5,
+            ..Default::default()
+        };
+        assert_eq!(ts.baseload(&options).daily[0].nightly_minimum_kw, 0.2);
+    }
+
+    #[test]
+    fn zero_consumption_day() {
+        let mut values = day_values(1);
+        values.extend([0.0; 24]);
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &values);
+        let report = ts.baseload(&BaseloadOptions::default());
+        assert!(report.daily[1].always_on_share.is_nan());
+        assert_eq!(report.monthly[0].always_on_share, 0.25);
+    }
+}
@@ -0,0 +1,220 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::BufWriter;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+
+use crate::{
+    extra_columns::ExtraColumnValues, find_gb_type_value, readback::ENUM_COLUMNS, TimeSeries,
+};
+
+// Frictionless Tabular Data Package (https://specs.frictionlessdata.io/): the as_csv output as
+// readings.csv, described by datapackage.json. Each field has its type, a description, and a
+// unit where there's one for the whole column. Enum columns list the values present, with the
+// ESPI schema's documentation of each value as categories (Data Package v2). Provenance goes in
+// "sources", plus a "personalgreenbutton" property with the version and provider quirks.
+
+const CSV_FILE_NAME: &str = "readings.csv";
+
+// (column, type, description) for the as_csv columns, in order.
+const FIELDS: [(&str, &str, &str); 15] = [
+    (
+        "title",
+        "string",
+        "Title of the usage point, as given by the provider.",
+    ),
+    ("cost", "number", "Cost of the reading, in currency."),
+    ("quality", "string", "Quality of the reading."),
+    ("value", "number", "Reading value, in uom."),
+    ("tou", "integer", "Time of use tier, 0 if not applicable."),
+    (
+        "time_period_start_unix",
+        "integer",
+        "Start of the interval, as seconds since 1970-01-01 in local time.",
+    ),
+    (
+        "time_period_duration_seconds",
+        "integer",
+        "Length of the interval.",
+    ),
+    (
+        "accumulation_behaviour",
+        "string",
+        "How value accumulates over time.",
+    ),
+    ("commodity", "string", "What is measured."),
+    ("currency", "string", "Currency of cost."),
+    (
+        "data_qualifier",
+        "string",
+        "Statistic the value represents.",
+    ),
+    ("flow_direction", "string", "Direction of flow."),
+    ("kind", "string", "Kind of measurement."),
+    ("phase", "string", "Electrical phases measured."),
+    ("uom", "string", "Unit of value."),
+];
+
+impl TimeSeries {
+    fn enum_column(&self, column: &str) -> &[&'static str] {
+        return match column {
+            "quality" => &self.quality,
+            "accumulation_behaviour" => &self.accumulation_behaviour,
+            "commodity" => &self.commodity,
+            "currency" => &self.currency,
+            "data_qualifier" => &self.data_qualifier,
+            "flow_direction" => &self.flow_direction,
+            "kind" => &self.kind,
+            "phase" => &self.phase,
+            "uom" => &self.uom,
+            _ => unreachable!(),
+        };
+    }
+
+    // The single value of an enum column, if there is one.
+    fn only_value(&self, column: &str) -> Option<&'static str> {
+        let values: BTreeSet<&str> = self.enum_column(column).iter().copied().collect();
+        return match values.len() {
+            1 => values.into_iter().next(),
+            _ => None,
+        };
+    }
+
+    fn data_package_field(&self, name: &str, field_type: &str, description: &str) -> Value {
+        let mut field = Map::new();
+        field.insert("name".to_string(), json!(name));
+        field.insert("type".to_string(), json!(field_type));
+        field.insert("description".to_string(), json!(description));
+
+        let unit = match name {
+            "value" => self.only_value("uom"),
+            "cost" => self.only_value("currency"),
+            "time_period_start_unix" | "time_period_duration_seconds" => Some("s"),
+            _ => None,
+        };
+        if let Some(unit) = unit {
+            field.insert("unit".to_string(), json!(unit));
+        }
+
+        if let Some((_, scope, espi_field)) = ENUM_COLUMNS.iter().find(|x| x.0 == name) {
+            let values: BTreeSet<&str> = self.enum_column(name).iter().copied().collect();
+            let categories: Vec<Value> = values
+                .iter()
+                .map(|value| match find_gb_type_value(scope, espi_field, value) {
+                    Some((code, details)) => json!({
+                        "value": value,
+                        "label": details.description,
+                        "code": code,
+                    }),
+                    None => json!({ "value": value }),
+                })
+                .collect();
+            field.insert("constraints".to_string(), json!({ "enum": values }));
+            field.insert("categories".to_string(), json!(categories));
+        }
+        return Value::Object(field);
+    }
+
+    /// datapackage.json for the as_csv output, at csv_path relative to the package.
+    pub fn data_package_json(&self, csv_path: &str) -> Result<String, String> {
+        let mut fields: Vec<Value> = FIELDS
+            .iter()
+            .map(|(name, field_type, description)| {
+                self.data_package_field(name, field_type, description)
+            })
+            .collect();
+        for column in &self.extra_columns {
+            let field_type = match column.values {
+                ExtraColumnValues::F32(_) => "number",
+                ExtraColumnValues::Str(_) => "string",
+            };
+            fields.push(json!({ "name": column.name, "type": field_type }));
+        }
+
+        let mut sources: Vec<Value> = self
+            .provenance
+            .source_files
+            .iter()
+            .map(|x| json!({ "title": x }))
+            .collect();
+        sources.extend(
+            self.provenance
+                .provider_hrefs
+                .iter()
+                .map(|x| json!({ "title": "Green Button provider", "path": x })),
+        );
+
+        let package = json!({
+            "profile": "tabular-data-package",
+            "name": "greenbutton",
+            "sources": sources,
+            "resources": [{
+                "profile": "tabular-data-resource",
+                "name": "readings",
+                "path": csv_path,
+                "format": "csv",
+                "mediatype": "text/csv",
+                "encoding": "utf-8",
+                "schema": {
+                    "fields": fields,
+                    "missingValues": ["", "NaN"],
+                },
+            }],
+            "personalgreenbutton": {
+                "version": env!("CARGO_PKG_VERSION"),
+                "quirks": self.provenance.quirks,
+            },
+        });
+        return serde_json::to_string_pretty(&package).map_err(|x| x.to_string());
+    }
+
+    /// Writes readings.csv and datapackage.json into dir.
+    pub fn write_data_package(&self, dir: &Path) -> Result<()> {
+        fs::create_dir_all(dir)?;
+        let csv = BufWriter::new(fs::File::create(dir.join(CSV_FILE_NAME))?);
+        self.write_csv(csv).map_err(|x| anyhow!(x))?;
+        let json = self
+            .data_package_json(CSV_FILE_NAME)
+            .map_err(|x| anyhow!(x))?;
+        fs::write(dir.join("datapackage.json"), json)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{extra_columns::ExtraColumnValues, test_util::evenly_spaced_timeseries};
+
+    #[test]
+    fn describes_columns() {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0, 2.0]);
+        ts.currency[1] = "USD";
+        ts.set_extra_column(
+            "anomaly",
+            ExtraColumnValues::Str(vec!["".into(), "spike".into()]),
+        )
+        .unwrap();
+        ts.add_source_file("usage.xml");
+
+        let json: serde_json::Value =
+            serde_json::from_str(&ts.data_package_json("readings.csv").unwrap()).unwrap();
+        assert_eq!(json["sources"][0]["title"], "usage.xml");
+        let fields = json["resources"][0]["schema"]["fields"].as_array().unwrap();
+        let field = |name: &str| fields.iter().find(|x| x["name"] == name).unwrap();
+
+        assert_eq!(field("value")["unit"], "Wh");
+        // Mixed currencies, so no unit.
+        assert!(field("cost").get("unit").is_none());
+        assert_eq!(
+            field("data_qualifier")["categories"][0],
+            serde_json::json!({ "value": "normal", "label": "Normal", "code": 12 })
+        );
+        assert_eq!(
+            field("currency")["constraints"]["enum"],
+            serde_json::json!(["CAD", "USD"])
+        );
+        assert_eq!(field("anomaly")["type"], "string");
+    }
+}
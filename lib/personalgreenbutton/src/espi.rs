@@ -0,0 +1,506 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{find_gb_type_value, TimeSeries};
+
+// Writes a TimeSeries back out as a Green Button (ESPI) Atom feed.
+//
+// Each title becomes a UsagePoint, and each distinct reading type within a title becomes a
+// MeterReading with its own ReadingType. Readings are split into one IntervalBlock per local day.
+// Starts are written in UTC along with the series' LocalTimeParameters, so parse_xml(as_espi_xml())
+// reproduces the original local timestamps. If the local time is unknown, the local timestamps are
+// written as is with a zero offset and no DST rules. Extra columns aren't written.
+
+const BASE_HREF: &str = "https://localhost/espi/1_1/resource";
+const SUBSCRIPTION_HREF: &str = "https://localhost/espi/1_1/resource/Subscription/1";
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+
+fn escape(x: &str) -> String {
+    return x
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;");
+}
+
+fn rfc3339(unix: i64) -> Result<String, String> {
+    return Ok(DateTime::from_timestamp(unix, 0)
+        .ok_or(format!("Invalid timestamp {}", unix))?
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string());
+}
+
+fn enum_value(xml_type: &str, field: &str, app_info: &str) -> Result<i32, String> {
+    return find_gb_type_value(xml_type, field, app_info)
+        .map(|x| x.0)
+        .ok_or(format!("No ESPI {} value for {:?}", field, app_info));
+}
+
+// ServiceKind of the UsagePoint, derived from the commodity.
+fn service_kind(commodity: &str) -> i32 {
+    return match commodity {
+        x if x.starts_with("electricity") => 0,
+        "naturalGas" | "propane" => 1,
+        "potableWater" | "nonpotableWater" => 2,
+        "heatingFluid" | "coolingFluid" | "steam" => 4,
+        "refuse" => 5,
+        "wasteWater" => 6,
+        "tvLicence" => 8,
+        "internet" => 9,
+        _ => 0,
+    };
+}
+
+// Smallest power of ten for which every value survives the integer encoding, using the same
+// arithmetic as the parser. Falls back to micro units.
+fn power_of_ten_multiplier(values: &[f32]) -> i32 {
+    for power in (-9..=0).rev() {
+        if values
+            .iter()
+            .all(|x| decode_value(encode_value(*x, power), power) == *x)
+        {
+            return power;
+        }
+    }
+    return -6;
+}
+
+fn encode_value(x: f32, power: i32) -> i64 {
+    return (x as f64 / 10f64.powi(power)).round() as i64;
+}
+
+fn decode_value(x: i64, power: i32) -> f32 {
+    return (x as f32) * f32::powf(10.0, power as f32);
+}
+
+struct Entry<'a> {
+    title: &'a str,
+    self_href: String,
+    up_href: String,
+    entry_type: &'a str,
+    // (href, type).
+    related: Vec<(String, &'a str)>,
+    content: String,
+}
+
+impl Entry<'_> {
+    fn write(&self, out: &mut String, id: usize, updated: &str) {
+        *out += "  <entry>\n    <content>\n";
+        *out += &self.content;
+        *out += "    </content>\n";
+        *out += &format!(
+            "    <id>urn:uuid:00000000-0000-0000-0000-{:012X}</id>\n",
+            id
+        );
+        *out += &format!("    <title>{}</title>\n", escape(self.title));
+        *out += &format!("    <published>{updated}</published>\n");
+        *out += &format!("    <updated>{updated}</updated>\n");
+        *out += &format!(
+            "    <link rel=\"self\" href=\"{}\" type=\"espi-entry/{}\"/>\n",
+            self.self_href, self.entry_type
+        );
+        *out += &format!(
+            "    <link rel=\"up\" href=\"{}\" type=\"espi-feed/{}\"/>\n",
+            self.up_href, self.entry_type
+        );
+        for (href, link_type) in &self.related {
+            *out += &format!("    <link rel=\"related\" href=\"{href}\" type=\"{link_type}\"/>\n");
+        }
+        *out += "  </entry>\n";
+    }
+}
+
+impl TimeSeries {
+    /// Row indices grouped by title, then by reading type, in order of first appearance.
+    fn espi_usage_points(&self) -> Vec<Vec<Vec<usize>>> {
+        let mut usage_point_indices = HashMap::<&str, usize>::new();
+        let mut meter_reading_indices = HashMap::<(&str, [&str; 8]), usize>::new();
+        let mut usage_points: Vec<Vec<Vec<usize>>> = vec![];
+        for i in 0..self.value.len() {
+            let usage_point = *usage_point_indices
+                .entry(&self.title[i])
+                .or_insert_with(|| {
+                    usage_points.push(vec![]);
+                    usage_points.len() - 1
+                });
+            let reading_type = self.reading_type_values(i);
+            let meter_reading = *meter_reading_indices
+                .entry((&self.title[i], reading_type))
+                .or_insert_with(|| {
+                    usage_points[usage_point].push(vec![]);
+                    usage_points[usage_point].len() - 1
+                });
+            usage_points[usage_point][meter_reading].push(i);
+        }
+        return usage_points;
+    }
+
+    fn espi_reading_type(&self, rows: &[usize], power: i32) -> Result<String, String> {
+        let i = rows[0];
+        let mut content = "      <espi:ReadingType>\n".to_string();
+        // Order must match the schema.
+        let mut fields = vec![
+            (
+                "accumulationBehaviour",
+                enum_value(
+                    "ReadingType",
+                    "accumulationBehaviour",
+                    self.accumulation_behaviour[i],
+                )?,
+            ),
+            (
+                "commodity",
+                enum_value("ReadingType", "commodity", self.commodity[i])?,
+            ),
+            (
+                "currency",
+                enum_value("ReadingType", "currency", self.currency[i])?,
+            ),
+            (
+                "dataQualifier",
+                enum_value("ReadingType", "dataQualifier", self.data_qualifier[i])?,
+            ),
+            (
+                "flowDirection",
+                enum_value("ReadingType", "flowDirection", self.flow_direction[i])?,
+            ),
+        ];
+        let duration = self.time_period_duration_seconds[i];
+        if rows
+            .iter()
+            .all(|x| self.time_period_duration_seconds[*x] == duration)
+        {
+            fields.push(("intervalLength", duration));
+        }
+        fields.push(("kind", enum_value("ReadingType", "kind", self.kind[i])?));
+        fields.push(("phase", enum_value("ReadingType", "phase", self.phase[i])?));
+        fields.push(("powerOfTenMultiplier", power));
+        fields.push(("uom", enum_value("ReadingType", "uom", self.uom[i])?));
+        for (name, value) in fields {
+            content += &format!("        <espi:{name}>{value}</espi:{name}>\n");
+        }
+        content += "      </espi:ReadingType>\n";
+        return Ok(content);
+    }
+
+    // starts are the UTC starts of all readings.
+    fn espi_interval_block(
+        &self,
+        rows: &[usize],
+        starts: &[i64],
+        power: i32,
+    ) -> Result<String, String> {
+        let start = rows.iter().map(|i| starts[*i]).min().unwrap();
+        let end = rows
+            .iter()
+            .map(|i| starts[*i] + self.time_period_duration_seconds[*i] as i64)
+            .max()
+            .unwrap();
+        let mut content = format!(
+            "      <espi:IntervalBlock>
+        <espi:interval>
+          <espi:duration>{}</espi:duration>
+          <espi:start>{}</espi:start>
+        </espi:interval>\n",
+            end - start,
+            start
+        );
+        for i in rows {
+            let i = *i;
+            content += "        <espi:IntervalReading>\n";
+            // Order must match the schema.
+            if !self.cost[i].is_nan() {
+                // In hundred thousandths of the currency.
+                content += &format!(
+                    "          <espi:cost>{}</espi:cost>\n",
+                    (self.cost[i] as f64 * 100000.0).round() as i64
+                );
+            }
+            content += &format!(
+                "          <espi:ReadingQuality>
+            <espi:quality>{}</espi:quality>
+          </espi:ReadingQuality>
+          <espi:timePeriod>
+            <espi:duration>{}</espi:duration>
+            <espi:start>{}</espi:start>
+          </espi:timePeriod>
+          <espi:value>{}</espi:value>\n",
+                enum_value("", "QualityOfReading", self.quality[i])?,
+                self.time_period_duration_seconds[i],
+                starts[i],
+                encode_value(self.value[i], power)
+            );
+            if self.tou[i] != 0 {
+                content += &format!("          <espi:tou>{}</espi:tou>\n", self.tou[i]);
+            }
+            content += "        </espi:IntervalReading>\n";
+        }
+        content += "      </espi:IntervalBlock>\n";
+        return Ok(content);
+    }
+}
+
+#[wasm_bindgen]
+impl TimeSeries {
+    /// Green Button (ESPI) Atom feed, readable by parse_xml.
+    #[wasm_bindgen(js_name = "asEspiXml")]
+    pub fn as_espi_xml(&self) -> Result<String, String> {
+        if let Some(i) = self.value.iter().position(|x| !x.is_finite()) {
+            return Err(format!("Can't write non-finite value at row {}", i));
+        }
+        let starts = self
+            .utc_starts()
+            .unwrap_or_else(|| self.time_period_start_unix.clone());
+        // Deterministic, so identical data gives identical feeds.
+        let updated = rfc3339(
+            (0..self.value.len())
+                .map(|i| starts[i] + self.time_period_duration_seconds[i] as i64)
+                .max()
+                .unwrap_or(0),
+        )?;
+        // (dstEndRule, dstOffset, dstStartRule, tzOffset), with no offset or DST if unknown.
+        let (dst_end_rule, dst_offset, dst_start_rule, tz_offset) =
+            match &self.provenance.local_time {
+                Some(x) => (
+                    x.dst_end_rule,
+                    x.dst_offset.num_seconds(),
+                    x.dst_start_rule,
+                    x.tz_offset.num_seconds(),
+                ),
+                None => (u32::MAX, 0, u32::MAX, 0),
+            };
+        let local_time_parameters_href = format!("{BASE_HREF}/LocalTimeParameters/1");
+
+        let mut entries = vec![Entry {
+            title: "Local Time Parameters",
+            self_href: local_time_parameters_href.clone(),
+            up_href: format!("{BASE_HREF}/LocalTimeParameters"),
+            entry_type: "LocalTimeParameters",
+            related: vec![],
+            content: format!(
+                "      <espi:LocalTimeParameters>
+        <espi:dstEndRule>{:08X}</espi:dstEndRule>
+        <espi:dstOffset>{}</espi:dstOffset>
+        <espi:dstStartRule>{:08X}</espi:dstStartRule>
+        <espi:tzOffset>{}</espi:tzOffset>
+      </espi:LocalTimeParameters>\n",
+                dst_end_rule, dst_offset, dst_start_rule, tz_offset
+            ),
+        }];
+
+        let mut reading_type_count = 0;
+        for (usage_point, meter_readings) in self.espi_usage_points().into_iter().enumerate() {
+            let title = &self.title[meter_readings[0][0]];
+            let usage_point_href = format!("{SUBSCRIPTION_HREF}/UsagePoint/{usage_point}");
+            entries.push(Entry {
+                title,
+                self_href: usage_point_href.clone(),
+                up_href: format!("{SUBSCRIPTION_HREF}/UsagePoint"),
+                entry_type: "UsagePoint",
+                related: vec![
+                    (
+                        local_time_parameters_href.clone(),
+                        "espi-entry/LocalTimeParameters",
+                    ),
+                    (
+                        format!("{usage_point_href}/MeterReading"),
+                        "espi-feed/MeterReading",
+                    ),
+                ],
+                content: format!(
+                    "      <espi:UsagePoint>
+        <espi:ServiceCategory>
+          <espi:kind>{}</espi:kind>
+        </espi:ServiceCategory>
+      </espi:UsagePoint>\n",
+                    service_kind(self.commodity[meter_readings[0][0]])
+                ),
+            });
+
+            for (meter_reading, rows) in meter_readings.into_iter().enumerate() {
+                let power = power_of_ten_multiplier(
+                    &rows.iter().map(|i| self.value[*i]).collect::<Vec<_>>(),
+                );
+                let meter_reading_href = format!("{usage_point_href}/MeterReading/{meter_reading}");
+                let reading_type_href = format!("{BASE_HREF}/ReadingType/{reading_type_count}");
+                reading_type_count += 1;
+
+                entries.push(Entry {
+                    title: "Meter Reading",
+                    self_href: meter_reading_href.clone(),
+                    up_href: format!("{usage_point_href}/MeterReading"),
+                    entry_type: "MeterReading",
+                    related: vec![
+                        (
+                            format!("{meter_reading_href}/IntervalBlock"),
+                            "espi-feed/IntervalBlock",
+                        ),
+                        (reading_type_href.clone(), "espi-entry/ReadingType"),
+                    ],
+                    content: "      <espi:MeterReading/>\n".to_string(),
+                });
+                entries.push(Entry {
+                    title: "Reading Type",
+                    self_href: reading_type_href,
+                    up_href: format!("{BASE_HREF}/ReadingType"),
+                    entry_type: "ReadingType",
+                    related: vec![],
+                    content: self.espi_reading_type(&rows, power)?,
+                });
+
+                let days = rows.chunk_by(|i, j| {
+                    self.time_period_start_unix[*i].div_euclid(SECONDS_PER_DAY)
+                        == self.time_period_start_unix[*j].div_euclid(SECONDS_PER_DAY)
+                });
+                for (block, day) in days.enumerate() {
+                    entries.push(Entry {
+                        title,
+                        self_href: format!("{meter_reading_href}/IntervalBlock/{block}"),
+                        up_href: format!("{meter_reading_href}/IntervalBlock"),
+                        entry_type: "IntervalBlock",
+                        related: vec![(meter_reading_href.clone(), "espi-entry/MeterReading")],
+                        content: self.espi_interval_block(day, &starts, power)?,
+                    });
+                }
+            }
+        }
+
+        let mut result = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<feed xmlns=\"http://www.w3.org/2005/Atom\"
+      xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\"
+      xmlns:espi=\"http://naesb.org/espi\"
+      xsi:schemaLocation=\"http://www.w3.org/2005/Atom https://greenbuttondata.org/xsd/3_3/atom.xsd http://naesb.org/espi https://www.naesb.org/espi.xsd\">
+  <id>urn:uuid:00000000-0000-0000-0000-000000000000</id>
+  <title>Green Button Export</title>
+  <updated>{updated}</updated>\n"
+        );
+        for (id, entry) in entries.iter().enumerate() {
+            entry.write(&mut result, id + 1, &updated);
+        }
+        result += "</feed>\n";
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::{
+        parse_xml,
+        test_util::{eastern, evenly_spaced_timeseries},
+    };
+
+    #[test]
+    fn round_trip() {
+        // 2024-01-01 22:00:00, so the readings span two days.
+        let mut ts = evenly_spaced_timeseries(
+            "House & garage",
+            "energy",
+            "Wh",
+            1704146400,
+            3600,
+            &[1.5, 2.25, 0.1, 400.0],
+        );
+        ts.cost = vec![0.12, f32::NAN, 0.5, 1.0];
+        ts.quality[1] = "estimated using linear interpolation";
+        let mut demand = evenly_spaced_timeseries("House & garage", "demand", "W", 0, 900, &[1.0]);
+        demand.tou = vec![2];
+        ts.extend(demand);
+        ts.extend(evenly_spaced_timeseries(
+            "Other",
+            "energy",
+            "Wh",
+            0,
+            3600,
+            &[3.0],
+        ));
+
+        let xml = ts.as_espi_xml().unwrap();
+        let parsed = parse_xml(&xml).unwrap();
+        assert_eq!(parsed.value, ts.value);
+        assert_eq!(parsed.title, ts.title);
+        assert_eq!(parsed.quality, ts.quality);
+        assert_eq!(parsed.tou, ts.tou);
+        assert_eq!(parsed.kind, ts.kind);
+        assert_eq!(parsed.uom, ts.uom);
+        assert_eq!(parsed.time_period_start_unix, ts.time_period_start_unix);
+        assert_eq!(
+            parsed.time_period_duration_seconds,
+            ts.time_period_duration_seconds
+        );
+        assert_eq!(parsed.cost[0], 0.12);
+        assert!(parsed.cost[1].is_nan());
+    }
+
+    #[test]
+    fn round_trip_dst() {
+        // The local hour repeated when DST ends in 2024.
+        let repeated = (1730419200..)
+            .step_by(3600)
+            .find(|x| eastern().is_repeated(*x))
+            .unwrap();
+        // 2024-07-01 12:00 EDT, then the hours around the end of DST, with both readings of the
+        // repeated one.
+        let mut ts = evenly_spaced_timeseries("House", "energy", "Wh", 1719835200, 3600, &[1.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "House",
+            "energy",
+            "Wh",
+            repeated - 3600,
+            3600,
+            &[2.0, 3.0],
+        ));
+        ts.extend(evenly_spaced_timeseries(
+            "House",
+            "energy",
+            "Wh",
+            repeated,
+            3600,
+            &[4.0, 5.0],
+        ));
+        ts.provenance.local_time = Some(eastern());
+
+        let xml = ts.as_espi_xml().unwrap();
+        assert!(xml.contains("<espi:dstStartRule>360E2000</espi:dstStartRule>"));
+        assert!(xml.contains("<espi:tzOffset>-18000</espi:tzOffset>"));
+        let starts: Vec<i64> = xml
+            .split("<espi:timePeriod>")
+            .skip(1)
+            .map(|x| {
+                let x = &x[x.find("<espi:start>").unwrap() + "<espi:start>".len()..];
+                return x[..x.find('<').unwrap()].parse().unwrap();
+            })
+            .collect();
+        assert_eq!(
+            starts,
+            [
+                1719835200 + 4 * 3600,
+                repeated - 3600 + 4 * 3600,
+                repeated + 4 * 3600,
+                repeated + 5 * 3600,
+                repeated + 3600 + 5 * 3600,
+            ]
+        );
+
+        let parsed = parse_xml(&xml).unwrap();
+        assert_eq!(parsed.value, ts.value);
+        assert_eq!(parsed.time_period_start_unix, ts.time_period_start_unix);
+    }
+
+    #[test]
+    fn test_file_round_trip() {
+        let xml = fs::read_to_string("../../test_files/EGD_Gas_EnergyUsage_20221225_20241225.xml")
+            .unwrap();
+        let mut ts = parse_xml(&xml).unwrap();
+        ts.sort();
+        let mut parsed = parse_xml(&ts.as_espi_xml().unwrap()).unwrap();
+        parsed.sort();
+        assert_eq!(parsed.value, ts.value);
+        assert_eq!(parsed.time_period_start_unix, ts.time_period_start_unix);
+        assert_eq!(parsed.commodity, ts.commodity);
+    }
+}
@@ -0,0 +1,242 @@
+use std::ops::Range;
+
+use permutation::Permutation;
+
+use crate::TimeSeries;
+
+// Derived columns (weather joins, anomaly flags, ...) which aren't part of the Green Button
+// data itself. Exporters append these after the regular columns.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExtraColumnValues {
+    F32(Vec<f32>),
+    Str(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtraColumn {
+    pub name: String,
+    // One value per reading.
+    pub values: ExtraColumnValues,
+}
+
+impl ExtraColumnValues {
+    pub fn len(&self) -> usize {
+        return match self {
+            ExtraColumnValues::F32(x) => x.len(),
+            ExtraColumnValues::Str(x) => x.len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // The value used for rows which don't have this column.
+    fn pad(&mut self, len: usize) {
+        match self {
+            ExtraColumnValues::F32(x) => x.resize(len, f32::NAN),
+            ExtraColumnValues::Str(x) => x.resize(len, String::new()),
+        }
+    }
+
+    fn empty_like(&self) -> ExtraColumnValues {
+        return match self {
+            ExtraColumnValues::F32(_) => ExtraColumnValues::F32(vec![]),
+            ExtraColumnValues::Str(_) => ExtraColumnValues::Str(vec![]),
+        };
+    }
+
+    fn drain(&mut self, range: Range<usize>) -> ExtraColumnValues {
+        return match self {
+            ExtraColumnValues::F32(x) => ExtraColumnValues::F32(x.drain(range).collect()),
+            ExtraColumnValues::Str(x) => ExtraColumnValues::Str(x.drain(range).collect()),
+        };
+    }
+
+    fn select(&self, indices: &[usize]) -> ExtraColumnValues {
+        return match self {
+            ExtraColumnValues::F32(x) => {
+                ExtraColumnValues::F32(indices.iter().map(|i| x[*i]).collect())
+            }
+            ExtraColumnValues::Str(x) => {
+                ExtraColumnValues::Str(indices.iter().map(|i| x[*i].clone()).collect())
+            }
+        };
+    }
+
+    fn extend(&mut self, other: ExtraColumnValues) {
+        match (self, other) {
+            (ExtraColumnValues::F32(x), ExtraColumnValues::F32(y)) => x.extend(y),
+            (ExtraColumnValues::Str(x), ExtraColumnValues::Str(y)) => x.extend(y),
+            (x, y) => {
+                // Mismatched types for the same name. Keep the existing type and pad.
+                let len = x.len() + y.len();
+                x.pad(len);
+            }
+        }
+    }
+
+    fn apply_permutation(&mut self, p: &mut Permutation) {
+        match self {
+            ExtraColumnValues::F32(x) => p.apply_slice_in_place(x),
+            ExtraColumnValues::Str(x) => p.apply_slice_in_place(x),
+        }
+    }
+
+    /// String representation of a single value, as used by text exporters.
+    pub fn value_to_string(&self, i: usize) -> String {
+        return match self {
+            ExtraColumnValues::F32(x) => x[i].to_string(),
+            ExtraColumnValues::Str(x) => x[i].to_string(),
+        };
+    }
+}
+
+impl TimeSeries {
+    /// Adds a derived column, replacing any existing column with the same name. There must be one
+    /// value per reading.
+    pub fn set_extra_column(
+        &mut self,
+        name: impl Into<String>,
+        values: ExtraColumnValues,
+    ) -> Result<(), String> {
+        let name = name.into();
+        if values.len() != self.value.len() {
+            return Err(format!(
+                "Extra column {:?} has {} values, but there are {} readings",
+                name,
+                values.len(),
+                self.value.len()
+            ));
+        }
+        let column = ExtraColumn { name, values };
+        match self
+            .extra_columns
+            .iter_mut()
+            .find(|x| x.name == column.name)
+        {
+            Some(existing) => *existing = column,
+            None => self.extra_columns.push(column),
+        }
+        return Ok(());
+    }
+
+    pub fn extra_column(&self, name: &str) -> Option<&ExtraColumnValues> {
+        return self
+            .extra_columns
+            .iter()
+            .find(|x| x.name == name)
+            .map(|x| &x.values);
+    }
+
+    pub(crate) fn drain_extra_columns(&mut self, range: Range<usize>) -> Vec<ExtraColumn> {
+        return self
+            .extra_columns
+            .iter_mut()
+            .map(|x| ExtraColumn {
+                name: x.name.clone(),
+                values: x.values.drain(range.clone()),
+            })
+            .collect();
+    }
+
+    pub(crate) fn select_extra_columns(&self, indices: &[usize]) -> Vec<ExtraColumn> {
+        return self
+            .extra_columns
+            .iter()
+            .map(|x| ExtraColumn {
+                name: x.name.clone(),
+                values: x.values.select(indices),
+            })
+            .collect();
+    }
+
+    pub(crate) fn apply_permutation_to_extra_columns(&mut self, p: &mut Permutation) {
+        for column in &mut self.extra_columns {
+            column.values.apply_permutation(p);
+        }
+    }
+
+    // Must be called before the regular columns are extended.
+    pub(crate) fn extend_extra_columns(&mut self, other_len: usize, other: Vec<ExtraColumn>) {
+        let self_len = self.value.len();
+        for mut other_column in other {
+            match self
+                .extra_columns
+                .iter_mut()
+                .find(|x| x.name == other_column.name)
+            {
+                Some(column) => column.values.extend(other_column.values),
+                None => {
+                    let mut values = other_column.values.empty_like();
+                    values.pad(self_len);
+                    values.extend(other_column.values);
+                    other_column.values = values;
+                    self.extra_columns.push(other_column);
+                }
+            }
+        }
+        // Columns which other didn't have.
+        for column in &mut self.extra_columns {
+            column.values.pad(self_len + other_len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExtraColumnValues;
+    use crate::test_util::evenly_spaced_timeseries;
+
+    #[test]
+    fn extend_pads_missing_columns() {
+        let mut a = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0, 2.0]);
+        a.set_extra_column("x", ExtraColumnValues::F32(vec![1.0, 2.0]))
+            .unwrap();
+        let mut b = evenly_spaced_timeseries("b", "energy", "Wh", 0, 3600, &[3.0]);
+        b.set_extra_column("y", ExtraColumnValues::Str(vec!["y".into()]))
+            .unwrap();
+        a.extend(b);
+
+        match a.extra_column("x").unwrap() {
+            ExtraColumnValues::F32(x) => {
+                assert_eq!(x[..2], [1.0, 2.0]);
+                assert!(x[2].is_nan());
+            }
+            _ => panic!("Wrong column type"),
+        }
+        assert!(a
+            .set_extra_column("z", ExtraColumnValues::F32(vec![1.0]))
+            .is_err());
+        assert_eq!(
+            a.extra_column("y").unwrap(),
+            &ExtraColumnValues::Str(vec!["".into(), "".into(), "y".into()])
+        );
+    }
+
+    #[test]
+    fn sort_and_chunk_keeps_extra_columns_aligned() {
+        let mut ts = evenly_spaced_timeseries("b", "energy", "Wh", 0, 3600, &[1.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            0,
+            3600,
+            &[2.0],
+        ));
+        ts.set_extra_column("x", ExtraColumnValues::F32(vec![10.0, 20.0]))
+            .unwrap();
+        let chunks = ts.sort_and_chunk();
+        assert_eq!(chunks[0].value, vec![2.0]);
+        assert_eq!(
+            chunks[0].extra_column("x").unwrap(),
+            &ExtraColumnValues::F32(vec![20.0])
+        );
+        assert_eq!(
+            chunks[1].extra_column("x").unwrap(),
+            &ExtraColumnValues::F32(vec![10.0])
+        );
+    }
+}
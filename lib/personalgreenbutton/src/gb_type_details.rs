@@ -1,5 +1,23 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+
 include!(concat!(env!("OUT_DIR"), "/espixsd.rs"));
 
+// (xml_type, field, app_info) => app_info, for looking up many values without scanning the map.
+static APP_INFOS: Lazy<HashMap<(&str, &str, &str), &'static str>> = Lazy::new(|| {
+    return GB_TYPE_DETAILS
+        .entries()
+        .map(|(key, details)| {
+            // Keys are xml_typeœfieldœvalue.
+            let mut parts = key.split('œ');
+            let xml_type = parts.next().unwrap_or_default();
+            let field = parts.next().unwrap_or_default();
+            return ((xml_type, field, details.0), details.0);
+        })
+        .collect();
+});
+
 pub struct GreenButtonFieldMetadata<'a> {
     pub app_info: &'a str,
     pub description: &'a str,
@@ -29,3 +47,57 @@ pub fn get_gb_type_details<'a>(
         }
     }
 }
+
+/// Reverse of get_gb_type_details, mapping an app_info string back to its enum value.
+/// If several values share an app_info, the smallest value is returned.
+pub fn find_gb_type_value(
+    xml_type: &str,
+    field: &str,
+    app_info: &str,
+) -> Option<(i32, GreenButtonFieldMetadata<'static>)> {
+    let prefix = format!("{}œ{}œ", xml_type, field);
+    return GB_TYPE_DETAILS
+        .entries()
+        .filter(|(key, details)| details.0 == app_info && key.starts_with(&prefix))
+        .filter_map(|(key, details)| {
+            let value: i32 = key[prefix.len()..].parse().ok()?;
+            return Some((
+                value,
+                GreenButtonFieldMetadata {
+                    app_info: details.0,
+                    description: details.1,
+                },
+            ));
+        })
+        .min_by_key(|x| x.0);
+}
+
+/// The static app_info string equal to app_info, if it's one of the field's values.
+pub(crate) fn find_gb_type_app_info(
+    xml_type: &str,
+    field: &str,
+    app_info: &str,
+) -> Option<&'static str> {
+    return APP_INFOS.get(&(xml_type, field, app_info)).copied();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{find_gb_type_app_info, find_gb_type_value, get_gb_type_details};
+
+    #[test]
+    fn find_value_round_trips() {
+        let (value, details) = find_gb_type_value("ReadingType", "uom", "Wh").unwrap();
+        assert_eq!(value, 72);
+        assert_eq!(
+            details.description,
+            get_gb_type_details("ReadingType", "uom", 72).description
+        );
+        assert!(find_gb_type_value("ReadingType", "uom", "furlongs").is_none());
+        assert_eq!(
+            find_gb_type_app_info("ReadingType", "uom", "Wh"),
+            Some("Wh")
+        );
+        assert!(find_gb_type_app_info("ReadingType", "kind", "Wh").is_none());
+    }
+}
@@ -0,0 +1,717 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+
+use crate::{reading_type::is_cumulative, TimeSeries};
+
+// Utility "Download My Data" Green Button CSV files.
+//
+// Supported dialects, detected from the header row:
+//  - Typed (PG&E, SDG&E, Con Edison and most others): a preamble of "key,value" rows (name,
+//    address, account number, service), then columns including DATE and START TIME, and usually
+//    TYPE, END TIME, USAGE, UNITS, COST and NOTES. Net metered accounts have IMPORT (kWh) and
+//    EXPORT (kWh) columns instead of USAGE. Units may also be part of the column name, as in
+//    "USAGE (kWh)".
+//  - Time period (SCE): an "Energy consumption time period" column holding
+//    "<start> to <end>", and a usage column naming its units, like
+//    "Usage(Real energy in kilowatt-hours)".
+//
+// Times are local, matching the XML parser's output. Costs are assumed to be in USD.
+//
+// as_green_button_csv writes the same dialects, see GreenButtonCsvDialect, so its output parses
+// back with parse_green_button_csv.
+
+enum Layout {
+    Typed {
+        type_column: Option<usize>,
+        date: usize,
+        start_time: usize,
+        end_time: usize,
+        // (column, flow direction, units from the column name).
+        usage: Vec<(usize, &'static str, Option<String>)>,
+        units: Option<usize>,
+        cost: Option<usize>,
+    },
+    TimePeriod {
+        period: usize,
+        usage: usize,
+        units: String,
+    },
+}
+
+struct Units {
+    multiplier: f32,
+    uom: &'static str,
+    kind: &'static str,
+    commodity: &'static str,
+}
+
+fn parse_units(s: &str) -> Result<Units> {
+    let units = |multiplier, uom, kind, commodity| Units {
+        multiplier,
+        uom,
+        kind,
+        commodity,
+    };
+    let electricity = "electricity SecondaryMetered";
+    let lower = s.trim().to_lowercase();
+    return Ok(match lower.as_str() {
+        "kwh" | "kilowatt-hours" | "real energy in kilowatt-hours" => {
+            units(1000.0, "Wh", "energy", electricity)
+        }
+        "wh" => units(1.0, "Wh", "energy", electricity),
+        "kw" | "kilowatts" => units(1000.0, "W", "demand", electricity),
+        "therm" | "therms" => units(1.0, "therm", "energy", "naturalGas"),
+        "ccf" => units(100.0, "ft3", "volume", "naturalGas"),
+        "cf" | "ft3" | "cubic feet" => units(1.0, "ft3", "volume", "naturalGas"),
+        "m3" | "cubic meters" => units(1.0, "m3", "volume", "naturalGas"),
+        "gal" | "gallons" => units(1.0, "usGal", "volume", "potableWater"),
+        _ => return Err(anyhow!("Unrecognized units {:?}", s)),
+    });
+}
+
+// "USAGE (kWh)" => "kWh".
+fn units_in_column_name(name: &str) -> Option<String> {
+    let (_, rest) = name.split_once('(')?;
+    let (units, _) = rest.split_once(')')?;
+    return Some(units.trim().to_string());
+}
+
+fn commodity_from_type(s: &str) -> Option<&'static str> {
+    let lower = s.to_lowercase();
+    if lower.contains("electric") {
+        return Some("electricity SecondaryMetered");
+    }
+    if lower.contains("gas") {
+        return Some("naturalGas");
+    }
+    if lower.contains("water") {
+        return Some("potableWater");
+    }
+    return None;
+}
+
+fn detect_layout(row: &csv::StringRecord) -> Option<Layout> {
+    let names: Vec<String> = row.iter().map(|x| x.trim().to_uppercase()).collect();
+    let find = |name: &str| names.iter().position(|x| x == name);
+
+    if let Some(period) = names
+        .iter()
+        .position(|x| x.starts_with("ENERGY CONSUMPTION TIME PERIOD"))
+    {
+        let usage = names.iter().position(|x| x.starts_with("USAGE"))?;
+        let units = units_in_column_name(&row[usage])?;
+        return Some(Layout::TimePeriod {
+            period,
+            usage,
+            units,
+        });
+    }
+
+    let (Some(date), Some(start_time)) = (find("DATE"), find("START TIME")) else {
+        return None;
+    };
+    let mut usage = vec![];
+    for (i, name) in names.iter().enumerate() {
+        let flow_direction = if name.starts_with("USAGE") || name.starts_with("IMPORT") {
+            "forward"
+        } else if name.starts_with("EXPORT") {
+            "reverse"
+        } else {
+            continue;
+        };
+        usage.push((i, flow_direction, units_in_column_name(&row[i])));
+    }
+    if usage.is_empty() {
+        return None;
+    }
+    return Some(Layout::Typed {
+        type_column: find("TYPE"),
+        date,
+        start_time,
+        end_time: find("END TIME")?,
+        usage,
+        units: find("UNITS"),
+        cost: find("COST"),
+    });
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate> {
+    for format in ["%Y-%m-%d", "%m/%d/%Y", "%m/%d/%y"] {
+        if let Ok(date) = NaiveDate::parse_from_str(s, format) {
+            return Ok(date);
+        }
+    }
+    return Err(anyhow!("Unrecognized date {:?}", s));
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime> {
+    for format in ["%H:%M", "%H:%M:%S", "%I:%M %p", "%I:%M:%S %p"] {
+        if let Ok(time) = NaiveTime::parse_from_str(s, format) {
+            return Ok(time);
+        }
+    }
+    return Err(anyhow!("Unrecognized time {:?}", s));
+}
+
+fn parse_datetime(s: &str) -> Result<NaiveDateTime> {
+    for format in [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%m/%d/%Y %H:%M:%S",
+        "%m/%d/%Y %H:%M",
+        "%m/%d/%Y %I:%M %p",
+    ] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(s, format) {
+            return Ok(datetime);
+        }
+    }
+    return Err(anyhow!("Unrecognized date and time {:?}", s));
+}
+
+// Handles "$1,234.56", "-$0.12" and "($0.12)". Empty costs are NaN.
+fn parse_amount(s: &str) -> Result<f32> {
+    let negative = s.starts_with('(') && s.ends_with(')');
+    let cleaned: String = s
+        .chars()
+        .filter(|x| !['$', ',', '(', ')', ' '].contains(x))
+        .collect();
+    if cleaned.is_empty() {
+        return Ok(f32::NAN);
+    }
+    let value: f32 = cleaned
+        .parse()
+        .map_err(|_| anyhow!("Unrecognized number {:?}", s))?;
+    return Ok(if negative { -value } else { value });
+}
+
+// End times are usually the last minute of the interval ("00:00" to "00:14"), but sometimes the
+// start of the next one. Intervals ending at midnight wrap.
+fn duration_seconds(start: NaiveDateTime, end: NaiveDateTime) -> i32 {
+    let mut duration = (end - start).num_seconds();
+    if duration < 0 {
+        duration += 24 * 3600;
+    }
+    if (duration + 60) % 900 == 0 {
+        duration += 60;
+    }
+    return duration as i32;
+}
+
+struct Reading {
+    title: String,
+    start: NaiveDateTime,
+    duration_seconds: i32,
+    value: f32,
+    cost: f32,
+    units: Units,
+    commodity: &'static str,
+    flow_direction: &'static str,
+}
+
+fn parse_row(
+    layout: &Layout,
+    row: &csv::StringRecord,
+    default_title: &str,
+) -> Result<Vec<Reading>> {
+    let field = |i: usize| row.get(i).unwrap_or("").trim();
+    let mut readings = vec![];
+    match layout {
+        Layout::Typed {
+            type_column,
+            date,
+            start_time,
+            end_time,
+            usage,
+            units,
+            cost,
+        } => {
+            let date = parse_date(field(*date))?;
+            let start = date.and_time(parse_time(field(*start_time))?);
+            let end = date.and_time(parse_time(field(*end_time))?);
+            let reading_type = type_column.map(field).unwrap_or("");
+            let title = if reading_type.is_empty() {
+                default_title
+            } else {
+                reading_type
+            };
+            // With both import and export columns, cost is attributed to import.
+            let mut cost = cost.map_or(Ok(f32::NAN), |x| parse_amount(field(x)))?;
+            for (column, flow_direction, column_units) in usage {
+                let value = parse_amount(field(*column))?;
+                if value.is_nan() {
+                    continue;
+                }
+                let units = match (column_units, units) {
+                    (Some(x), _) => parse_units(x)?,
+                    (None, Some(x)) => parse_units(field(*x))?,
+                    (None, None) => return Err(anyhow!("Missing units")),
+                };
+                readings.push(Reading {
+                    title: title.to_string(),
+                    start,
+                    duration_seconds: duration_seconds(start, end),
+                    value,
+                    cost,
+                    commodity: commodity_from_type(reading_type).unwrap_or(units.commodity),
+                    units,
+                    flow_direction,
+                });
+                cost = f32::NAN;
+            }
+        }
+        Layout::TimePeriod {
+            period,
+            usage,
+            units,
+        } => {
+            // Some exports use a non-breaking space around "to".
+            let period = field(*period).replace('\u{a0}', " ");
+            let (start, end) = period
+                .split_once(" to ")
+                .ok_or(anyhow!("Unrecognized time period {:?}", period))?;
+            let start = parse_datetime(start.trim())?;
+            let end = parse_datetime(end.trim())?;
+            let value = parse_amount(field(*usage))?;
+            if !value.is_nan() {
+                let units = parse_units(units)?;
+                readings.push(Reading {
+                    title: default_title.to_string(),
+                    start,
+                    duration_seconds: (end - start).num_seconds() as i32,
+                    value,
+                    cost: f32::NAN,
+                    commodity: units.commodity,
+                    units,
+                    flow_direction: "forward",
+                });
+            }
+        }
+    }
+    return Ok(readings);
+}
+
+pub fn parse_green_button_csv(s: &str) -> Result<TimeSeries> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(s.trim_start_matches('\u{feff}').as_bytes());
+    let mut rows = reader.records();
+
+    // Preamble rows are "key,value" pairs, until the header.
+    let mut default_title = "Green Button CSV".to_string();
+    let layout = loop {
+        let row = rows
+            .next()
+            .ok_or(anyhow!("Missing Green Button CSV header"))??;
+        if let Some(layout) = detect_layout(&row) {
+            break layout;
+        }
+        if row.len() >= 2 && row[0].trim().eq_ignore_ascii_case("service") {
+            default_title = row[1].trim().to_string();
+        }
+    };
+
+    let mut timeseries = TimeSeries::default();
+    for row in rows {
+        let row = row?;
+        if row.iter().all(|x| x.trim().is_empty()) {
+            continue;
+        }
+        for reading in parse_row(&layout, &row, &default_title)? {
+            timeseries.title.push(reading.title);
+            timeseries.cost.push(reading.cost);
+            timeseries.quality.push("other");
+            timeseries
+                .value
+                .push(reading.value * reading.units.multiplier);
+            timeseries.tou.push(0);
+            timeseries
+                .time_period_start_unix
+                .push(reading.start.and_utc().timestamp());
+            timeseries
+                .time_period_duration_seconds
+                .push(reading.duration_seconds);
+            timeseries.accumulation_behaviour.push("deltaData");
+            timeseries.commodity.push(reading.commodity);
+            timeseries.currency.push("USD");
+            timeseries.data_qualifier.push("normal");
+            timeseries.flow_direction.push(reading.flow_direction);
+            timeseries.kind.push(reading.units.kind);
+            timeseries.phase.push("none");
+            timeseries.uom.push(reading.units.uom);
+        }
+    }
+    return Ok(timeseries);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GreenButtonCsvDialect {
+    // Preamble, then TYPE,DATE,START TIME,END TIME,USAGE,UNITS,COST,NOTES with ISO dates and
+    // 24 hour times, as PG&E.
+    Typed,
+    // The same columns, with US dates and 12 hour times ("1/31/2024", "12:00 AM").
+    TypedUsDates,
+    // "Energy consumption time period" and a usage column naming the units, as SCE. Only holds
+    // one unit.
+    TimePeriod,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GreenButtonCsvOptions {
+    pub dialect: GreenButtonCsvDialect,
+    // Preamble values for the typed dialects. Utilities fill these in from the account.
+    pub name: String,
+    pub address: String,
+    pub account_number: String,
+}
+
+impl Default for GreenButtonCsvOptions {
+    fn default() -> Self {
+        return GreenButtonCsvOptions {
+            dialect: GreenButtonCsvDialect::Typed,
+            name: String::new(),
+            address: String::new(),
+            account_number: String::new(),
+        };
+    }
+}
+
+// Units as written, and what to divide the value by. The inverse of parse_units.
+fn csv_units(uom: &str) -> Option<(&'static str, f32)> {
+    return match uom {
+        "Wh" => Some(("kWh", 1000.0)),
+        "W" => Some(("kW", 1000.0)),
+        "therm" => Some(("therms", 1.0)),
+        "ft3" => Some(("CCF", 100.0)),
+        "m3" => Some(("m3", 1.0)),
+        "usGal" => Some(("gal", 1.0)),
+        _ => None,
+    };
+}
+
+// The TYPE column. The parser takes the commodity from it.
+fn type_from_commodity(commodity: &str) -> &'static str {
+    if commodity.starts_with("electricity") {
+        return "Electric usage";
+    }
+    return match commodity {
+        "naturalGas" => "Natural gas usage",
+        "potableWater" => "Water usage",
+        _ => "Usage",
+    };
+}
+
+fn format_amount(x: f32) -> String {
+    if x.is_nan() {
+        return String::new();
+    }
+    if x < 0.0 {
+        return format!("-${:.2}", -x);
+    }
+    return format!("${:.2}", x);
+}
+
+impl TimeSeries {
+    /// Utility style Green Button CSV. Readings in units without a CSV equivalent, and
+    /// cumulative registers, are left out. The typed dialects expect intervals of a day or less.
+    /// Fails on readings other than forward (delivered) flow, on more than one title, and for the
+    /// time period dialect, on more than one unit, since the output couldn't tell them apart.
+    pub fn as_green_button_csv_with_options(
+        &self,
+        options: &GreenButtonCsvOptions,
+    ) -> Result<String, String> {
+        let mut rows: Vec<(usize, &str, f32)> = vec![];
+        let mut order: Vec<usize> = (0..self.value.len()).collect();
+        order.sort_by_key(|i| (&self.title[*i], self.time_period_start_unix[*i]));
+        for i in order {
+            if is_cumulative(self.accumulation_behaviour[i]) {
+                continue;
+            }
+            if let Some((units, divisor)) = csv_units(self.uom[i]) {
+                rows.push((i, units, divisor));
+            }
+        }
+        if let Some((i, _, _)) = rows.iter().find(|x| self.flow_direction[x.0] != "forward") {
+            return Err(format!(
+                "Green Button CSV only holds forward readings, got {} readings in {}.",
+                self.flow_direction[*i], self.title[*i]
+            ));
+        }
+        let mut titles: Vec<&str> = rows.iter().map(|x| self.title[x.0].as_str()).collect();
+        titles.dedup();
+        if titles.len() > 1 {
+            return Err(format!(
+                "Green Button CSV holds one title, got {}.",
+                titles.join(", ")
+            ));
+        }
+
+        let local = |unix: i64| -> Result<NaiveDateTime, String> {
+            return Ok(DateTime::from_timestamp(unix, 0)
+                .ok_or("Invalid timestamp")?
+                .naive_utc());
+        };
+        let csv_error = |x: csv::Error| x.to_string();
+        let writer = |buf: Vec<u8>| csv::WriterBuilder::new().flexible(true).from_writer(buf);
+        let mut wtr = writer(vec![]);
+        match options.dialect {
+            GreenButtonCsvDialect::Typed | GreenButtonCsvDialect::TypedUsDates => {
+                let (date_format, time_format) = match options.dialect {
+                    GreenButtonCsvDialect::Typed => ("%Y-%m-%d", "%H:%M"),
+                    _ => ("%-m/%-d/%Y", "%I:%M %p"),
+                };
+                wtr.write_record(["Name", &options.name])
+                    .map_err(csv_error)?;
+                wtr.write_record(["Address", &options.address])
+                    .map_err(csv_error)?;
+                wtr.write_record(["Account Number", &options.account_number])
+                    .map_err(csv_error)?;
+                wtr.write_record(["Service", titles.first().unwrap_or(&"")])
+                    .map_err(csv_error)?;
+                // An empty line before the header.
+                let mut buf = wtr.into_inner().map_err(|x| x.to_string())?;
+                buf.push(b'\n');
+                wtr = writer(buf);
+                wtr.write_record([
+                    "TYPE",
+                    "DATE",
+                    "START TIME",
+                    "END TIME",
+                    "USAGE",
+                    "UNITS",
+                    "COST",
+                    "NOTES",
+                ])
+                .map_err(csv_error)?;
+                for (i, units, divisor) in rows {
+                    let start = local(self.time_period_start_unix[i])?;
+                    // The last minute of the interval.
+                    let end = local(
+                        self.time_period_start_unix[i]
+                            + (self.time_period_duration_seconds[i] as i64 - 60).max(0),
+                    )?;
+                    wtr.write_record([
+                        type_from_commodity(self.commodity[i]).to_string(),
+                        start.format(date_format).to_string(),
+                        start.format(time_format).to_string(),
+                        end.format(time_format).to_string(),
+                        (self.value[i] / divisor).to_string(),
+                        units.to_string(),
+                        format_amount(self.cost[i]),
+                        String::new(),
+                    ])
+                    .map_err(csv_error)?;
+                }
+            }
+            GreenButtonCsvDialect::TimePeriod => {
+                let mut units: Vec<&str> = rows.iter().map(|x| x.1).collect();
+                units.sort();
+                units.dedup();
+                if units.len() > 1 {
+                    return Err(format!(
+                        "The time period dialect holds one unit, got {}.",
+                        units.join(", ")
+                    ));
+                }
+                let usage = match units.first() {
+                    Some(&"kWh") | None => "Real energy in kilowatt-hours",
+                    Some(x) => x,
+                };
+                wtr.write_record(["Energy Usage Information"])
+                    .map_err(csv_error)?;
+                wtr.write_record([
+                    "Energy consumption time period",
+                    &format!("Usage({})", usage),
+                    "Reading quality",
+                ])
+                .map_err(csv_error)?;
+                for (i, _, divisor) in rows {
+                    let start = self.time_period_start_unix[i];
+                    let end = start + self.time_period_duration_seconds[i] as i64;
+                    let format = "%Y-%m-%d %H:%M:%S";
+                    wtr.write_record([
+                        format!(
+                            "{} to {}",
+                            local(start)?.format(format),
+                            local(end)?.format(format)
+                        ),
+                        (self.value[i] / divisor).to_string(),
+                        String::new(),
+                    ])
+                    .map_err(csv_error)?;
+                }
+            }
+        }
+        let csv = String::from_utf8(wtr.into_inner().map_err(|x| x.to_string())?).unwrap();
+        return Ok(csv);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_green_button_csv, GreenButtonCsvDialect, GreenButtonCsvOptions};
+    use crate::test_util::evenly_spaced_timeseries;
+
+    #[test]
+    fn typed_with_preamble() {
+        let csv = "\u{feff}Name,JANE DOE
+Address,\"1 MAIN ST, SPRINGFIELD\"
+Account Number,1234
+Service,Service 1
+
+TYPE,DATE,START TIME,END TIME,USAGE,UNITS,COST,NOTES
+Electric usage,2024-01-01,00:00,00:14,0.25,kWh,$0.05,
+Electric usage,2024-01-01,00:15,00:29,0.50,kWh,($0.10),
+Natural gas usage,1/1/2024,12:00 AM,11:59 PM,1.5,therms,\"$1,002.00\",
+";
+        let ts = parse_green_button_csv(csv).unwrap();
+        assert_eq!(
+            ts.title,
+            ["Electric usage", "Electric usage", "Natural gas usage"]
+        );
+        assert_eq!(ts.value, [250.0, 500.0, 1.5]);
+        assert_eq!(ts.uom, ["Wh", "Wh", "therm"]);
+        assert_eq!(ts.commodity[2], "naturalGas");
+        assert_eq!(ts.cost, [0.05, -0.1, 1002.0]);
+        // 2024-01-01 00:15:00.
+        assert_eq!(ts.time_period_start_unix[1], 1704068100);
+        assert_eq!(ts.time_period_duration_seconds, [900, 900, 86400]);
+    }
+
+    #[test]
+    fn net_metered() {
+        let csv = "TYPE,DATE,START TIME,END TIME,IMPORT (kWh),EXPORT (kWh),COST
+Electric usage,2024-01-01,12:00,12:59,0.1,2.0,$0.01
+";
+        let ts = parse_green_button_csv(csv).unwrap();
+        assert_eq!(ts.flow_direction, ["forward", "reverse"]);
+        assert_eq!(ts.value, [100.0, 2000.0]);
+        assert_eq!(ts.cost[0], 0.01);
+        assert!(ts.cost[1].is_nan());
+        assert_eq!(ts.time_period_duration_seconds, [3600, 3600]);
+    }
+
+    #[test]
+    fn time_period() {
+        let csv = "Energy Usage Information
+Energy consumption time period,Usage(Real energy in kilowatt-hours),Reading quality
+\"2024-01-01 00:00:00 to 2024-01-01 01:00:00\",\"1.250\",\"\"
+";
+        let ts = parse_green_button_csv(csv).unwrap();
+        assert_eq!(ts.value, [1250.0]);
+        assert_eq!(ts.time_period_duration_seconds, [3600]);
+        assert_eq!(ts.kind, ["energy"]);
+    }
+
+    #[test]
+    fn write_typed() {
+        // 2024-01-01 00:00, every 15 minutes.
+        let mut ts = evenly_spaced_timeseries(
+            "Service 1",
+            "energy",
+            "Wh",
+            1704067200,
+            900,
+            &[250.0, 500.0],
+        );
+        ts.cost = vec![0.05, -0.1];
+        let csv = ts
+            .as_green_button_csv_with_options(&GreenButtonCsvOptions {
+                name: "JANE DOE".to_string(),
+                ..Default::default()
+            })
+            .unwrap();
+        assert_eq!(
+            csv,
+            "Name,JANE DOE
+Address,
+Account Number,
+Service,Service 1
+
+TYPE,DATE,START TIME,END TIME,USAGE,UNITS,COST,NOTES
+Electric usage,2024-01-01,00:00,00:14,0.25,kWh,$0.05,
+Electric usage,2024-01-01,00:15,00:29,0.5,kWh,-$0.10,
+"
+        );
+        let read = parse_green_button_csv(&csv).unwrap();
+        // The title is only in the Service row, and the parser titles typed readings by TYPE.
+        assert_eq!(read.title, ["Electric usage", "Electric usage"]);
+        assert_eq!(read.value, ts.value);
+        assert_eq!(read.time_period_start_unix, ts.time_period_start_unix);
+        assert_eq!(
+            read.time_period_duration_seconds,
+            ts.time_period_duration_seconds
+        );
+
+        let options = GreenButtonCsvOptions {
+            dialect: GreenButtonCsvDialect::TypedUsDates,
+            ..Default::default()
+        };
+        let csv = ts.as_green_button_csv_with_options(&options).unwrap();
+        assert!(csv.contains("\nElectric usage,1/1/2024,12:15 AM,12:29 AM,0.5,kWh,-$0.10,\n"));
+        assert_eq!(parse_green_button_csv(&csv).unwrap().value, ts.value);
+
+        ts.extend(evenly_spaced_timeseries(
+            "Service 2",
+            "energy",
+            "Wh",
+            1704067200,
+            900,
+            &[1.0],
+        ));
+        assert_eq!(
+            ts.as_green_button_csv_with_options(&options),
+            Err("Green Button CSV holds one title, got Service 1, Service 2.".to_string())
+        );
+    }
+
+    #[test]
+    fn write_time_period() {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 1704067200, 3600, &[1250.0]);
+        let options = GreenButtonCsvOptions {
+            dialect: GreenButtonCsvDialect::TimePeriod,
+            ..Default::default()
+        };
+        let csv = ts.as_green_button_csv_with_options(&options).unwrap();
+        let read = parse_green_button_csv(&csv).unwrap();
+        assert_eq!(read.value, [1250.0]);
+        assert_eq!(read.time_period_duration_seconds, [3600]);
+
+        let mut other_title = ts.clone();
+        other_title.extend(evenly_spaced_timeseries(
+            "b",
+            "energy",
+            "Wh",
+            1704067200,
+            3600,
+            &[1.0],
+        ));
+        assert_eq!(
+            other_title.as_green_button_csv_with_options(&options),
+            Err("Green Button CSV holds one title, got a, b.".to_string())
+        );
+
+        ts.extend(evenly_spaced_timeseries(
+            "a",
+            "volume",
+            "m3",
+            0,
+            3600,
+            &[1.0],
+        ));
+        assert!(ts.as_green_button_csv_with_options(&options).is_err());
+    }
+
+    #[test]
+    fn write_rejects_reverse_flow() {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 1704067200, 3600, &[1.0, 2.0]);
+        ts.flow_direction[1] = "reverse";
+        assert_eq!(
+            ts.as_green_button_csv_with_options(&GreenButtonCsvOptions::default()),
+            Err(
+                "Green Button CSV only holds forward readings, got reverse readings in a."
+                    .to_string()
+            )
+        );
+    }
+}
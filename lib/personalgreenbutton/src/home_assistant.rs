@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+
+use chrono::{DateTime, FixedOffset};
+use serde_json::json;
+
+use crate::{
+    openmetrics::metric_name_part, reading_type::is_cumulative, stats::round6, TimeSeries,
+};
+
+// Home Assistant long-term statistics, for the energy dashboard. Readings are spread over the
+// hours they cover (pro rata, so a monthly gas reading adds a little to every hour of the
+// month) and summed per hour. Each (title, commodity, flow direction, unit) is a statistic with
+// id "<source>:<title>_<commodity>", or "<source>:<title>_<commodity>_return" for energy returned
+// to the grid, where sum is the running total and state is the same.
+//
+// Only forward and reverse interval readings in units HA knows are exported: electricity energy
+// in Wh, kWh or MWh, gas in m³ or ft³ (or kWh, see HomeAssistantOptions::gas_kwh_per_m3), therms
+// in kWh and water in m³, L or gal. Net readings, cumulative registers and anything else
+// (demand, power, ...) are skipped.
+//
+// Output shapes:
+// - as_home_assistant_json: a list of {"metadata": ..., "stats": [...]}, the body of the
+//   recorder/import_statistics websocket command, with starts as ISO 8601 with the UTC offset.
+//   The offset follows the feed's DST rules when known (see Provenance::local_time). Readings
+//   in the hour repeated when DST ends are all in its first hour.
+// - as_home_assistant_csv: statistic_id, unit, start, state, sum rows in the layout of the
+//   import_statistics custom integration, with starts as "dd.mm.YYYY HH:MM" local time.
+
+const SECONDS_PER_HOUR: i64 = 3600;
+const KWH_PER_THERM: f64 = 29.3071;
+const M3_PER_FT3: f64 = 0.0283168;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HomeAssistantEnergyUnit {
+    Wh,
+    KWh,
+    MWh,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct HomeAssistantOptions {
+    // Statistic id prefix. External statistics need a source other than "recorder".
+    pub source: String,
+    // Local time - UTC, since timestamps are stored in local time. Used for the JSON starts of
+    // readings without DST rules, e.g. from Green Button CSV.
+    pub utc_offset_seconds: i64,
+    pub electricity_unit: HomeAssistantEnergyUnit,
+    // Converts gas volumes to energy, e.g. 10.55 kWh per m³ of natural gas. Gas stays in m³ or
+    // ft³ when not set.
+    pub gas_kwh_per_m3: Option<f64>,
+}
+
+impl Default for HomeAssistantOptions {
+    fn default() -> Self {
+        return HomeAssistantOptions {
+            source: "greenbutton".to_string(),
+            utc_offset_seconds: 0,
+            electricity_unit: HomeAssistantEnergyUnit::KWh,
+            gas_kwh_per_m3: None,
+        };
+    }
+}
+
+// HA unit and the factor to convert a reading to it.
+fn home_assistant_unit(
+    commodity: &str,
+    kind: &str,
+    uom: &str,
+    options: &HomeAssistantOptions,
+) -> Option<(&'static str, f64)> {
+    let gas = matches!(commodity, "naturalGas" | "propane");
+    let volume_m3 = match uom {
+        "m3" | "m3compensated" | "m3uncompensated" => Some(1.0),
+        "ft3" | "ft3compensated" => Some(M3_PER_FT3),
+        _ => None,
+    };
+    if let (true, Some(m3), Some(kwh_per_m3)) = (gas, volume_m3, options.gas_kwh_per_m3) {
+        return Some(("kWh", m3 * kwh_per_m3));
+    }
+    return match uom {
+        "Wh" if kind == "energy" => Some(match options.electricity_unit {
+            HomeAssistantEnergyUnit::Wh => ("Wh", 1.0),
+            HomeAssistantEnergyUnit::KWh => ("kWh", 1e-3),
+            HomeAssistantEnergyUnit::MWh => ("MWh", 1e-6),
+        }),
+        "therm" => Some(("kWh", KWH_PER_THERM)),
+        "m3" | "m3compensated" | "m3uncompensated" => Some(("m³", 1.0)),
+        "ft3" | "ft3compensated" => Some(("ft³", 1.0)),
+        "litre" | "litreCompensated" | "litreUncompensated" => Some(("L", 1.0)),
+        "usGal" => Some(("gal", 1.0)),
+        _ => None,
+    };
+}
+
+struct Statistic {
+    statistic_id: String,
+    name: String,
+    unit: &'static str,
+    // Hour start -> total for the hour.
+    hours: BTreeMap<i64, f64>,
+}
+
+impl TimeSeries {
+    fn home_assistant_statistics(&self, options: &HomeAssistantOptions) -> Vec<Statistic> {
+        let mut statistics: Vec<Statistic> = vec![];
+        for i in 0..self.value.len() {
+            if is_cumulative(self.accumulation_behaviour[i]) || !self.value[i].is_finite() {
+                continue;
+            }
+            let suffix = match self.flow_direction[i] {
+                "forward" => "",
+                "reverse" => "_return",
+                _ => continue,
+            };
+            let Some((unit, factor)) =
+                home_assistant_unit(self.commodity[i], self.kind[i], self.uom[i], options)
+            else {
+                continue;
+            };
+            let statistic_id = format!(
+                "{}:{}_{}{}",
+                metric_name_part(&options.source),
+                metric_name_part(&self.title[i]),
+                metric_name_part(self.commodity[i]),
+                suffix
+            );
+            let index = match statistics
+                .iter()
+                .position(|x| x.statistic_id == statistic_id && x.unit == unit)
+            {
+                Some(x) => x,
+                None => {
+                    statistics.push(Statistic {
+                        statistic_id,
+                        name: format!(
+                            "{} {}{}",
+                            self.title[i],
+                            self.commodity[i],
+                            suffix.replace('_', " ")
+                        ),
+                        unit,
+                        hours: BTreeMap::new(),
+                    });
+                    statistics.len() - 1
+                }
+            };
+
+            let value = self.value[i] as f64 * factor;
+            let start = self.time_period_start_unix[i];
+            let duration = self.time_period_duration_seconds[i] as i64;
+            let hours = &mut statistics[index].hours;
+            if duration <= 0 {
+                let hour = start.div_euclid(SECONDS_PER_HOUR) * SECONDS_PER_HOUR;
+                *hours.entry(hour).or_default() += value;
+                continue;
+            }
+            let end = start + duration;
+            let mut hour = start.div_euclid(SECONDS_PER_HOUR) * SECONDS_PER_HOUR;
+            while hour < end {
+                let overlap = end.min(hour + SECONDS_PER_HOUR) - start.max(hour);
+                *hours.entry(hour).or_default() += value * overlap as f64 / duration as f64;
+                hour += SECONDS_PER_HOUR;
+            }
+        }
+        return statistics;
+    }
+
+    pub fn as_home_assistant_json(&self, options: &HomeAssistantOptions) -> Result<String, String> {
+        let to_utc = |local: i64| -> i64 {
+            return match &self.provenance.local_time {
+                Some(local_time) => local_time.to_utc(local),
+                None => local - options.utc_offset_seconds,
+            };
+        };
+        let mut result = vec![];
+        for statistic in self.home_assistant_statistics(options) {
+            let mut sum = 0.0;
+            let mut stats = vec![];
+            for (hour, value) in &statistic.hours {
+                sum += value;
+                let utc = to_utc(*hour);
+                let offset = i32::try_from(hour - utc)
+                    .ok()
+                    .and_then(FixedOffset::east_opt)
+                    .ok_or("Invalid UTC offset")?;
+                let start = DateTime::from_timestamp(utc, 0)
+                    .ok_or("Invalid timestamp")?
+                    .with_timezone(&offset);
+                stats.push(json!({
+                    "start": start.to_rfc3339(),
+                    "state": round6(sum),
+                    "sum": round6(sum),
+                }));
+            }
+            result.push(json!({
+                "metadata": {
+                    "has_mean": false,
+                    "has_sum": true,
+                    "name": statistic.name,
+                    "source": metric_name_part(&options.source),
+                    "statistic_id": statistic.statistic_id,
+                    "unit_of_measurement": statistic.unit,
+                },
+                "stats": stats,
+            }));
+        }
+        return serde_json::to_string_pretty(&result).map_err(|x| x.to_string());
+    }
+
+    pub fn as_home_assistant_csv(&self, options: &HomeAssistantOptions) -> Result<String, String> {
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        wtr.write_record(["statistic_id", "unit", "start", "state", "sum"])
+            .map_err(|x| x.to_string())?;
+        for statistic in self.home_assistant_statistics(options) {
+            let mut sum = 0.0;
+            for (hour, value) in &statistic.hours {
+                sum += value;
+                let start = DateTime::from_timestamp(*hour, 0)
+                    .ok_or("Invalid timestamp")?
+                    .naive_utc();
+                wtr.write_record([
+                    statistic.statistic_id.clone(),
+                    statistic.unit.to_string(),
+                    start.format("%d.%m.%Y %H:%M").to_string(),
+                    round6(sum).to_string(),
+                    round6(sum).to_string(),
+                ])
+                .map_err(|x| x.to_string())?;
+            }
+        }
+        let csv = String::from_utf8(wtr.into_inner().map_err(|x| x.to_string())?).unwrap();
+        return Ok(csv);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HomeAssistantEnergyUnit, HomeAssistantOptions};
+    use crate::test_util::{eastern, evenly_spaced_timeseries};
+
+    #[test]
+    fn spreads_over_hours() {
+        // 2024-01-01 00:30 to 02:30, in two 1 hour readings.
+        let mut ts =
+            evenly_spaced_timeseries("House", "energy", "Wh", 1704069000, 3600, &[1000.0, 2000.0]);
+        let mut power = evenly_spaced_timeseries("House", "power", "W", 1704069000, 3600, &[5.0]);
+        power.kind[0] = "power";
+        ts.extend(power);
+
+        let options = HomeAssistantOptions {
+            utc_offset_seconds: -5 * 3600,
+            ..Default::default()
+        };
+        assert_eq!(
+            ts.as_home_assistant_csv(&options).unwrap(),
+            "statistic_id,unit,start,state,sum
+greenbutton:house_electricity_secondary_metered,kWh,01.01.2024 00:00,0.5,0.5
+greenbutton:house_electricity_secondary_metered,kWh,01.01.2024 01:00,2,2
+greenbutton:house_electricity_secondary_metered,kWh,01.01.2024 02:00,3,3
+"
+        );
+
+        let options = HomeAssistantOptions {
+            electricity_unit: HomeAssistantEnergyUnit::Wh,
+            ..options
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&ts.as_home_assistant_json(&options).unwrap()).unwrap();
+        assert_eq!(json[0]["metadata"]["unit_of_measurement"], "Wh");
+        assert_eq!(json[0]["stats"][1]["start"], "2024-01-01T01:00:00-05:00");
+        assert_eq!(json[0]["stats"][2]["sum"], 3000.0);
+    }
+
+    #[test]
+    fn gas_in_kwh() {
+        let mut ts = evenly_spaced_timeseries("House", "volume", "m3", 0, 2 * 3600, &[2.0]);
+        ts.commodity[0] = "naturalGas";
+        let options = HomeAssistantOptions {
+            gas_kwh_per_m3: Some(10.0),
+            ..Default::default()
+        };
+        let csv = ts.as_home_assistant_csv(&options).unwrap();
+        assert_eq!(
+            csv.lines().nth(2),
+            Some("greenbutton:house_natural_gas,kWh,01.01.1970 01:00,20,20")
+        );
+    }
+
+    #[test]
+    fn forward_and_reverse() {
+        let mut ts = evenly_spaced_timeseries("House", "energy", "Wh", 1704067200, 3600, &[1000.0]);
+        for flow_direction in ["reverse", "net"] {
+            let mut x =
+                evenly_spaced_timeseries("House", "energy", "Wh", 1704067200, 3600, &[400.0]);
+            x.flow_direction[0] = flow_direction;
+            ts.extend(x);
+        }
+        let mut demand =
+            evenly_spaced_timeseries("House", "demand", "Wh", 1704067200, 3600, &[9.0]);
+        demand.kind[0] = "demand";
+        ts.extend(demand);
+
+        let csv = ts
+            .as_home_assistant_csv(&HomeAssistantOptions::default())
+            .unwrap();
+        assert_eq!(
+            csv,
+            "statistic_id,unit,start,state,sum
+greenbutton:house_electricity_secondary_metered,kWh,01.01.2024 00:00,1,1
+greenbutton:house_electricity_secondary_metered_return,kWh,01.01.2024 00:00,0.4,0.4
+"
+        );
+    }
+
+    #[test]
+    fn json_starts_follow_dst() {
+        // 2024-07-01 12:00 and 2024-01-01 12:00, local time.
+        let mut ts = evenly_spaced_timeseries("House", "energy", "Wh", 1719835200, 3600, &[1.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "House",
+            "energy",
+            "Wh",
+            1704110400,
+            3600,
+            &[2.0],
+        ));
+        ts.provenance.local_time = Some(eastern());
+        let json: serde_json::Value = serde_json::from_str(
+            &ts.as_home_assistant_json(&HomeAssistantOptions::default())
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(json[0]["stats"][0]["start"], "2024-01-01T12:00:00-05:00");
+        assert_eq!(json[0]["stats"][1]["start"], "2024-07-01T12:00:00-04:00");
+    }
+}
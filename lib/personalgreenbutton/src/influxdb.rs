@@ -0,0 +1,265 @@
+use std::io::Write;
+
+use regex::Regex;
+
+use crate::{extra_columns::ExtraColumnValues, TimeSeries};
+
+// InfluxDB line protocol, see
+// https://docs.influxdata.com/influxdb/v2/reference/syntax/line-protocol/.
+//
+// Every column other than the timestamp is written either as a tag or as a field, depending on
+// InfluxdbOptions::tags. Strings become quoted string fields, and NaN (or infinite) values and
+// empty strings are omitted. Keys are the as_csv column names, except accumulation_behavior, which
+// keeps the spelling of earlier versions so existing databases keep working.
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfluxdbMeasurement {
+    // Title with spaces replaced by underscores and anything else non-alphanumeric removed.
+    SanitizedTitle,
+    Title,
+    // The same measurement for every reading. The title is then written as a field, unless it's
+    // one of the tags.
+    Fixed(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InfluxdbPrecision {
+    Seconds,
+    Milliseconds,
+    Microseconds,
+    Nanoseconds,
+}
+
+impl InfluxdbPrecision {
+    // The value of the "precision" query parameter of the write API.
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            InfluxdbPrecision::Seconds => "s",
+            InfluxdbPrecision::Milliseconds => "ms",
+            InfluxdbPrecision::Microseconds => "us",
+            InfluxdbPrecision::Nanoseconds => "ns",
+        };
+    }
+
+    fn per_second(&self) -> i64 {
+        return match self {
+            InfluxdbPrecision::Seconds => 1,
+            InfluxdbPrecision::Milliseconds => 1_000,
+            InfluxdbPrecision::Microseconds => 1_000_000,
+            InfluxdbPrecision::Nanoseconds => 1_000_000_000,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InfluxdbOptions {
+    pub measurement: InfluxdbMeasurement,
+    // Keys of the columns written as tags. All other columns are fields.
+    pub tags: Vec<String>,
+    // Constant tags added to every line, e.g. ("db", "greenbutton").
+    pub extra_tags: Vec<(String, String)>,
+    pub precision: InfluxdbPrecision,
+    // Write tou and time_period_duration_seconds as integer fields (with an "i" suffix). Earlier
+    // versions wrote them as floats, and InfluxDB rejects writes which change a field's type.
+    pub integer_fields: bool,
+}
+
+impl Default for InfluxdbOptions {
+    fn default() -> Self {
+        return InfluxdbOptions {
+            measurement: InfluxdbMeasurement::SanitizedTitle,
+            tags: [
+                "accumulation_behavior",
+                "commodity",
+                "currency",
+                "data_qualifier",
+                "flow_direction",
+                "kind",
+                "phase",
+                "uom",
+            ]
+            .iter()
+            .map(|x| x.to_string())
+            .collect(),
+            extra_tags: vec![],
+            precision: InfluxdbPrecision::Nanoseconds,
+            integer_fields: false,
+        };
+    }
+}
+
+enum Value<'a> {
+    Str(&'a str),
+    Float(f32),
+    Int(i64),
+}
+
+impl Value<'_> {
+    fn is_missing(&self) -> bool {
+        return match self {
+            Value::Str(x) => x.is_empty(),
+            Value::Float(x) => !x.is_finite(),
+            Value::Int(_) => false,
+        };
+    }
+}
+
+fn escape_measurement(x: &str) -> String {
+    return x.replace(',', "\\,").replace(' ', "\\ ");
+}
+
+// Tag keys, tag values and field keys.
+fn escape_key(x: &str) -> String {
+    return escape_measurement(x).replace('=', "\\=");
+}
+
+fn escape_string_field(x: &str) -> String {
+    return format!("\"{}\"", x.replace('\\', "\\\\").replace('"', "\\\""));
+}
+
+impl TimeSeries {
+    fn influxdb_columns(&self, i: usize) -> Vec<(&str, Value<'_>)> {
+        let mut columns = vec![
+            ("title", Value::Str(&self.title[i])),
+            ("cost", Value::Float(self.cost[i])),
+            ("quality", Value::Str(self.quality[i])),
+            ("value", Value::Float(self.value[i])),
+            ("tou", Value::Int(self.tou[i] as i64)),
+            (
+                "time_period_duration_seconds",
+                Value::Int(self.time_period_duration_seconds[i] as i64),
+            ),
+            (
+                "accumulation_behavior",
+                Value::Str(self.accumulation_behaviour[i]),
+            ),
+            ("commodity", Value::Str(self.commodity[i])),
+            ("currency", Value::Str(self.currency[i])),
+            ("data_qualifier", Value::Str(self.data_qualifier[i])),
+            ("flow_direction", Value::Str(self.flow_direction[i])),
+            ("kind", Value::Str(self.kind[i])),
+            ("phase", Value::Str(self.phase[i])),
+            ("uom", Value::Str(self.uom[i])),
+        ];
+        for column in &self.extra_columns {
+            columns.push((
+                &column.name,
+                match &column.values {
+                    ExtraColumnValues::F32(x) => Value::Float(x[i]),
+                    ExtraColumnValues::Str(x) => Value::Str(&x[i]),
+                },
+            ));
+        }
+        return columns;
+    }
+
+    pub fn as_influxdb_with_options(&self, options: &InfluxdbOptions) -> String {
+        let mut buf: Vec<u8> = vec![];
+        // Writing to a Vec can't fail.
+        self.write_influxdb(&mut buf, options).unwrap();
+        return String::from_utf8(buf).unwrap();
+    }
+
+    /// Streams line protocol, one line at a time. Lines without any fields are skipped, as
+    /// they aren't valid line protocol.
+    pub fn write_influxdb<W: Write>(
+        &self,
+        mut w: W,
+        options: &InfluxdbOptions,
+    ) -> Result<(), String> {
+        let special_chars = Regex::new(r"[^A-Za-z0-9_]").unwrap();
+
+        for i in 0..self.value.len() {
+            let measurement = match &options.measurement {
+                InfluxdbMeasurement::SanitizedTitle => special_chars
+                    .replace_all(&self.title[i].replace(' ', "_"), "")
+                    .to_string(),
+                InfluxdbMeasurement::Title => escape_measurement(&self.title[i]),
+                InfluxdbMeasurement::Fixed(x) => escape_measurement(x),
+            };
+            let title_is_measurement =
+                !matches!(options.measurement, InfluxdbMeasurement::Fixed(_));
+
+            let mut tags: Vec<(String, String)> = options
+                .extra_tags
+                .iter()
+                .map(|(key, value)| (escape_key(key), escape_key(value)))
+                .collect();
+            let mut fields = vec![];
+            for (name, value) in self.influxdb_columns(i) {
+                if value.is_missing() {
+                    continue;
+                }
+                if options.tags.iter().any(|x| x == name) {
+                    let value = match value {
+                        Value::Str(x) => x.to_string(),
+                        Value::Float(x) => x.to_string(),
+                        Value::Int(x) => x.to_string(),
+                    };
+                    tags.push((escape_key(name), escape_key(&value)));
+                    continue;
+                }
+                if name == "title" && title_is_measurement {
+                    continue;
+                }
+                let value = match value {
+                    Value::Str(x) => escape_string_field(x),
+                    Value::Float(x) => x.to_string(),
+                    Value::Int(x) if options.integer_fields => format!("{x}i"),
+                    Value::Int(x) => x.to_string(),
+                };
+                fields.push(format!("{}={}", escape_key(name), value));
+            }
+            if fields.is_empty() {
+                continue;
+            }
+            // Sorted tags are faster to ingest.
+            tags.sort();
+
+            let mut line = measurement;
+            for (key, value) in tags {
+                line += &format!(",{key}={value}");
+            }
+            let time = self.time_period_start_unix[i] * options.precision.per_second();
+            writeln!(w, "{} {} {}", line, fields.join(","), time).map_err(|x| x.to_string())?;
+        }
+        w.flush().map_err(|x| x.to_string())?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{InfluxdbMeasurement, InfluxdbOptions, InfluxdbPrecision};
+    use crate::test_util::evenly_spaced_timeseries;
+
+    #[test]
+    fn default_options() {
+        let mut ts = evenly_spaced_timeseries("My house, 1", "energy", "Wh", 60, 3600, &[1.5]);
+        ts.quality[0] = "estimated using \"reference\" day";
+        assert_eq!(
+            ts.as_influxdb(),
+            "My_house_1,accumulation_behavior=deltaData,commodity=electricity\\ SecondaryMetered,\
+currency=CAD,data_qualifier=normal,flow_direction=forward,kind=energy,phase=none,uom=Wh \
+quality=\"estimated using \\\"reference\\\" day\",value=1.5,tou=0,\
+time_period_duration_seconds=3600 60000000000\n"
+        );
+    }
+
+    #[test]
+    fn fixed_measurement_and_precision() {
+        let mut ts = evenly_spaced_timeseries("a=b", "energy", "Wh", 60, 3600, &[1.5]);
+        ts.cost[0] = 0.25;
+        let options = InfluxdbOptions {
+            measurement: InfluxdbMeasurement::Fixed("energy usage".to_string()),
+            tags: vec!["title".to_string()],
+            extra_tags: vec![("db".to_string(), "greenbutton".to_string())],
+            precision: InfluxdbPrecision::Seconds,
+            integer_fields: true,
+        };
+        let line = ts.as_influxdb_with_options(&options);
+        assert!(line.starts_with("energy\\ usage,db=greenbutton,title=a\\=b cost=0.25,"));
+        assert!(line.contains(",tou=0i,time_period_duration_seconds=3600i,"));
+        assert!(line.contains(",uom=\"Wh\" 60\n"));
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use chrono::DateTime;
+use serde_json::{json, Map, Value};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    extra_columns::ExtraColumnValues,
+    find_gb_type_value,
+    reading_type::{ESPI_FIELDS, READING_TYPE_FIELDS},
+    stats::f32_to_f64,
+    TimeSeries,
+};
+
+// JSON exports. Both shapes share the same field names as as_csv, except that
+// time_period_start_unix is replaced by time_period_start, an ISO 8601 local time without an
+// offset (timestamps are already shifted to local time during parsing), and each reading has a
+// series_id.
+//
+// Series are the distinct (title, reading type) combinations. Each series object holds the
+// title and, for every ReadingType field, {"value", "code", "description"}, where value is the
+// string used in the reading columns and code and description come from the ESPI schema.
+//
+// Column oriented (as_json):
+//   {"series": [<series>, ...], "readings": {"series_id": [...], "title": [...], ...}}
+// NDJSON (as_ndjson), one object per line:
+//   {"record_type": "series", "series_id": 0, ...<series>}, for each series, followed by
+//   {"record_type": "reading", "series_id": 0, "title": ..., ...}, for each reading.
+// NaN values (e.g. missing cost) are written as null.
+
+fn iso_8601(unix: i64) -> Result<String, String> {
+    return Ok(DateTime::from_timestamp(unix, 0)
+        .ok_or(format!("Invalid timestamp {}", unix))?
+        .naive_utc()
+        .format("%Y-%m-%dT%H:%M:%S")
+        .to_string());
+}
+
+fn f32_json(x: f32) -> Value {
+    return json!(Some(x).filter(|x| x.is_finite()).map(f32_to_f64));
+}
+
+fn field_metadata(espi_field: &str, value: &str) -> Value {
+    return match find_gb_type_value("ReadingType", espi_field, value) {
+        Some((code, details)) => json!({
+            "value": value,
+            "code": code,
+            "description": details.description,
+        }),
+        None => json!({ "value": value, "code": null, "description": null }),
+    };
+}
+
+impl TimeSeries {
+    /// Returns the series objects and the series_id of each reading.
+    fn json_series(&self) -> (Vec<Map<String, Value>>, Vec<usize>) {
+        let mut ids = HashMap::<(&str, [&str; 8]), usize>::new();
+        let mut series = vec![];
+        let mut series_ids = vec![];
+        for i in 0..self.value.len() {
+            let reading_type = self.reading_type_values(i);
+            let id = *ids
+                .entry((&self.title[i], reading_type))
+                .or_insert_with(|| {
+                    let mut metadata = Map::new();
+                    metadata.insert("title".to_string(), json!(self.title[i]));
+                    for (field, (espi_field, value)) in READING_TYPE_FIELDS
+                        .iter()
+                        .zip(ESPI_FIELDS.iter().zip(reading_type))
+                    {
+                        metadata.insert(field.to_string(), field_metadata(espi_field, value));
+                    }
+                    series.push(metadata);
+                    series.len() - 1
+                });
+            series_ids.push(id);
+        }
+        return (series, series_ids);
+    }
+
+    fn json_reading(&self, i: usize, series_id: usize) -> Result<Map<String, Value>, String> {
+        let mut reading = Map::new();
+        reading.insert("series_id".to_string(), json!(series_id));
+        reading.insert("title".to_string(), json!(self.title[i]));
+        reading.insert("cost".to_string(), f32_json(self.cost[i]));
+        reading.insert("quality".to_string(), json!(self.quality[i]));
+        reading.insert("value".to_string(), f32_json(self.value[i]));
+        reading.insert("tou".to_string(), json!(self.tou[i]));
+        reading.insert(
+            "time_period_start".to_string(),
+            json!(iso_8601(self.time_period_start_unix[i])?),
+        );
+        reading.insert(
+            "time_period_duration_seconds".to_string(),
+            json!(self.time_period_duration_seconds[i]),
+        );
+        for (field, value) in READING_TYPE_FIELDS.iter().zip(self.reading_type_values(i)) {
+            reading.insert(field.to_string(), json!(value));
+        }
+        for column in &self.extra_columns {
+            let value = match &column.values {
+                ExtraColumnValues::F32(x) => f32_json(x[i]),
+                ExtraColumnValues::Str(x) => json!(x[i]),
+            };
+            reading.insert(column.name.to_string(), value);
+        }
+        return Ok(reading);
+    }
+}
+
+#[wasm_bindgen]
+impl TimeSeries {
+    /// Column oriented JSON, mirroring the struct of vecs layout.
+    #[wasm_bindgen(js_name = "asJSON")]
+    pub fn as_json(&self) -> Result<String, String> {
+        let (series, series_ids) = self.json_series();
+        let mut readings = Map::<String, Value>::new();
+        for (i, series_id) in series_ids.into_iter().enumerate() {
+            for (key, value) in self.json_reading(i, series_id)? {
+                match readings.entry(key).or_insert_with(|| json!([])) {
+                    Value::Array(column) => column.push(value),
+                    _ => unreachable!(),
+                }
+            }
+        }
+        let result = json!({ "series": series, "readings": readings });
+        return serde_json::to_string(&result).map_err(|x| x.to_string());
+    }
+
+    /// Newline delimited JSON, with the series records first.
+    #[wasm_bindgen(js_name = "asNDJSON")]
+    pub fn as_ndjson(&self) -> Result<String, String> {
+        let (series, series_ids) = self.json_series();
+        let mut result = String::new();
+        for (series_id, metadata) in series.into_iter().enumerate() {
+            let mut record = Map::new();
+            record.insert("record_type".to_string(), json!("series"));
+            record.insert("series_id".to_string(), json!(series_id));
+            record.extend(metadata);
+            result += &serde_json::to_string(&record).map_err(|x| x.to_string())?;
+            result += "\n";
+        }
+        for (i, series_id) in series_ids.into_iter().enumerate() {
+            let mut record = Map::new();
+            record.insert("record_type".to_string(), json!("reading"));
+            record.extend(self.json_reading(i, series_id)?);
+            result += &serde_json::to_string(&record).map_err(|x| x.to_string())?;
+            result += "\n";
+        }
+        return Ok(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::test_util::evenly_spaced_timeseries;
+
+    #[test]
+    fn column_oriented() {
+        // 2024-01-01 00:00:00.
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 1704067200, 3600, &[1.0, 2.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "b",
+            "energy",
+            "Wh",
+            0,
+            3600,
+            &[3.0],
+        ));
+        let json: Value = serde_json::from_str(&ts.as_json().unwrap()).unwrap();
+
+        assert_eq!(json["series"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            json["series"][0]["uom"],
+            json!({"value": "Wh", "code": 72, "description": "Real energy, Watt hours, Wh"})
+        );
+        assert_eq!(json["readings"]["series_id"], json!([0, 0, 1]));
+        assert_eq!(json["readings"]["value"], json!([1.0, 2.0, 3.0]));
+        assert_eq!(json["readings"]["cost"], json!([null, null, null]));
+        assert_eq!(
+            json["readings"]["time_period_start"][1],
+            json!("2024-01-01T01:00:00")
+        );
+    }
+
+    #[test]
+    fn ndjson() {
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0, 2.0]);
+        let lines: Vec<Value> = ts
+            .as_ndjson()
+            .unwrap()
+            .lines()
+            .map(|x| serde_json::from_str(x).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["record_type"], json!("series"));
+        assert_eq!(lines[0]["kind"]["description"], json!("Energy"));
+        assert_eq!(lines[2]["record_type"], json!("reading"));
+        assert_eq!(lines[2]["series_id"], json!(0));
+        assert_eq!(lines[2]["value"], json!(2.0));
+    }
+}
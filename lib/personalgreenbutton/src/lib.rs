@@ -5,29 +5,77 @@ use std::collections::HashMap;
 use anyhow::{anyhow, Ok, Result};
 use chrono::{DateTime, Datelike, NaiveDateTime, TimeDelta};
 use entry::parse_entry;
-use local_time_parameters::{
-    get_date_from_dst_rule, LocalTimeParameters, LocalTimeParametersSingle,
-};
+use local_time_parameters::{get_date_from_dst_rule, in_dst, LocalTimeParameters};
 use parse_helpers::enums_to_strings;
 use roxmltree::Document;
 
+mod anomalies;
+mod arrow_ipc;
+mod baseload;
 mod content;
+mod data_package;
 mod entry;
+mod espi;
+mod extra_columns;
 mod gb_type_details;
+mod green_button_csv;
+mod home_assistant;
+mod influxdb;
 mod interval_reading;
+mod json;
+mod load_profile;
 mod local_time_parameters;
+mod openmetrics;
 mod parquet_column_writers;
+mod parquet_dataset;
+mod parquet_options;
 mod parse_helpers;
+mod peak_demand;
+mod periods;
+mod portfolio_manager;
+mod provenance;
+mod readback;
 mod reading_type;
+mod sql_script;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
 mod time_period;
 mod timeseries;
+mod weather;
+mod wide_csv;
+mod xlsx;
+
+#[cfg(test)]
+mod test_util;
 
+pub use crate::anomalies::{Anomaly, AnomalyOptions, AnomalyReason};
+pub use crate::baseload::{BaseloadEstimate, BaseloadOptions, BaseloadReport, BaseloadTrend};
 pub use crate::entry::Entries;
+pub use crate::extra_columns::{ExtraColumn, ExtraColumnValues};
+pub use crate::home_assistant::{HomeAssistantEnergyUnit, HomeAssistantOptions};
+pub use crate::influxdb::{InfluxdbMeasurement, InfluxdbOptions, InfluxdbPrecision};
 pub use crate::interval_reading::IntervalReadings;
-pub use crate::reading_type::ReadingTypes;
+pub use crate::load_profile::{LoadProfile, LoadProfileColumns, LoadProfileStatistic};
+pub use crate::local_time_parameters::LocalTimeParametersSingle;
+pub use crate::openmetrics::OpenMetricsOptions;
+pub use crate::parquet_options::{ParquetCompression, ParquetOptions};
+pub use crate::peak_demand::{coincident_peaks, CoincidentPeak, Peak};
+pub use crate::periods::Period;
+pub use crate::provenance::Provenance;
+pub use crate::reading_type::{is_cumulative, ReadingTypes, READING_TYPE_FIELDS};
+pub use crate::sql_script::{SqlDialect, SqlLoadStatement, SqlScriptOptions};
+pub use crate::stats::{f32_to_f64, round6};
 pub use crate::timeseries::TimeSeries;
+pub use crate::weather::{
+    parse_weather_csv, DegreeDay, DegreeDayOptions, WeatherCsvOptions, WeatherObservations,
+};
 
-pub use gb_type_details::get_gb_type_details;
+pub use crate::green_button_csv::{
+    parse_green_button_csv, GreenButtonCsvDialect, GreenButtonCsvOptions,
+};
+pub use crate::wide_csv::{WideCsvFill, WideCsvOptions};
+pub use gb_type_details::{find_gb_type_value, get_gb_type_details};
 
 pub fn denormalize_and_link(
     entries: &Entries,
@@ -154,10 +202,8 @@ pub fn denormalize_and_link(
                     None
                 });
         }
-        if let (Some(last_dst_start), Some(last_dst_end)) = (last_dst_start, last_dst_end) {
-            if last_dst_start < date_time && date_time < last_dst_end {
-                date_time += local_time_parameters.dst_offset;
-            }
+        if in_dst(date_time, last_dst_start, last_dst_end) {
+            date_time += local_time_parameters.dst_offset;
         }
         date_time += local_time_parameters.tz_offset;
 
@@ -184,6 +230,7 @@ pub fn denormalize_and_link(
         timeseries.uom.push(uom[rt_index]);
     }
 
+    timeseries.provenance.local_time = Some(local_time_parameters);
     timeseries.fix_provider_bugs_if_needed(&entries.href[0]);
 
     return Ok(timeseries);
@@ -231,3 +278,15 @@ pub fn parse_xml<'a, 'b>(xml: &str) -> Result<TimeSeries> {
     )?;
     return Ok(timeseries);
 }
+
+/// Parses Green Button XML, a utility's Green Button CSV export, or our own as_csv output.
+pub fn parse_green_button(s: &str) -> Result<TimeSeries> {
+    let trimmed = s.trim_start_matches('\u{feff}').trim_start();
+    if trimmed.starts_with('<') {
+        return parse_xml(s);
+    }
+    if trimmed.starts_with("title,cost,quality,value,") {
+        return TimeSeries::from_csv(trimmed);
+    }
+    return parse_green_button_csv(s);
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, Timelike};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    stats::{mean, median, percentile},
+    TimeSeries,
+};
+
+// Load profiles bin readings by local hour of day (rows) against a calendar
+// grouping (columns), which is the shape heatmaps want.
+
+pub const HOURS_PER_DAY: usize = 24;
+
+const WEEKDAY_LABELS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+const MONTH_LABELS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadProfileColumns {
+    Weekday,
+    Month,
+    Date,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoadProfileStatistic {
+    Mean,
+    Median,
+    P95,
+    Sum,
+}
+
+#[wasm_bindgen]
+#[derive(Debug, Default, Clone)]
+pub struct LoadProfile {
+    #[wasm_bindgen(skip)]
+    pub titles: Vec<String>,
+    #[wasm_bindgen(skip)]
+    pub column_labels: Vec<String>,
+    // Indexed by [title][hour][column], NaN where there are no readings.
+    #[wasm_bindgen(skip)]
+    pub values: Vec<f32>,
+}
+
+impl LoadProfile {
+    pub fn get(&self, title_index: usize, hour: usize, column: usize) -> f32 {
+        let columns = self.column_labels.len();
+        return self.values[(title_index * HOURS_PER_DAY + hour) * columns + column];
+    }
+
+    pub fn as_csv(&self) -> Result<String, String> {
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        let mut header = vec!["title".to_string(), "hour".to_string()];
+        header.extend(self.column_labels.iter().cloned());
+        wtr.write_record(&header).map_err(|x| x.to_string())?;
+
+        for (title_index, title) in self.titles.iter().enumerate() {
+            for hour in 0..HOURS_PER_DAY {
+                let mut record = vec![title.to_string(), hour.to_string()];
+                record.extend(
+                    (0..self.column_labels.len())
+                        .map(|column| self.get(title_index, hour, column).to_string()),
+                );
+                wtr.write_record(&record).map_err(|x| x.to_string())?;
+            }
+        }
+        let csv = String::from_utf8(wtr.into_inner().map_err(|x| x.to_string())?).unwrap();
+        return Ok(csv);
+    }
+}
+
+fn local_datetime(unix: i64) -> NaiveDateTime {
+    return DateTime::from_timestamp(unix, 0).unwrap().naive_utc();
+}
+
+impl TimeSeries {
+    /// Bins values by local hour of day against the requested columns, separately for each title.
+    /// Readings are binned by their start time, so this is most useful for hourly or finer data.
+    pub fn load_profile(
+        &self,
+        columns: LoadProfileColumns,
+        statistic: LoadProfileStatistic,
+    ) -> LoadProfile {
+        let titles: BTreeSet<&str> = self.title.iter().map(|x| x.as_str()).collect();
+        let title_indices: BTreeMap<&str, usize> = titles
+            .iter()
+            .enumerate()
+            .map(|(i, title)| (*title, i))
+            .collect();
+
+        let dates: BTreeSet<NaiveDate> = match columns {
+            LoadProfileColumns::Date => self
+                .time_period_start_unix
+                .iter()
+                .map(|x| local_datetime(*x).date())
+                .collect(),
+            _ => BTreeSet::new(),
+        };
+        let column_labels: Vec<String> = match columns {
+            LoadProfileColumns::Weekday => WEEKDAY_LABELS.iter().map(|x| x.to_string()).collect(),
+            LoadProfileColumns::Month => MONTH_LABELS.iter().map(|x| x.to_string()).collect(),
+            LoadProfileColumns::Date => dates.iter().map(|x| x.to_string()).collect(),
+        };
+        let date_indices: BTreeMap<NaiveDate, usize> = dates
+            .into_iter()
+            .enumerate()
+            .map(|(i, date)| (date, i))
+            .collect();
+
+        let column_count = column_labels.len();
+        let mut bins: Vec<Vec<f32>> = vec![vec![]; titles.len() * HOURS_PER_DAY * column_count];
+        for i in 0..self.value.len() {
+            let datetime = local_datetime(self.time_period_start_unix[i]);
+            let column = match columns {
+                LoadProfileColumns::Weekday => datetime.weekday().num_days_from_monday() as usize,
+                LoadProfileColumns::Month => datetime.month0() as usize,
+                LoadProfileColumns::Date => date_indices[&datetime.date()],
+            };
+            let title_index = title_indices[self.title[i].as_str()];
+            let hour = datetime.hour() as usize;
+            bins[(title_index * HOURS_PER_DAY + hour) * column_count + column].push(self.value[i]);
+        }
+
+        let values = bins
+            .into_iter()
+            .map(|mut bin| match statistic {
+                _ if bin.is_empty() => f32::NAN,
+                LoadProfileStatistic::Mean => mean(&bin),
+                LoadProfileStatistic::Median => median(&mut bin),
+                LoadProfileStatistic::P95 => percentile(&mut bin, 95.0),
+                LoadProfileStatistic::Sum => bin.iter().sum(),
+            })
+            .collect();
+
+        return LoadProfile {
+            titles: titles.into_iter().map(|x| x.to_string()).collect(),
+            column_labels,
+            values,
+        };
+    }
+}
+
+#[wasm_bindgen]
+impl TimeSeries {
+    #[wasm_bindgen(js_name = "loadProfile")]
+    pub fn load_profile_js(
+        &self,
+        columns: LoadProfileColumns,
+        statistic: LoadProfileStatistic,
+    ) -> LoadProfile {
+        return self.load_profile(columns, statistic);
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen]
+impl LoadProfile {
+    #[wasm_bindgen(getter)]
+    pub fn titles(&self) -> Vec<String> {
+        return self.titles.clone();
+    }
+    #[wasm_bindgen(getter)]
+    pub fn column_labels(&self) -> Vec<String> {
+        return self.column_labels.clone();
+    }
+    #[wasm_bindgen(getter)]
+    pub fn hours(&self) -> usize {
+        return HOURS_PER_DAY;
+    }
+    // Exposed as a Float32Array, indexed by [title][hour][column].
+    #[wasm_bindgen(getter)]
+    pub fn values(&self) -> Vec<f32> {
+        return self.values.clone();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoadProfileColumns, LoadProfileStatistic};
+    use crate::test_util::evenly_spaced_timeseries;
+
+    // Monday 2024-01-01 00:00:00.
+    const MONDAY: i64 = 1704067200;
+
+    #[test]
+    fn weekday_profile() {
+        // Two days of hourly readings, where each reading is the hour of day + the day.
+        let values: Vec<f32> = (0..48).map(|i| (i % 24 + i / 24) as f32).collect();
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", MONDAY, 3600, &values);
+        let profile = ts.load_profile(LoadProfileColumns::Weekday, LoadProfileStatistic::Sum);
+        assert_eq!(profile.titles, vec!["a"]);
+        assert_eq!(profile.column_labels.len(), 7);
+        assert_eq!(profile.get(0, 5, 0), 5.0);
+        assert_eq!(profile.get(0, 5, 1), 6.0);
+        assert!(profile.get(0, 5, 2).is_nan());
+    }
+
+    #[test]
+    fn date_profile_mean() {
+        // Two 30 minute readings per hour.
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", MONDAY, 1800, &[1.0, 3.0, 5.0]);
+        let profile = ts.load_profile(LoadProfileColumns::Date, LoadProfileStatistic::Mean);
+        assert_eq!(profile.column_labels, vec!["2024-01-01"]);
+        assert_eq!(profile.get(0, 0, 0), 2.0);
+        assert_eq!(profile.get(0, 1, 0), 5.0);
+
+        let csv = profile.as_csv().unwrap();
+        assert!(csv.starts_with("title,hour,2024-01-01\na,0,2\na,1,5\n"));
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::DateTime;
 use chrono::Datelike;
 use chrono::Days;
 use chrono::NaiveDate;
@@ -21,6 +22,8 @@ pub struct LocalTimeParameters {
     pub tz_offset: i64,
 }
 
+/// The feed's time zone and DST rules, used to convert the stored local times to and from UTC.
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct LocalTimeParametersSingle {
     pub dst_start_rule: u32,
     pub dst_end_rule: u32,
@@ -28,6 +31,67 @@ pub struct LocalTimeParametersSingle {
     pub tz_offset: TimeDelta,
 }
 
+// Whether a UTC time is in DST, given the year's DST start and end.
+pub fn in_dst(
+    utc: NaiveDateTime,
+    dst_start: Option<NaiveDateTime>,
+    dst_end: Option<NaiveDateTime>,
+) -> bool {
+    if let (Some(dst_start), Some(dst_end)) = (dst_start, dst_end) {
+        return dst_start < utc && utc < dst_end;
+    }
+    return false;
+}
+
+impl LocalTimeParametersSingle {
+    // Invalid rules mean no DST, as in denormalize_and_link.
+    fn dst_bounds(&self, year: i32) -> (Option<NaiveDateTime>, Option<NaiveDateTime>) {
+        return (
+            get_date_from_dst_rule(self.dst_start_rule, year).unwrap_or(None),
+            get_date_from_dst_rule(self.dst_end_rule, year).unwrap_or(None),
+        );
+    }
+
+    fn utc_in_dst(&self, utc: NaiveDateTime) -> bool {
+        let (dst_start, dst_end) = self.dst_bounds(utc.year());
+        return in_dst(utc, dst_start, dst_end);
+    }
+
+    /// Local time of a UTC time, both as seconds since the epoch, as denormalize_and_link
+    /// computes time_period_start_unix.
+    pub fn to_local(&self, utc_unix: i64) -> i64 {
+        let Some(utc) = DateTime::from_timestamp(utc_unix, 0) else {
+            return utc_unix;
+        };
+        let mut local = utc_unix + self.tz_offset.num_seconds();
+        if self.utc_in_dst(utc.naive_utc()) {
+            local += self.dst_offset.num_seconds();
+        }
+        return local;
+    }
+
+    /// UTC time of a local time. Local times in the hour repeated when DST ends are taken to be
+    /// the first, DST, one.
+    pub fn to_utc(&self, local_unix: i64) -> i64 {
+        let standard = local_unix - self.tz_offset.num_seconds();
+        let dst = standard - self.dst_offset.num_seconds();
+        if self.to_local(dst) == local_unix {
+            return dst;
+        }
+        return standard;
+    }
+
+    /// Whether a local time is in the hour repeated when DST ends, so two readings can share it.
+    pub fn is_repeated(&self, local_unix: i64) -> bool {
+        let standard = local_unix - self.tz_offset.num_seconds();
+        let dst = standard - self.dst_offset.num_seconds();
+        return self.dst_offset.num_seconds() > 0
+            && dst != standard
+            && self.to_local(dst) == local_unix
+            && self.to_local(standard) == local_unix;
+    }
+}
+
 /*
 The operators:
 
@@ -181,6 +245,31 @@ mod tests {
     use crate::local_time_parameters::{get_date, get_datetime};
 
     use super::get_date_from_dst_rule;
+    use crate::test_util::eastern;
+
+    #[test]
+    fn local_and_utc() {
+        let local_time = eastern();
+        // 2024-07-01 16:00 UTC is 12:00 EDT.
+        assert_eq!(local_time.to_local(1719849600), 1719849600 - 4 * 3600);
+        assert_eq!(local_time.to_utc(1719849600 - 4 * 3600), 1719849600);
+        // 2024-01-01 17:00 UTC is 12:00 EST.
+        assert_eq!(local_time.to_local(1704128400), 1704128400 - 5 * 3600);
+        assert_eq!(local_time.to_utc(1704128400 - 5 * 3600), 1704128400);
+
+        // The rule times are compared to UTC, as in denormalize_and_link, so the hour before
+        // the end of DST and the hour after it have the same local time.
+        let dst_end = get_date_from_dst_rule(local_time.dst_end_rule, 2024)
+            .unwrap()
+            .unwrap()
+            .and_utc()
+            .timestamp();
+        let repeated = local_time.to_local(dst_end - 3600);
+        assert_eq!(repeated, local_time.to_local(dst_end));
+        assert!(local_time.is_repeated(repeated));
+        assert!(!local_time.is_repeated(repeated - 3600));
+        assert_eq!(local_time.to_utc(repeated), dst_end - 3600);
+    }
 
     // 0: DST starts/ends on the Day of the Month
     #[test]
@@ -0,0 +1,246 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::{
+    reading_type::{is_cumulative, READING_TYPE_FIELDS},
+    TimeSeries,
+};
+
+// OpenMetrics text with timestamps, for backfilling Prometheus with
+// `promtool tsdb create-blocks-from openmetrics`, see
+// https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md.
+//
+// There's a metric family per (commodity, kind, uom), named e.g.
+// greenbutton_electricity_secondary_metered_energy_wh. Interval readings are gauges. Cumulative
+// registers are counters, with "register" before the unit so the two can't collide, e.g.
+// greenbutton_natural_gas_volume_register_m3_total. Each (title, reading type) is a series,
+// labelled with the title and the reading type columns.
+//
+// Timestamps are the UTC start of each reading, in seconds since the epoch, when the local time
+// parameters are known, so both readings of the hour repeated when DST ends are kept. Otherwise
+// they're time_period_start_unix, which is local time (like the InfluxDB output). Samples are
+// grouped by family and series and sorted by time, as the format requires. NaN values are
+// skipped, as are repeated timestamps within a series.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpenMetricsOptions {
+    // First part of every metric name.
+    pub prefix: String,
+}
+
+impl Default for OpenMetricsOptions {
+    fn default() -> Self {
+        return OpenMetricsOptions {
+            prefix: "greenbutton".to_string(),
+        };
+    }
+}
+
+// Lower snake case, e.g. "electricity SecondaryMetered" -> "electricity_secondary_metered".
+pub(crate) fn metric_name_part(x: &str) -> String {
+    let mut result = String::new();
+    let mut previous: Option<char> = None;
+    for c in x.chars() {
+        if !c.is_ascii_alphanumeric() {
+            result.push('_');
+        } else {
+            if c.is_ascii_uppercase()
+                && previous.is_some_and(|x| x.is_ascii_lowercase() || x.is_ascii_digit())
+            {
+                result.push('_');
+            }
+            result.push(c.to_ascii_lowercase());
+        }
+        previous = Some(c);
+    }
+    return result
+        .split('_')
+        .filter(|x| !x.is_empty())
+        .collect::<Vec<_>>()
+        .join("_");
+}
+
+fn escape_label_value(x: &str) -> String {
+    return x
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+}
+
+struct Family {
+    counter: bool,
+    unit: String,
+    help: String,
+    // Label set -> reading indices.
+    series: BTreeMap<String, Vec<usize>>,
+}
+
+impl TimeSeries {
+    pub fn as_openmetrics_with_options(&self, options: &OpenMetricsOptions) -> String {
+        let mut buf: Vec<u8> = vec![];
+        // Writing to a Vec can't fail.
+        self.write_openmetrics(&mut buf, options).unwrap();
+        return String::from_utf8(buf).unwrap();
+    }
+
+    pub fn write_openmetrics<W: Write>(
+        &self,
+        mut w: W,
+        options: &OpenMetricsOptions,
+    ) -> Result<(), String> {
+        let mut families = BTreeMap::<String, Family>::new();
+        for i in 0..self.value.len() {
+            let counter = is_cumulative(self.accumulation_behaviour[i]);
+            let unit = metric_name_part(self.uom[i]);
+            let mut parts = vec![
+                metric_name_part(&options.prefix),
+                metric_name_part(self.commodity[i]),
+                metric_name_part(self.kind[i]),
+            ];
+            if counter {
+                parts.push("register".to_string());
+            }
+            parts.push(unit.clone());
+            let name = parts
+                .into_iter()
+                .filter(|x| !x.is_empty())
+                .collect::<Vec<_>>()
+                .join("_");
+
+            let mut labels = vec![format!("title=\"{}\"", escape_label_value(&self.title[i]))];
+            for (field, value) in READING_TYPE_FIELDS.iter().zip(self.reading_type_values(i)) {
+                labels.push(format!("{}=\"{}\"", field, escape_label_value(value)));
+            }
+
+            let family = families.entry(name).or_insert_with(|| Family {
+                counter,
+                unit,
+                help: format!(
+                    "Green Button {} of {}, in {}.",
+                    self.kind[i], self.commodity[i], self.uom[i]
+                ),
+                series: BTreeMap::new(),
+            });
+            family.series.entry(labels.join(",")).or_default().push(i);
+        }
+
+        let starts = self
+            .utc_starts()
+            .unwrap_or_else(|| self.time_period_start_unix.clone());
+        let write_error = |x: std::io::Error| x.to_string();
+        for (name, family) in families {
+            let metric_type = if family.counter { "counter" } else { "gauge" };
+            writeln!(w, "# TYPE {} {}", name, metric_type).map_err(write_error)?;
+            if !family.unit.is_empty() {
+                writeln!(w, "# UNIT {} {}", name, family.unit).map_err(write_error)?;
+            }
+            writeln!(w, "# HELP {} {}", name, family.help).map_err(write_error)?;
+            let sample_name = if family.counter {
+                format!("{}_total", name)
+            } else {
+                name
+            };
+            for (labels, mut indices) in family.series {
+                indices.sort_by_key(|i| starts[*i]);
+                let mut previous_time = None;
+                for i in indices {
+                    let time = starts[i];
+                    if !self.value[i].is_finite() || previous_time == Some(time) {
+                        continue;
+                    }
+                    previous_time = Some(time);
+                    writeln!(
+                        w,
+                        "{}{{{}}} {} {}",
+                        sample_name, labels, self.value[i], time
+                    )
+                    .map_err(write_error)?;
+                }
+            }
+        }
+        writeln!(w, "# EOF").map_err(write_error)?;
+        w.flush().map_err(write_error)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{metric_name_part, OpenMetricsOptions};
+    use crate::test_util::{eastern, evenly_spaced_timeseries};
+
+    #[test]
+    fn name_parts() {
+        assert_eq!(
+            metric_name_part("electricity SecondaryMetered"),
+            "electricity_secondary_metered"
+        );
+        assert_eq!(metric_name_part("m3"), "m3");
+        assert_eq!(metric_name_part("VAh"), "vah");
+    }
+
+    #[test]
+    fn counters_and_gauges() {
+        let mut ts = evenly_spaced_timeseries("a \"b\"", "energy", "Wh", 3600, 3600, &[2.0, 1.5]);
+        let mut register =
+            evenly_spaced_timeseries("a \"b\"", "energy", "Wh", 0, 3600, &[10.0, f32::NAN]);
+        register.accumulation_behaviour = vec!["cumulative"; 2];
+        ts.extend(register);
+
+        let labels = "title=\"a \\\"b\\\"\",accumulation_behaviour=\"{}\",\
+commodity=\"electricity SecondaryMetered\",currency=\"CAD\",data_qualifier=\"normal\",\
+flow_direction=\"forward\",kind=\"energy\",phase=\"none\",uom=\"Wh\"";
+        let gauge = "greenbutton_electricity_secondary_metered_energy_wh";
+        let counter = "greenbutton_electricity_secondary_metered_energy_register_wh";
+        let expected = [
+            format!("# TYPE {counter} counter"),
+            format!("# UNIT {counter} wh"),
+            format!("# HELP {counter} Green Button energy of electricity SecondaryMetered, in Wh."),
+            format!(
+                "{counter}_total{{{}}} 10 0",
+                labels.replace("{}", "cumulative")
+            ),
+            format!("# TYPE {gauge} gauge"),
+            format!("# UNIT {gauge} wh"),
+            format!("# HELP {gauge} Green Button energy of electricity SecondaryMetered, in Wh."),
+            format!("{gauge}{{{}}} 2 3600", labels.replace("{}", "deltaData")),
+            format!("{gauge}{{{}}} 1.5 7200", labels.replace("{}", "deltaData")),
+            "# EOF".to_string(),
+        ];
+        assert_eq!(
+            ts.as_openmetrics_with_options(&OpenMetricsOptions::default()),
+            expected.join("\n") + "\n"
+        );
+    }
+
+    #[test]
+    fn utc_timestamps() {
+        let repeated = (1730419200..)
+            .step_by(3600)
+            .find(|x| eastern().is_repeated(*x))
+            .unwrap();
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", repeated, 3600, &[1.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            repeated,
+            3600,
+            &[2.0],
+        ));
+        ts.provenance.local_time = Some(eastern());
+        let openmetrics = ts.as_openmetrics_with_options(&OpenMetricsOptions::default());
+        let samples: Vec<&str> = openmetrics
+            .lines()
+            .filter(|x| !x.starts_with('#'))
+            .map(|x| x.split_once("} ").unwrap().1)
+            .collect();
+        assert_eq!(
+            samples,
+            [
+                format!("1 {}", repeated + 4 * 3600),
+                format!("2 {}", repeated + 5 * 3600)
+            ]
+        );
+    }
+}
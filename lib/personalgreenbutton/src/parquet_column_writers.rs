@@ -69,3 +69,36 @@ pub fn write_f32s<T: std::io::Write + Send>(
 
     return Err("Invalid column type in parquet schema.".to_string());
 }
+
+// A column chunk to be written to a row group.
+pub enum ParquetColumn<'a> {
+    Strs(Vec<&'a str>),
+    F32s(&'a [f32]),
+    I32s(&'a [i32]),
+    TimestampMillis(Vec<i64>),
+}
+
+impl ParquetColumn<'_> {
+    pub fn schema_line(&self, name: &str) -> String {
+        return match self {
+            ParquetColumn::Strs(_) => format!("REQUIRED BYTE_ARRAY {} (STRING);\n", name),
+            ParquetColumn::F32s(_) => format!("REQUIRED FLOAT {};\n", name),
+            ParquetColumn::I32s(_) => format!("REQUIRED INT32 {};\n", name),
+            ParquetColumn::TimestampMillis(_) => {
+                format!("REQUIRED INT64 {} (TIMESTAMP(MILLIS, false));\n", name)
+            }
+        };
+    }
+
+    pub fn write<T: std::io::Write + Send>(
+        &self,
+        row_group_writer: &mut SerializedRowGroupWriter<T>,
+    ) -> Result<usize, String> {
+        return match self {
+            ParquetColumn::Strs(x) => write_strs(row_group_writer, x),
+            ParquetColumn::F32s(x) => write_f32s(row_group_writer, x),
+            ParquetColumn::I32s(x) => write_i32s(row_group_writer, x),
+            ParquetColumn::TimestampMillis(x) => write_i64s(row_group_writer, x),
+        };
+    }
+}
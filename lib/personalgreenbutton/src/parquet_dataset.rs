@@ -0,0 +1,271 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Datelike};
+use parquet::{
+    file::footer::parse_metadata,
+    format::{FileMetaData, RowGroup},
+    schema::types::to_thrift,
+    thrift::TSerializable,
+};
+use thrift::protocol::TCompactOutputProtocol;
+
+use crate::{ParquetOptions, TimeSeries};
+
+// Hive partitioned parquet dataset, readable by DuckDB, Spark, pyarrow, polars, etc:
+//   <dir>/title=<title>/commodity=<commodity>/year=<year>/month=<month>/part-0.parquet
+// Partition columns are encoded in the path rather than stored in the files. Months are by
+// local time, like time_period_start_unix.
+//
+// Writing into an existing dataset merges the new data into the partitions it touches: existing
+// readings with the same title, reading type, tou and start are replaced, and the rest are kept,
+// so a download of part of a month doesn't drop its other days. Afterwards, <dir>/_metadata is
+// rebuilt from the footers of every partition file, so readers can plan a query without opening
+// each file. Files with a different schema than the one just written (e.g. with other extra
+// columns) are left out of _metadata.
+
+const PARTITION_COLUMNS: [&str; 2] = ["title", "commodity"];
+const PART_FILE_NAME: &str = "part-0.parquet";
+
+// Hive's escaping for partition values (FileUtils.escapePathName).
+fn escape_partition_value(x: &str) -> String {
+    let mut result = String::new();
+    for c in x.chars() {
+        if c < ' ' || "\"#%'*/:=?\\\x7f{[]^".contains(c) {
+            result += &format!("%{:02X}", c as u32);
+        } else {
+            result.push(c);
+        }
+    }
+    return result;
+}
+
+fn find_parquet_files(dir: &Path, result: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap().to_string_lossy();
+        // Hidden and summary files, e.g. _metadata.
+        if name.starts_with('_') || name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            find_parquet_files(&path, result)?;
+        } else if name.ends_with(".parquet") {
+            result.push(path);
+        }
+    }
+    return Ok(());
+}
+
+// A footer only parquet file, listing the row groups of all partition files.
+fn write_summary_metadata(dir: &Path, template: Option<FileMetaData>) -> Result<()> {
+    let mut files = vec![];
+    find_parquet_files(dir, &mut files)?;
+    files.sort();
+
+    let mut template = template;
+    let mut row_groups: Vec<RowGroup> = vec![];
+    for path in files {
+        let metadata = parse_metadata(&fs::File::open(&path)?)?;
+        let schema = to_thrift(metadata.file_metadata().schema())?;
+        let template = template.get_or_insert_with(|| {
+            FileMetaData::new(
+                metadata.file_metadata().version(),
+                schema.clone(),
+                0,
+                vec![],
+                None,
+                metadata.file_metadata().created_by().map(|x| x.to_string()),
+                None,
+                None,
+                None,
+            )
+        });
+        if schema != template.schema {
+            continue;
+        }
+        let relative_path = path
+            .strip_prefix(dir)?
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        for row_group in metadata.row_groups() {
+            let mut row_group = row_group.to_thrift();
+            for column in &mut row_group.columns {
+                column.file_path = Some(relative_path.clone());
+            }
+            row_groups.push(row_group);
+        }
+    }
+    let Some(mut metadata) = template else {
+        return Ok(());
+    };
+    metadata.num_rows = row_groups.iter().map(|x| x.num_rows).sum();
+    metadata.row_groups = row_groups;
+
+    let mut footer = vec![];
+    metadata.write_to_out_protocol(&mut TCompactOutputProtocol::new(&mut footer))?;
+    let mut result = b"PAR1".to_vec();
+    result.extend(&footer);
+    result.extend((footer.len() as u32).to_le_bytes());
+    result.extend(b"PAR1");
+    fs::write(dir.join("_metadata"), result)?;
+    return Ok(());
+}
+
+impl TimeSeries {
+    /// Returns the partition files written.
+    pub fn write_parquet_dataset(
+        &self,
+        dir: &Path,
+        options: &ParquetOptions,
+    ) -> Result<Vec<PathBuf>> {
+        let mut partitions = BTreeMap::<(&str, &str, i32, u32), Vec<usize>>::new();
+        for i in 0..self.value.len() {
+            let start = DateTime::from_timestamp(self.time_period_start_unix[i], 0)
+                .ok_or(anyhow!("Invalid timestamp"))?
+                .naive_utc();
+            partitions
+                .entry((
+                    &self.title[i],
+                    self.commodity[i],
+                    start.year(),
+                    start.month(),
+                ))
+                .or_default()
+                .push(i);
+        }
+
+        let mut template = None;
+        let mut written = vec![];
+        for ((title, commodity, year, month), indices) in partitions {
+            let partition_dir = dir
+                .join(format!("title={}", escape_partition_value(title)))
+                .join(format!("commodity={}", escape_partition_value(commodity)))
+                .join(format!("year={}", year))
+                .join(format!("month={}", month));
+            fs::create_dir_all(&partition_dir)?;
+
+            let mut existing = vec![];
+            find_parquet_files(&partition_dir, &mut existing)?;
+            existing.sort();
+            let mut partition = TimeSeries::default();
+            for path in &existing {
+                let old = TimeSeries::from_parquet_partition(
+                    &fs::read(path)?,
+                    &[("title", title), ("commodity", commodity)],
+                )
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+                partition.overlay(old);
+            }
+            partition.overlay(self.select(&indices));
+            let mut order: Vec<usize> = (0..partition.value.len()).collect();
+            order.sort_by_key(|i| partition.time_period_start_unix[*i]);
+
+            // Write next to the old partition, then swap it in.
+            let tmp_path = partition_dir.join(".part-0.parquet.tmp");
+            let metadata = partition.select(&order).write_parquet_file(
+                fs::File::create(&tmp_path)?,
+                options,
+                &PARTITION_COLUMNS,
+            );
+            let metadata = metadata.map_err(|x| anyhow!(x))?;
+            for path in existing {
+                fs::remove_file(path)?;
+            }
+            let path = partition_dir.join(PART_FILE_NAME);
+            fs::rename(&tmp_path, &path)?;
+
+            template.get_or_insert(metadata);
+            written.push(path);
+        }
+
+        write_summary_metadata(dir, template)?;
+        return Ok(written);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use parquet::file::footer::parse_metadata;
+
+    use super::escape_partition_value;
+    use crate::{test_util::evenly_spaced_timeseries, ParquetOptions, TimeSeries};
+
+    #[test]
+    fn escaping() {
+        assert_eq!(escape_partition_value("a b/c=d"), "a b%2Fc%3Dd");
+    }
+
+    #[test]
+    fn partitions_and_replaces() {
+        let dir = std::env::temp_dir().join(format!("parquet_dataset_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let options = ParquetOptions {
+            row_group_size: 10,
+            ..Default::default()
+        };
+
+        // 2024-01-31 00:00 to 2024-02-01 23:00, hourly.
+        let values: Vec<f32> = (0..48).map(|x| x as f32).collect();
+        let mut ts =
+            evenly_spaced_timeseries("My house", "energy", "Wh", 1706659200, 3600, &values);
+        ts.extend(evenly_spaced_timeseries(
+            "Cabin",
+            "energy",
+            "Wh",
+            1706659200,
+            3600,
+            &[1.0],
+        ));
+        let written = ts.write_parquet_dataset(&dir, &options).unwrap();
+        assert_eq!(written.len(), 3);
+        let january = dir
+            .join("title=My house/commodity=electricity SecondaryMetered")
+            .join("year=2024/month=1/part-0.parquet");
+        assert!(written.contains(&january));
+
+        let metadata = parse_metadata(&fs::File::open(&january).unwrap()).unwrap();
+        assert_eq!(metadata.num_row_groups(), 3);
+        assert_eq!(metadata.file_metadata().num_rows(), 24);
+        let schema = metadata.file_metadata().schema_descr();
+        assert!(schema.columns().iter().all(|x| x.name() != "title"));
+
+        let summary = parse_metadata(&fs::File::open(dir.join("_metadata")).unwrap()).unwrap();
+        assert_eq!(summary.file_metadata().num_rows(), 49);
+        assert_eq!(summary.num_row_groups(), 7);
+        let first_column = &summary.row_group(0).columns()[0];
+        assert!(first_column
+            .file_path()
+            .unwrap()
+            .starts_with("title=Cabin/"));
+        assert!(first_column.statistics().is_some());
+
+        // Only part of February is replaced, the rest of the month and January are kept.
+        let february =
+            evenly_spaced_timeseries("My house", "energy", "Wh", 1706745600, 3600, &[5.0, 6.0]);
+        let written = february.write_parquet_dataset(&dir, &options).unwrap();
+        assert_eq!(written.len(), 1);
+        let summary = parse_metadata(&fs::File::open(dir.join("_metadata")).unwrap()).unwrap();
+        assert_eq!(summary.file_metadata().num_rows(), 49);
+
+        let read = TimeSeries::from_parquet_partition(
+            &fs::read(&written[0]).unwrap(),
+            &[
+                ("title", "My house"),
+                ("commodity", "electricity SecondaryMetered"),
+            ],
+        )
+        .unwrap();
+        let expected: Vec<f32> = [5, 6].into_iter().chain(26..48).map(|x| x as f32).collect();
+        assert_eq!(read.value, expected);
+        assert_eq!(read.title[0], "My house");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,142 @@
+use parquet::{
+    basic::Compression, file::properties::WriterProperties, format::KeyValue,
+    schema::types::ColumnPath,
+};
+
+use crate::provenance::Provenance;
+
+// Writer settings shared by as_parquet and write_parquet_dataset. Gzip and zstd are behind the
+// parquet-gzip and parquet-zstd features, so the wasm build only pays for snappy.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParquetCompression {
+    None,
+    Snappy,
+    Gzip,
+    Zstd,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParquetOptions {
+    pub compression: ParquetCompression,
+    // Dictionary encode string columns, which mostly hold a handful of distinct values.
+    // Numeric columns are always plain encoded.
+    pub dictionary: bool,
+    // Maximum rows per row group.
+    pub row_group_size: usize,
+}
+
+impl Default for ParquetOptions {
+    fn default() -> Self {
+        return ParquetOptions {
+            compression: ParquetCompression::Snappy,
+            dictionary: true,
+            row_group_size: 64 * 1024,
+        };
+    }
+}
+
+impl ParquetOptions {
+    fn compression(&self) -> Result<Compression, String> {
+        return match self.compression {
+            ParquetCompression::None => Ok(Compression::UNCOMPRESSED),
+            ParquetCompression::Snappy => Ok(Compression::SNAPPY),
+            #[cfg(feature = "parquet-gzip")]
+            ParquetCompression::Gzip => Ok(Compression::GZIP(Default::default())),
+            #[cfg(feature = "parquet-zstd")]
+            ParquetCompression::Zstd => Ok(Compression::ZSTD(Default::default())),
+            #[allow(unreachable_patterns)]
+            x => Err(format!(
+                "{:?} compression isn't available in this build.",
+                x
+            )),
+        };
+    }
+
+    pub(crate) fn writer_properties(
+        &self,
+        string_columns: &[&str],
+        provenance: &Provenance,
+    ) -> Result<WriterProperties, String> {
+        let mut builder = WriterProperties::builder()
+            .set_compression(self.compression()?)
+            .set_max_row_group_size(self.row_group_size.max(1))
+            .set_dictionary_enabled(false)
+            .set_key_value_metadata(Some(
+                provenance
+                    .key_value_metadata()
+                    .into_iter()
+                    .map(|(key, value)| KeyValue::new(key, value))
+                    .collect(),
+            ));
+        for column in string_columns {
+            builder =
+                builder.set_column_dictionary_enabled(ColumnPath::from(*column), self.dictionary);
+        }
+        return Ok(builder.build());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use parquet::{
+        basic::{Compression, Encoding},
+        file::{footer::decode_metadata, metadata::ParquetMetaData},
+    };
+
+    use super::{ParquetCompression, ParquetOptions};
+    use crate::test_util::evenly_spaced_timeseries;
+
+    fn footer(parquet: &[u8]) -> ParquetMetaData {
+        let len = parquet.len();
+        let footer_len = u32::from_le_bytes(parquet[len - 8..len - 4].try_into().unwrap());
+        return decode_metadata(&parquet[len - 8 - footer_len as usize..len - 8]).unwrap();
+    }
+
+    #[test]
+    fn options_and_provenance() {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0, 2.0, 3.0]);
+        ts.add_source_file("usage.xml");
+        ts.fix_provider_bugs_if_needed("https://enova.example/espi/1_1/resource/Subscription/1");
+
+        let options = ParquetOptions {
+            compression: ParquetCompression::None,
+            dictionary: true,
+            row_group_size: 2,
+        };
+        let metadata = footer(&ts.as_parquet_with_options(&options).unwrap());
+        assert_eq!(metadata.num_row_groups(), 2);
+        let columns = metadata.row_group(0).columns();
+        assert_eq!(columns[0].compression(), Compression::UNCOMPRESSED);
+        // title is dictionary encoded, cost isn't.
+        assert!(columns[0].encodings().contains(&Encoding::RLE_DICTIONARY));
+        assert!(!columns[1].encodings().contains(&Encoding::RLE_DICTIONARY));
+
+        let key_values = metadata.file_metadata().key_value_metadata().unwrap();
+        let get = |key: &str| -> String {
+            return key_values
+                .iter()
+                .find(|x| x.key == key)
+                .unwrap()
+                .value
+                .clone()
+                .unwrap();
+        };
+        assert_eq!(get("personalgreenbutton.source_files"), "[\"usage.xml\"]");
+        assert_eq!(
+            get("personalgreenbutton.quirks"),
+            "[\"enova: cost scaled by 100\"]"
+        );
+    }
+
+    #[test]
+    #[cfg(not(feature = "parquet-zstd"))]
+    fn unavailable_compression() {
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0]);
+        let options = ParquetOptions {
+            compression: ParquetCompression::Zstd,
+            ..Default::default()
+        };
+        assert!(ts.as_parquet_with_options(&options).is_err());
+    }
+}
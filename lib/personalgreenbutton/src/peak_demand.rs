@@ -0,0 +1,232 @@
+use std::collections::BTreeMap;
+
+use crate::{periods::Period, reading_type::is_cumulative, TimeSeries};
+
+// Demand charges are billed on the highest average kW over a single interval,
+// so everything here works on per-interval average demand.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Peak {
+    pub title: String,
+    // Start of the month or billing period this peak belongs to.
+    pub period_start_unix: i64,
+    pub time_period_start_unix: i64,
+    pub time_period_duration_seconds: i32,
+    pub demand_kw: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CoincidentPeak {
+    pub period_start_unix: i64,
+    pub time_period_start_unix: i64,
+    pub total_demand_kw: f32,
+    // Demand of each series at the time of the coincident peak, ordered by title.
+    pub contributions: Vec<(String, f32)>,
+}
+
+impl TimeSeries {
+    /// Average demand in kW over each interval.
+    /// Energy readings are divided by the interval length, readings which are already
+    /// demand are used directly. Readings in any other unit are NaN, as are cumulative registers
+    /// and anything other than forward (delivered) flow.
+    pub fn demand_kw(&self) -> Vec<f32> {
+        return (0..self.value.len())
+            .map(|i| {
+                if is_cumulative(self.accumulation_behaviour[i])
+                    || self.flow_direction[i] != "forward"
+                {
+                    return f32::NAN;
+                }
+                match (self.kind[i], self.uom[i]) {
+                    ("demand" | "power", "W") => self.value[i] / 1000.0,
+                    (_, "Wh") => {
+                        let duration_seconds = self.time_period_duration_seconds[i];
+                        if duration_seconds <= 0 {
+                            return f32::NAN;
+                        }
+                        self.value[i] / 1000.0 * 3600.0 / duration_seconds as f32
+                    }
+                    _ => f32::NAN,
+                }
+            })
+            .collect();
+    }
+
+    /// The highest demand interval for each title in each period, ordered by title and period.
+    pub fn peak_demand(&self, period: &Period) -> Vec<Peak> {
+        let demand_kw = self.demand_kw();
+        let mut peaks = BTreeMap::<(&str, i64), Peak>::new();
+        for (i, demand) in demand_kw.into_iter().enumerate() {
+            if demand.is_nan() {
+                continue;
+            }
+            let Some(period_start_unix) = period.start(self.time_period_start_unix[i]) else {
+                continue;
+            };
+            let peak = self.peak_at(i, period_start_unix, demand);
+            peaks
+                .entry((&self.title[i], period_start_unix))
+                .and_modify(|existing| {
+                    if peak.demand_kw > existing.demand_kw {
+                        *existing = peak.clone();
+                    }
+                })
+                .or_insert(peak);
+        }
+        return peaks.into_values().collect();
+    }
+
+    /// The `n` highest demand intervals for each title, highest first.
+    pub fn top_peaks(&self, n: usize) -> Vec<Peak> {
+        let demand_kw = self.demand_kw();
+        let mut indices_by_title = BTreeMap::<&str, Vec<usize>>::new();
+        for (i, demand) in demand_kw.iter().enumerate() {
+            if demand.is_nan() {
+                continue;
+            }
+            indices_by_title.entry(&self.title[i]).or_default().push(i);
+        }
+
+        let mut peaks: Vec<Peak> = vec![];
+        for (_, mut indices) in indices_by_title {
+            indices.sort_by(|a, b| demand_kw[*b].total_cmp(&demand_kw[*a]));
+            peaks.extend(
+                indices
+                    .into_iter()
+                    .take(n)
+                    .map(|i| self.peak_at(i, self.time_period_start_unix[i], demand_kw[i])),
+            );
+        }
+        return peaks;
+    }
+
+    fn peak_at(&self, i: usize, period_start_unix: i64, demand_kw: f32) -> Peak {
+        return Peak {
+            title: self.title[i].clone(),
+            period_start_unix,
+            time_period_start_unix: self.time_period_start_unix[i],
+            time_period_duration_seconds: self.time_period_duration_seconds[i],
+            demand_kw,
+        };
+    }
+}
+
+/// Finds the interval in each period where the summed demand across all series is highest.
+/// Series are aligned on interval start, so they should share an interval length.
+pub fn coincident_peaks(series: &[TimeSeries], period: &Period) -> Vec<CoincidentPeak> {
+    // Interval start => demand per title.
+    let mut demand_by_start = BTreeMap::<i64, BTreeMap<&str, f32>>::new();
+    for timeseries in series {
+        let demand_kw = timeseries.demand_kw();
+        for (i, demand) in demand_kw.into_iter().enumerate() {
+            if demand.is_nan() {
+                continue;
+            }
+            *demand_by_start
+                .entry(timeseries.time_period_start_unix[i])
+                .or_default()
+                .entry(&timeseries.title[i])
+                .or_default() += demand;
+        }
+    }
+
+    let mut peaks = BTreeMap::<i64, CoincidentPeak>::new();
+    for (start, demand_by_title) in demand_by_start {
+        let Some(period_start_unix) = period.start(start) else {
+            continue;
+        };
+        let total_demand_kw: f32 = demand_by_title.values().sum();
+        if let Some(existing) = peaks.get(&period_start_unix) {
+            if existing.total_demand_kw >= total_demand_kw {
+                continue;
+            }
+        }
+        peaks.insert(
+            period_start_unix,
+            CoincidentPeak {
+                period_start_unix,
+                time_period_start_unix: start,
+                total_demand_kw,
+                contributions: demand_by_title
+                    .into_iter()
+                    .map(|(title, demand)| (title.to_string(), demand))
+                    .collect(),
+            },
+        );
+    }
+    return peaks.into_values().collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::coincident_peaks;
+    use crate::{periods::Period, test_util::evenly_spaced_timeseries};
+
+    // 2024-01-31 23:00:00.
+    const START: i64 = 1706742000;
+
+    #[test]
+    fn demand_from_energy() {
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 900, &[250.0, 1000.0]);
+        assert_eq!(ts.demand_kw(), vec![1.0, 4.0]);
+    }
+
+    #[test]
+    fn demand_used_directly() {
+        let ts = evenly_spaced_timeseries("a", "demand", "W", START, 900, &[2500.0]);
+        assert_eq!(ts.demand_kw(), vec![2.5]);
+    }
+
+    #[test]
+    fn registers_and_export_ignored() {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &[1000.0; 3]);
+        ts.accumulation_behaviour[1] = "summation";
+        ts.flow_direction[2] = "reverse";
+        let demand_kw = ts.demand_kw();
+        assert_eq!(demand_kw[0], 1.0);
+        assert!(demand_kw[1].is_nan() && demand_kw[2].is_nan());
+    }
+
+    #[test]
+    fn monthly_peaks() {
+        // Hourly readings, the first two fall in January, the rest in February.
+        let ts = evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            START - 3600,
+            3600,
+            &[1000.0, 3000.0, 2000.0, 5000.0, 4000.0],
+        );
+        let peaks = ts.peak_demand(&Period::Month);
+        assert_eq!(peaks.len(), 2);
+        assert_eq!(peaks[0].demand_kw, 3.0);
+        assert_eq!(peaks[0].time_period_start_unix, START);
+        assert_eq!(peaks[1].demand_kw, 5.0);
+        assert_eq!(peaks[1].time_period_start_unix, START + 2 * 3600);
+    }
+
+    #[test]
+    fn top_peaks() {
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &[1.0, 3.0, 2.0]);
+        let peaks = ts.top_peaks(2);
+        assert_eq!(
+            peaks.iter().map(|x| x.demand_kw).collect::<Vec<_>>(),
+            vec![0.003, 0.002]
+        );
+    }
+
+    #[test]
+    fn coincident() {
+        let a = evenly_spaced_timeseries("a", "energy", "Wh", START, 3600, &[1000.0, 3000.0]);
+        let b = evenly_spaced_timeseries("b", "energy", "Wh", START, 3600, &[4000.0, 1000.0]);
+        let peaks = coincident_peaks(&[a, b], &Period::Billing(vec![START, START + 7200]));
+        assert_eq!(peaks.len(), 1);
+        assert_eq!(peaks[0].time_period_start_unix, START);
+        assert_eq!(peaks[0].total_demand_kw, 5.0);
+        assert_eq!(
+            peaks[0].contributions,
+            vec![("a".to_string(), 1.0), ("b".to_string(), 4.0)]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use chrono::{DateTime, Datelike, NaiveDate};
+
+/// How readings are grouped when summarizing over time.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Period {
+    /// Calendar months, in local time.
+    Month,
+    /// Billing periods, given as sorted local start times. Each period runs until the next
+    /// boundary, so N + 1 boundaries describe N periods. Readings outside are ignored.
+    Billing(Vec<i64>),
+}
+
+impl Period {
+    /// Returns the (start, end) of the period containing the given local timestamp.
+    pub fn bounds(&self, unix: i64) -> Option<(i64, i64)> {
+        return match self {
+            Period::Month => {
+                let date = DateTime::from_timestamp(unix, 0)?.date_naive();
+                let start = NaiveDate::from_ymd_opt(date.year(), date.month(), 1)?;
+                let end = if date.month() == 12 {
+                    NaiveDate::from_ymd_opt(date.year() + 1, 1, 1)?
+                } else {
+                    NaiveDate::from_ymd_opt(date.year(), date.month() + 1, 1)?
+                };
+                Some((
+                    start.and_hms_opt(0, 0, 0)?.and_utc().timestamp(),
+                    end.and_hms_opt(0, 0, 0)?.and_utc().timestamp(),
+                ))
+            }
+            Period::Billing(boundaries) => {
+                // Index of the first boundary after unix.
+                let index = boundaries.partition_point(|x| *x <= unix);
+                if index == 0 || index == boundaries.len() {
+                    return None;
+                }
+                Some((boundaries[index - 1], boundaries[index]))
+            }
+        };
+    }
+
+    pub fn start(&self, unix: i64) -> Option<i64> {
+        return self.bounds(unix).map(|(start, _)| start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Period;
+
+    #[test]
+    fn month_bounds() {
+        // 2024-12-15 12:00:00.
+        let (start, end) = Period::Month.bounds(1734264000).unwrap();
+        // 2024-12-01 and 2025-01-01.
+        assert_eq!(start, 1733011200);
+        assert_eq!(end, 1735689600);
+    }
+
+    #[test]
+    fn billing_bounds() {
+        let period = Period::Billing(vec![100, 200, 300]);
+        assert_eq!(period.bounds(50), None);
+        assert_eq!(period.bounds(100), Some((100, 200)));
+        assert_eq!(period.bounds(299), Some((200, 300)));
+        assert_eq!(period.bounds(300), None);
+    }
+}
@@ -0,0 +1,201 @@
+use std::collections::BTreeMap;
+
+use chrono::DateTime;
+
+use crate::{
+    reading_type::is_cumulative,
+    stats::{f32_to_f64, round6},
+    Period, TimeSeries,
+};
+
+// ENERGY STAR Portfolio Manager meter consumption upload. Interval readings are summed per
+// period (by reading start, like peak_demand), and each (title, meter type, unit) becomes a
+// meter block, separated by an empty line:
+//
+//   Meter Name,<title>
+//   Meter Type,Electric - Grid
+//   Units,kWh (thousand Watt-hours)
+//   Start Date,End Date,Usage/Quantity,Cost ($),Estimation
+//   01/01/2024,01/31/2024,512.5,61.2,No
+//
+// Dates are those of the readings in the period, so a period only partly covered by the data
+// (e.g. the first and last months of a download) is reported as the part covered rather than as
+// a whole period. End dates are inclusive, as in Portfolio Manager. Estimation is "Yes" when any
+// reading in the period has an estimated or projected quality. Only forward (delivered) flow is
+// reported. Commodities and units without a Portfolio Manager equivalent, and cumulative
+// registers, are skipped.
+
+// Portfolio Manager meter type for a commodity.
+fn meter_type(commodity: &str) -> Option<&'static str> {
+    return match commodity {
+        "electricity SecondaryMetered"
+        | "electricity PrimaryMetered"
+        | "electricity TransmissionMetered" => Some("Electric - Grid"),
+        "naturalGas" => Some("Natural Gas"),
+        "propane" => Some("Propane"),
+        "potableWater" => Some("Municipally Supplied Potable Water - Mixed Indoor/Outdoor"),
+        _ => None,
+    };
+}
+
+// Portfolio Manager unit and the factor to convert a reading to it.
+fn meter_unit(uom: &str) -> Option<(&'static str, f64)> {
+    return match uom {
+        "Wh" => Some(("kWh (thousand Watt-hours)", 1e-3)),
+        "therm" => Some(("therms", 1.0)),
+        "m3" | "m3compensated" | "m3uncompensated" => Some(("cm (Cubic meters)", 1.0)),
+        "ft3" | "ft3compensated" => Some(("cf (cubic feet)", 1.0)),
+        "litre" | "litreCompensated" | "litreUncompensated" => Some(("cm (Cubic meters)", 1e-3)),
+        "usGal" => Some(("Gallons (US)", 1.0)),
+        "imperialGal" => Some(("Gallons (UK)", 1.0)),
+        _ => None,
+    };
+}
+
+fn is_estimated(quality: &str) -> bool {
+    return quality.starts_with("estimated") || quality == "projected (forecast)";
+}
+
+fn pm_date(unix: i64) -> Result<String, String> {
+    return Ok(DateTime::from_timestamp(unix, 0)
+        .ok_or("Invalid timestamp")?
+        .naive_utc()
+        .format("%m/%d/%Y")
+        .to_string());
+}
+
+struct Bill {
+    // Start of the first reading and end of the last one, within the period.
+    start: i64,
+    end: i64,
+    usage: f64,
+    cost: Option<f64>,
+    estimated: bool,
+}
+
+impl TimeSeries {
+    pub fn as_portfolio_manager_csv(&self, period: &Period) -> Result<String, String> {
+        // (title, meter type, unit) -> period start -> bill.
+        let mut meters = BTreeMap::<(&str, &str, &str), BTreeMap<i64, Bill>>::new();
+        for i in 0..self.value.len() {
+            if is_cumulative(self.accumulation_behaviour[i])
+                || self.flow_direction[i] != "forward"
+                || !self.value[i].is_finite()
+            {
+                continue;
+            }
+            let (Some(meter_type), Some((unit, factor))) =
+                (meter_type(self.commodity[i]), meter_unit(self.uom[i]))
+            else {
+                continue;
+            };
+            let reading_start = self.time_period_start_unix[i];
+            let Some((start, end)) = period.bounds(reading_start) else {
+                continue;
+            };
+            let bill = meters
+                .entry((&self.title[i], meter_type, unit))
+                .or_default()
+                .entry(start)
+                .or_insert(Bill {
+                    start: reading_start,
+                    end: reading_start,
+                    usage: 0.0,
+                    cost: None,
+                    estimated: false,
+                });
+            let reading_end = reading_start + self.time_period_duration_seconds[i].max(0) as i64;
+            bill.start = bill.start.min(reading_start);
+            bill.end = bill.end.max(reading_end.min(end));
+            bill.usage += f32_to_f64(self.value[i]) * factor;
+            if self.cost[i].is_finite() {
+                *bill.cost.get_or_insert(0.0) += f32_to_f64(self.cost[i]);
+            }
+            bill.estimated |= is_estimated(self.quality[i]);
+        }
+
+        let mut blocks = vec![];
+        for ((title, meter_type, unit), bills) in meters {
+            let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(vec![]);
+            (|| -> csv::Result<()> {
+                wtr.write_record(["Meter Name", title])?;
+                wtr.write_record(["Meter Type", meter_type])?;
+                wtr.write_record(["Units", unit])?;
+                wtr.write_record([
+                    "Start Date",
+                    "End Date",
+                    "Usage/Quantity",
+                    "Cost ($)",
+                    "Estimation",
+                ])?;
+                return Ok(());
+            })()
+            .map_err(|x| x.to_string())?;
+            for bill in bills.values() {
+                let round = |x: f64| round6(x).to_string();
+                wtr.write_record([
+                    pm_date(bill.start)?,
+                    // The last day with readings.
+                    pm_date(bill.end.max(bill.start + 1) - 1)?,
+                    round(bill.usage),
+                    bill.cost.map(round).unwrap_or_default(),
+                    if bill.estimated { "Yes" } else { "No" }.to_string(),
+                ])
+                .map_err(|x| x.to_string())?;
+            }
+            blocks.push(String::from_utf8(wtr.into_inner().map_err(|x| x.to_string())?).unwrap());
+        }
+        return Ok(blocks.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{test_util::evenly_spaced_timeseries, Period};
+
+    #[test]
+    fn meter_blocks() {
+        // 2024-01-31 and 2024-02-01, daily.
+        let mut ts = evenly_spaced_timeseries(
+            "Office",
+            "energy",
+            "Wh",
+            1706659200,
+            86400,
+            &[1500.0, 500.0],
+        );
+        ts.cost = vec![0.2, 0.1];
+        ts.quality[1] = "estimated using reference day";
+        let mut gas = evenly_spaced_timeseries("Office", "volume", "m3", 1706659200, 86400, &[2.0]);
+        gas.commodity[0] = "naturalGas";
+        ts.extend(gas);
+        let mut power = evenly_spaced_timeseries("Office", "power", "W", 1706659200, 86400, &[1.0]);
+        power.kind[0] = "power";
+        ts.extend(power);
+        let mut export =
+            evenly_spaced_timeseries("Office", "energy", "Wh", 1706659200, 86400, &[700.0]);
+        export.flow_direction[0] = "reverse";
+        ts.extend(export);
+
+        assert_eq!(
+            ts.as_portfolio_manager_csv(&Period::Month).unwrap(),
+            "Meter Name,Office
+Meter Type,Electric - Grid
+Units,kWh (thousand Watt-hours)
+Start Date,End Date,Usage/Quantity,Cost ($),Estimation
+01/31/2024,01/31/2024,1.5,0.2,No
+02/01/2024,02/01/2024,0.5,0.1,Yes
+
+Meter Name,Office
+Meter Type,Natural Gas
+Units,cm (Cubic meters)
+Start Date,End Date,Usage/Quantity,Cost ($),Estimation
+01/31/2024,01/31/2024,2,,No
+"
+        );
+
+        let billing = Period::Billing(vec![1706659200, 1706659200 + 2 * 86400]);
+        let csv = ts.as_portfolio_manager_csv(&billing).unwrap();
+        assert_eq!(csv.lines().nth(4), Some("01/31/2024,02/01/2024,2,0.3,Yes"));
+    }
+}
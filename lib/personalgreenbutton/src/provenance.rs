@@ -0,0 +1,93 @@
+use chrono::TimeDelta;
+use serde_json::json;
+
+use crate::{local_time_parameters::LocalTimeParametersSingle, TimeSeries};
+
+// Where a TimeSeries came from. Merged (without duplicates) by TimeSeries::extend, and written
+// to file level metadata by exporters that support it, e.g. parquet.
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Provenance {
+    // Input files, as named by the caller. The parser doesn't know about files.
+    pub source_files: Vec<String>,
+    // Href of the first entry of each parsed feed, which identifies the provider.
+    pub provider_hrefs: Vec<String>,
+    // Provider bugs worked around while parsing, see fix_provider_bugs_if_needed.
+    pub quirks: Vec<String>,
+    // Time zone and DST rules of the first feed, which local times can be converted back to UTC
+    // with. None for inputs without LocalTimeParameters, e.g. utility CSV exports.
+    pub local_time: Option<LocalTimeParametersSingle>,
+}
+
+fn push_unique(values: &mut Vec<String>, value: String) {
+    if !value.is_empty() && !values.contains(&value) {
+        values.push(value);
+    }
+}
+
+impl Provenance {
+    pub fn extend(&mut self, other: Provenance) {
+        for x in other.source_files {
+            push_unique(&mut self.source_files, x);
+        }
+        for x in other.provider_hrefs {
+            push_unique(&mut self.provider_hrefs, x);
+        }
+        for x in other.quirks {
+            push_unique(&mut self.quirks, x);
+        }
+        if self.local_time.is_none() {
+            self.local_time = other.local_time;
+        }
+    }
+
+    // Key-value pairs for file metadata. List values are JSON arrays.
+    pub fn key_value_metadata(&self) -> Vec<(String, String)> {
+        let list = |x: &Vec<String>| serde_json::Value::from(x.clone()).to_string();
+        let mut metadata = vec![
+            (
+                "personalgreenbutton.version".to_string(),
+                env!("CARGO_PKG_VERSION").to_string(),
+            ),
+            (
+                "personalgreenbutton.source_files".to_string(),
+                list(&self.source_files),
+            ),
+            (
+                "personalgreenbutton.provider_hrefs".to_string(),
+                list(&self.provider_hrefs),
+            ),
+            ("personalgreenbutton.quirks".to_string(), list(&self.quirks)),
+        ];
+        if let Some(x) = &self.local_time {
+            let local_time = json!({
+                "dst_start_rule": x.dst_start_rule,
+                "dst_end_rule": x.dst_end_rule,
+                "dst_offset": x.dst_offset.num_seconds(),
+                "tz_offset": x.tz_offset.num_seconds(),
+            });
+            metadata.push((
+                "personalgreenbutton.local_time".to_string(),
+                local_time.to_string(),
+            ));
+        }
+        return metadata;
+    }
+
+    // Reverse of the personalgreenbutton.local_time metadata.
+    pub(crate) fn parse_local_time(value: &str) -> Option<LocalTimeParametersSingle> {
+        let x: serde_json::Value = serde_json::from_str(value).ok()?;
+        return Some(LocalTimeParametersSingle {
+            dst_start_rule: u32::try_from(x["dst_start_rule"].as_u64()?).ok()?,
+            dst_end_rule: u32::try_from(x["dst_end_rule"].as_u64()?).ok()?,
+            dst_offset: TimeDelta::seconds(x["dst_offset"].as_i64()?),
+            tz_offset: TimeDelta::seconds(x["tz_offset"].as_i64()?),
+        });
+    }
+}
+
+impl TimeSeries {
+    pub fn add_source_file(&mut self, name: &str) {
+        push_unique(&mut self.provenance.source_files, name.to_string());
+    }
+}
@@ -0,0 +1,318 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use parquet::{
+    basic::Type as PhysicalType,
+    file::reader::{FileReader, SerializedFileReader},
+    record::Field,
+};
+
+use crate::{
+    extra_columns::ExtraColumnValues, gb_type_details::find_gb_type_app_info, Provenance,
+    TimeSeries,
+};
+
+// Reads as_csv and as_parquet output back into a TimeSeries, e.g. to merge archived exports
+// with new downloads. Columns are matched by name. Enum columns must hold values from the GB
+// type table (or "Missing app info"), and are mapped back to those static strings. Any other
+// column becomes an extra column: FLOAT in parquet, or numeric in every CSV row, is F32, and
+// anything else is Str.
+
+// (column, XML scope, ESPI field) for the enum columns.
+pub(crate) const ENUM_COLUMNS: [(&str, &str, &str); 9] = [
+    ("quality", "", "QualityOfReading"),
+    (
+        "accumulation_behaviour",
+        "ReadingType",
+        "accumulationBehaviour",
+    ),
+    ("commodity", "ReadingType", "commodity"),
+    ("currency", "ReadingType", "currency"),
+    ("data_qualifier", "ReadingType", "dataQualifier"),
+    ("flow_direction", "ReadingType", "flowDirection"),
+    ("kind", "ReadingType", "kind"),
+    ("phase", "ReadingType", "phase"),
+    ("uom", "ReadingType", "uom"),
+];
+
+const REQUIRED_COLUMNS: [&str; 6] = [
+    "title",
+    "cost",
+    "value",
+    "tou",
+    "time_period_start_unix",
+    "time_period_duration_seconds",
+];
+
+fn static_enum(column: &str, value: &str) -> Result<&'static str> {
+    let (_, scope, field) = ENUM_COLUMNS.iter().find(|x| x.0 == column).unwrap();
+    if value == "Missing app info" {
+        return Ok("Missing app info");
+    }
+    return find_gb_type_app_info(scope, field, value).ok_or(anyhow!(
+        "Unknown {} {:?}",
+        column,
+        value
+    ));
+}
+
+fn is_core_column(name: &str) -> bool {
+    return REQUIRED_COLUMNS.contains(&name) || ENUM_COLUMNS.iter().any(|x| x.0 == name);
+}
+
+fn check_columns(names: &[String]) -> Result<()> {
+    for name in REQUIRED_COLUMNS
+        .iter()
+        .chain(ENUM_COLUMNS.iter().map(|x| &x.0))
+    {
+        if !names.iter().any(|x| x == name) {
+            return Err(anyhow!("Missing column {}", name));
+        }
+    }
+    return Ok(());
+}
+
+impl TimeSeries {
+    fn push_enum(&mut self, column: &str, value: &str) -> Result<()> {
+        let value = static_enum(column, value)?;
+        match column {
+            "quality" => self.quality.push(value),
+            "accumulation_behaviour" => self.accumulation_behaviour.push(value),
+            "commodity" => self.commodity.push(value),
+            "currency" => self.currency.push(value),
+            "data_qualifier" => self.data_qualifier.push(value),
+            "flow_direction" => self.flow_direction.push(value),
+            "kind" => self.kind.push(value),
+            "phase" => self.phase.push(value),
+            "uom" => self.uom.push(value),
+            _ => unreachable!(),
+        }
+        return Ok(());
+    }
+
+    /// Reads the output of as_csv.
+    pub fn from_csv(s: &str) -> Result<TimeSeries> {
+        let mut reader = csv::Reader::from_reader(s.as_bytes());
+        let names: Vec<String> = reader.headers()?.iter().map(|x| x.to_string()).collect();
+        check_columns(&names)?;
+
+        let mut timeseries = TimeSeries::default();
+        let mut extra_columns: Vec<(usize, Vec<String>)> = vec![];
+        for (i, name) in names.iter().enumerate() {
+            if !is_core_column(name) {
+                extra_columns.push((i, vec![]));
+            }
+        }
+
+        for record in reader.records() {
+            let record = record?;
+            for (name, value) in names.iter().zip(record.iter()) {
+                let parse_error = || anyhow!("Invalid {} {:?}", name, value);
+                match name.as_str() {
+                    "title" => timeseries.title.push(value.to_string()),
+                    "cost" => timeseries
+                        .cost
+                        .push(value.parse().map_err(|_| parse_error())?),
+                    "value" => timeseries
+                        .value
+                        .push(value.parse().map_err(|_| parse_error())?),
+                    "tou" => timeseries
+                        .tou
+                        .push(value.parse().map_err(|_| parse_error())?),
+                    "time_period_start_unix" => timeseries
+                        .time_period_start_unix
+                        .push(value.parse().map_err(|_| parse_error())?),
+                    "time_period_duration_seconds" => timeseries
+                        .time_period_duration_seconds
+                        .push(value.parse().map_err(|_| parse_error())?),
+                    x if ENUM_COLUMNS.iter().any(|y| y.0 == x) => timeseries.push_enum(x, value)?,
+                    _ => {}
+                }
+            }
+            for (i, values) in &mut extra_columns {
+                values.push(record.get(*i).unwrap_or("").to_string());
+            }
+        }
+
+        for (i, values) in extra_columns {
+            let floats: Option<Vec<f32>> = values.iter().map(|x| x.parse().ok()).collect();
+            let values = match floats {
+                Some(x) => ExtraColumnValues::F32(x),
+                None => ExtraColumnValues::Str(values),
+            };
+            timeseries
+                .set_extra_column(&names[i], values)
+                .map_err(|x| anyhow!(x))?;
+        }
+        return Ok(timeseries);
+    }
+
+    /// Reads the output of as_parquet (or as_parquet_with_options), including the provenance
+    /// key-value metadata.
+    pub fn from_parquet(parquet: &[u8]) -> Result<TimeSeries> {
+        return TimeSeries::from_parquet_partition(parquet, &[]);
+    }
+
+    /// Reads a file of a partitioned dataset, where the partition columns are only in the path.
+    /// Every reading gets the given (column, value) for them.
+    pub(crate) fn from_parquet_partition(
+        parquet: &[u8],
+        partition_values: &[(&str, &str)],
+    ) -> Result<TimeSeries> {
+        let reader = SerializedFileReader::new(Bytes::copy_from_slice(parquet))?;
+        let metadata = reader.metadata().file_metadata();
+        let fields = metadata.schema_descr().root_schema().get_fields();
+        let mut names: Vec<String> = fields.iter().map(|x| x.name().to_string()).collect();
+        names.extend(partition_values.iter().map(|x| x.0.to_string()));
+        check_columns(&names)?;
+
+        let mut timeseries = TimeSeries::default();
+        for key_value in metadata.key_value_metadata().into_iter().flatten() {
+            let values = || -> Vec<String> {
+                return key_value
+                    .value
+                    .as_ref()
+                    .and_then(|x| serde_json::from_str(x).ok())
+                    .unwrap_or_default();
+            };
+            match key_value.key.as_str() {
+                "personalgreenbutton.source_files" => {
+                    timeseries.provenance.source_files = values();
+                }
+                "personalgreenbutton.provider_hrefs" => {
+                    timeseries.provenance.provider_hrefs = values();
+                }
+                "personalgreenbutton.quirks" => timeseries.provenance.quirks = values(),
+                "personalgreenbutton.local_time" => {
+                    timeseries.provenance.local_time = key_value
+                        .value
+                        .as_deref()
+                        .and_then(Provenance::parse_local_time);
+                }
+                _ => {}
+            }
+        }
+
+        let mut extra_columns: Vec<(&str, ExtraColumnValues)> = vec![];
+        for field in fields {
+            let name = field.name();
+            if is_core_column(name) {
+                continue;
+            }
+            let values = match field.get_physical_type() {
+                PhysicalType::FLOAT => ExtraColumnValues::F32(vec![]),
+                _ => ExtraColumnValues::Str(vec![]),
+            };
+            extra_columns.push((name, values));
+        }
+
+        for row in reader.get_row_iter(None)? {
+            let row = row?;
+            for (name, field) in row.get_column_iter() {
+                let type_error = || anyhow!("Unexpected {} {:?}", name, field);
+                match (name.as_str(), field) {
+                    ("title", Field::Str(x)) => timeseries.title.push(x.clone()),
+                    ("cost", Field::Float(x)) => timeseries.cost.push(*x),
+                    ("value", Field::Float(x)) => timeseries.value.push(*x),
+                    ("tou", Field::Int(x)) => timeseries.tou.push(*x),
+                    ("time_period_start_unix", Field::TimestampMillis(x)) => {
+                        timeseries.time_period_start_unix.push(x / 1000)
+                    }
+                    ("time_period_duration_seconds", Field::Int(x)) => {
+                        timeseries.time_period_duration_seconds.push(*x)
+                    }
+                    (x, Field::Str(value)) if ENUM_COLUMNS.iter().any(|y| y.0 == x) => {
+                        timeseries.push_enum(x, value)?
+                    }
+                    (x, _) if is_core_column(x) => return Err(type_error()),
+                    (x, field) => {
+                        let (_, values) = extra_columns.iter_mut().find(|y| y.0 == x).unwrap();
+                        match (values, field) {
+                            (ExtraColumnValues::F32(values), Field::Float(x)) => values.push(*x),
+                            (ExtraColumnValues::Str(values), Field::Str(x)) => {
+                                values.push(x.clone())
+                            }
+                            _ => return Err(type_error()),
+                        }
+                    }
+                }
+            }
+        }
+
+        for (column, value) in partition_values {
+            for _ in 0..timeseries.value.len() {
+                match *column {
+                    "title" => timeseries.title.push(value.to_string()),
+                    x => timeseries.push_enum(x, value)?,
+                }
+            }
+        }
+        for (name, values) in extra_columns {
+            timeseries
+                .set_extra_column(name, values)
+                .map_err(|x| anyhow!(x))?;
+        }
+        return Ok(timeseries);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        extra_columns::ExtraColumnValues,
+        test_util::{eastern, evenly_spaced_timeseries},
+        TimeSeries,
+    };
+
+    fn timeseries() -> TimeSeries {
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0, 2.5]);
+        ts.cost[1] = 0.25;
+        ts.quality[0] = "other";
+        ts.set_extra_column(
+            "temperature_c",
+            ExtraColumnValues::F32(vec![-3.5, f32::NAN]),
+        )
+        .unwrap();
+        ts.set_extra_column(
+            "anomaly",
+            ExtraColumnValues::Str(vec!["".into(), "spike".into()]),
+        )
+        .unwrap();
+        ts.add_source_file("usage.xml");
+        return ts;
+    }
+
+    // NaN != NaN, so compare via the CSV.
+    fn assert_same(a: &TimeSeries, b: &TimeSeries) {
+        assert_eq!(a.as_csv().unwrap(), b.as_csv().unwrap());
+        assert_eq!(a.extra_columns.len(), b.extra_columns.len());
+    }
+
+    #[test]
+    fn csv_round_trip() {
+        let ts = timeseries();
+        let read = TimeSeries::from_csv(&ts.as_csv().unwrap()).unwrap();
+        assert_same(&ts, &read);
+        assert_eq!(read.kind[0], "energy");
+        assert!(matches!(
+            read.extra_column("temperature_c"),
+            Some(ExtraColumnValues::F32(_))
+        ));
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        let mut ts = timeseries();
+        ts.provenance.local_time = Some(eastern());
+        let read = TimeSeries::from_parquet(&ts.as_parquet().unwrap()).unwrap();
+        assert_same(&ts, &read);
+        assert_eq!(read.provenance, ts.provenance);
+    }
+
+    #[test]
+    fn unknown_enum_value() {
+        let mut ts = timeseries();
+        ts.uom[0] = "furlongs";
+        let error = TimeSeries::from_csv(&ts.as_csv().unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "Unknown uom \"furlongs\"");
+    }
+}
@@ -3,6 +3,55 @@ use columnar_struct_vec::columnar_struct_vec;
 use roxmltree::Node;
 
 use crate::parse_helpers::{parse_text_of, strip_espi_prefix};
+use crate::TimeSeries;
+
+/// Column names of the reading type fields, in the order of TimeSeries::reading_type_values.
+pub const READING_TYPE_FIELDS: [&str; 8] = [
+    "accumulation_behaviour",
+    "commodity",
+    "currency",
+    "data_qualifier",
+    "flow_direction",
+    "kind",
+    "phase",
+    "uom",
+];
+
+// Names in the ESPI schema, in the same order as READING_TYPE_FIELDS.
+pub(crate) const ESPI_FIELDS: [&str; 8] = [
+    "accumulationBehaviour",
+    "commodity",
+    "currency",
+    "dataQualifier",
+    "flowDirection",
+    "kind",
+    "phase",
+    "uom",
+];
+
+/// Whether readings are register reads rather than usage over their interval.
+pub fn is_cumulative(accumulation_behaviour: &str) -> bool {
+    return matches!(
+        accumulation_behaviour,
+        "cumulative" | "continuousCumulative" | "summation"
+    );
+}
+
+impl TimeSeries {
+    /// The reading type fields of a reading, in the order of READING_TYPE_FIELDS.
+    pub fn reading_type_values(&self, i: usize) -> [&'static str; 8] {
+        return [
+            self.accumulation_behaviour[i],
+            self.commodity[i],
+            self.currency[i],
+            self.data_qualifier[i],
+            self.flow_direction[i],
+            self.kind[i],
+            self.phase[i],
+            self.uom[i],
+        ];
+    }
+}
 
 #[derive(Debug)]
 #[columnar_struct_vec]
@@ -0,0 +1,331 @@
+use std::io::Write;
+
+use chrono::DateTime;
+
+use crate::{extra_columns::ExtraColumnValues, reading_type::READING_TYPE_FIELDS, TimeSeries};
+
+// SQL script export, for loading into a database with psql -f, sqlite3 or duckdb without any
+// glue code: CREATE TABLE IF NOT EXISTS, then the readings in one transaction, as batched
+// INSERTs or (PostgreSQL only) COPY ... FROM STDIN. Optionally the table is made a TimescaleDB
+// hypertable on time_period_start.
+//
+// The table has the as_csv columns, except that time_period_start_unix is replaced by a local
+// time_period_start TIMESTAMP (TEXT in SQLite, as "YYYY-MM-DD HH:MM:SS"). NaN values are NULL.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SqlDialect {
+    Postgres,
+    Sqlite,
+    Duckdb,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SqlLoadStatement {
+    Insert,
+    // PostgreSQL only.
+    Copy,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SqlScriptOptions {
+    pub dialect: SqlDialect,
+    pub table: String,
+    // PostgreSQL only.
+    pub timescale: bool,
+    pub load: SqlLoadStatement,
+    // Rows per INSERT statement.
+    pub batch_size: usize,
+}
+
+impl Default for SqlScriptOptions {
+    fn default() -> Self {
+        return SqlScriptOptions {
+            dialect: SqlDialect::Postgres,
+            table: "readings".to_string(),
+            timescale: false,
+            load: SqlLoadStatement::Insert,
+            batch_size: 1000,
+        };
+    }
+}
+
+#[derive(Clone, Copy)]
+enum SqlType {
+    Text,
+    Float,
+    Integer,
+    Timestamp,
+}
+
+impl SqlType {
+    fn name(&self, dialect: SqlDialect) -> &'static str {
+        return match (self, dialect) {
+            (SqlType::Text, SqlDialect::Duckdb) => "VARCHAR",
+            (SqlType::Text, _) => "TEXT",
+            (SqlType::Float, SqlDialect::Duckdb) => "FLOAT",
+            (SqlType::Float, _) => "REAL",
+            (SqlType::Integer, _) => "INTEGER",
+            (SqlType::Timestamp, SqlDialect::Sqlite) => "TEXT",
+            (SqlType::Timestamp, _) => "TIMESTAMP",
+        };
+    }
+}
+
+enum SqlValue<'a> {
+    Text(&'a str),
+    Float(f32),
+    Integer(i64),
+    Timestamp(i64),
+}
+
+fn quote_identifier(x: &str) -> String {
+    return format!("\"{}\"", x.replace('"', "\"\""));
+}
+
+fn quote_literal(x: &str) -> String {
+    return format!("'{}'", x.replace('\'', "''"));
+}
+
+// Shared by the INSERT and COPY formats, None is NULL.
+fn sql_value_text(value: &SqlValue) -> Result<Option<String>, String> {
+    return Ok(match value {
+        SqlValue::Text(x) => Some(x.to_string()),
+        SqlValue::Float(x) if x.is_finite() => Some(x.to_string()),
+        SqlValue::Float(_) => None,
+        SqlValue::Integer(x) => Some(x.to_string()),
+        SqlValue::Timestamp(x) => Some(
+            DateTime::from_timestamp(*x, 0)
+                .ok_or("Invalid timestamp")?
+                .naive_utc()
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        ),
+    });
+}
+
+// COPY text format.
+fn escape_copy(x: &str) -> String {
+    return x
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r");
+}
+
+impl TimeSeries {
+    // (name, type, nullable).
+    fn sql_columns(&self) -> Vec<(&str, SqlType, bool)> {
+        let mut columns = vec![
+            ("title", SqlType::Text, false),
+            ("cost", SqlType::Float, true),
+            ("quality", SqlType::Text, false),
+            ("value", SqlType::Float, true),
+            ("tou", SqlType::Integer, false),
+            ("time_period_start", SqlType::Timestamp, false),
+            ("time_period_duration_seconds", SqlType::Integer, false),
+        ];
+        columns.extend(
+            READING_TYPE_FIELDS
+                .iter()
+                .map(|x| (*x, SqlType::Text, false)),
+        );
+        for column in &self.extra_columns {
+            let sql_type = match column.values {
+                ExtraColumnValues::F32(_) => SqlType::Float,
+                ExtraColumnValues::Str(_) => SqlType::Text,
+            };
+            columns.push((&column.name, sql_type, true));
+        }
+        return columns;
+    }
+
+    // In the same order as sql_columns.
+    fn sql_values(&self, i: usize) -> Vec<SqlValue<'_>> {
+        let mut values = vec![
+            SqlValue::Text(&self.title[i]),
+            SqlValue::Float(self.cost[i]),
+            SqlValue::Text(self.quality[i]),
+            SqlValue::Float(self.value[i]),
+            SqlValue::Integer(self.tou[i] as i64),
+            SqlValue::Timestamp(self.time_period_start_unix[i]),
+            SqlValue::Integer(self.time_period_duration_seconds[i] as i64),
+        ];
+        values.extend(self.reading_type_values(i).map(SqlValue::Text));
+        for column in &self.extra_columns {
+            values.push(match &column.values {
+                ExtraColumnValues::F32(x) => SqlValue::Float(x[i]),
+                ExtraColumnValues::Str(x) => SqlValue::Text(&x[i]),
+            });
+        }
+        return values;
+    }
+
+    pub fn as_sql_script(&self, options: &SqlScriptOptions) -> Result<String, String> {
+        let mut buf: Vec<u8> = vec![];
+        self.write_sql_script(&mut buf, options)?;
+        return Ok(String::from_utf8(buf).unwrap());
+    }
+
+    pub fn write_sql_script<W: Write>(
+        &self,
+        mut w: W,
+        options: &SqlScriptOptions,
+    ) -> Result<(), String> {
+        let postgres = options.dialect == SqlDialect::Postgres;
+        if options.timescale && !postgres {
+            return Err("TimescaleDB hypertables need the PostgreSQL dialect.".to_string());
+        }
+        if options.load == SqlLoadStatement::Copy && !postgres {
+            return Err("COPY FROM STDIN needs the PostgreSQL dialect.".to_string());
+        }
+        let write_error = |x: std::io::Error| x.to_string();
+
+        let table = quote_identifier(&options.table);
+        let columns = self.sql_columns();
+        let column_names = columns
+            .iter()
+            .map(|(name, _, _)| quote_identifier(name))
+            .collect::<Vec<_>>()
+            .join(", ");
+        writeln!(w, "BEGIN;").map_err(write_error)?;
+        if options.timescale {
+            writeln!(w, "CREATE EXTENSION IF NOT EXISTS timescaledb;").map_err(write_error)?;
+        }
+        writeln!(w, "CREATE TABLE IF NOT EXISTS {} (", table).map_err(write_error)?;
+        for (i, (name, sql_type, nullable)) in columns.iter().enumerate() {
+            let not_null = if *nullable { "" } else { " NOT NULL" };
+            let separator = if i + 1 < columns.len() { "," } else { "" };
+            writeln!(
+                w,
+                "    {} {}{}{}",
+                quote_identifier(name),
+                sql_type.name(options.dialect),
+                not_null,
+                separator
+            )
+            .map_err(write_error)?;
+        }
+        writeln!(w, ");").map_err(write_error)?;
+        if options.timescale {
+            writeln!(
+                w,
+                "SELECT create_hypertable({}, 'time_period_start', if_not_exists => TRUE);",
+                quote_literal(&table)
+            )
+            .map_err(write_error)?;
+        }
+
+        match options.load {
+            SqlLoadStatement::Insert => {
+                let batch_size = options.batch_size.max(1);
+                for i in 0..self.value.len() {
+                    if i % batch_size == 0 {
+                        writeln!(w, "INSERT INTO {} ({}) VALUES", table, column_names)
+                            .map_err(write_error)?;
+                    }
+                    let mut row = vec![];
+                    for value in self.sql_values(i) {
+                        row.push(match sql_value_text(&value)? {
+                            None => "NULL".to_string(),
+                            Some(x)
+                                if matches!(value, SqlValue::Float(_) | SqlValue::Integer(_)) =>
+                            {
+                                x
+                            }
+                            Some(x) => quote_literal(&x),
+                        });
+                    }
+                    let last = (i + 1) % batch_size == 0 || i + 1 == self.value.len();
+                    writeln!(w, "({}){}", row.join(", "), if last { ";" } else { "," })
+                        .map_err(write_error)?;
+                }
+            }
+            SqlLoadStatement::Copy => {
+                writeln!(w, "COPY {} ({}) FROM STDIN;", table, column_names)
+                    .map_err(write_error)?;
+                for i in 0..self.value.len() {
+                    let mut row = vec![];
+                    for value in self.sql_values(i) {
+                        row.push(match sql_value_text(&value)? {
+                            None => "\\N".to_string(),
+                            Some(x) => escape_copy(&x),
+                        });
+                    }
+                    writeln!(w, "{}", row.join("\t")).map_err(write_error)?;
+                }
+                writeln!(w, "\\.").map_err(write_error)?;
+            }
+        }
+        writeln!(w, "COMMIT;").map_err(write_error)?;
+        w.flush().map_err(write_error)?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SqlDialect, SqlLoadStatement, SqlScriptOptions};
+    use crate::test_util::evenly_spaced_timeseries;
+
+    const COLUMNS: &str = "\"title\", \"cost\", \"quality\", \"value\", \"tou\", \
+\"time_period_start\", \"time_period_duration_seconds\", \"accumulation_behaviour\", \
+\"commodity\", \"currency\", \"data_qualifier\", \"flow_direction\", \"kind\", \"phase\", \"uom\"";
+
+    #[test]
+    fn postgres() {
+        let ts = evenly_spaced_timeseries("Bob's house", "energy", "Wh", 0, 3600, &[1.5, 2.0, 3.0]);
+        let options = SqlScriptOptions {
+            timescale: true,
+            batch_size: 2,
+            ..Default::default()
+        };
+        let sql = ts.as_sql_script(&options).unwrap();
+        assert!(sql.contains("    \"time_period_start\" TIMESTAMP NOT NULL,\n"));
+        assert!(sql.contains("SELECT create_hypertable('\"readings\"', 'time_period_start', "));
+        assert_eq!(sql.matches("INSERT INTO").count(), 2);
+        assert!(sql.contains(&format!(
+            "INSERT INTO \"readings\" ({}) VALUES\n('Bob''s house', NULL, 'valid', 1.5, 0, \
+'1970-01-01 00:00:00', 3600, 'deltaData', 'electricity SecondaryMetered', 'CAD', 'normal', \
+'forward', 'energy', 'none', 'Wh'),\n",
+            COLUMNS
+        )));
+
+        let options = SqlScriptOptions {
+            load: SqlLoadStatement::Copy,
+            ..Default::default()
+        };
+        let sql = ts.as_sql_script(&options).unwrap();
+        assert!(sql.contains(&format!(
+            "COPY \"readings\" ({}) FROM STDIN;\nBob's house\t\\N\tvalid\t1.5\t0\t\
+1970-01-01 00:00:00\t3600\t",
+            COLUMNS
+        )));
+        assert!(sql.ends_with("\n\\.\nCOMMIT;\n"));
+
+        let options = SqlScriptOptions {
+            dialect: SqlDialect::Duckdb,
+            ..options
+        };
+        assert!(ts.as_sql_script(&options).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "sqlite")]
+    fn runs_in_sqlite() {
+        let ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.5, f32::NAN]);
+        let options = SqlScriptOptions {
+            dialect: SqlDialect::Sqlite,
+            ..Default::default()
+        };
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(&ts.as_sql_script(&options).unwrap())
+            .unwrap();
+        let (count, total): (i64, f64) = connection
+            .query_row("SELECT COUNT(*), SUM(value) FROM readings", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((count, total), (2, 1.5));
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::Result;
+use rusqlite::{params, Connection, OptionalExtension};
+
+use crate::{extra_columns::ExtraColumnValues, TimeSeries};
+
+// Normalized SQLite export. Each title is a usage point, and each distinct set of ReadingType
+// fields is stored once. A series is a (usage point, reading type) pair, and readings are keyed
+// by (series_id, tou, time_period_start_utc), so exporting into an existing database upserts and
+// both readings of the hour repeated when DST ends are kept. Extra columns are added to the
+// readings table as needed.
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS usage_points (
+        id INTEGER PRIMARY KEY,
+        title TEXT NOT NULL UNIQUE
+    );
+    CREATE TABLE IF NOT EXISTS reading_types (
+        id INTEGER PRIMARY KEY,
+        accumulation_behaviour TEXT NOT NULL,
+        commodity TEXT NOT NULL,
+        currency TEXT NOT NULL,
+        data_qualifier TEXT NOT NULL,
+        flow_direction TEXT NOT NULL,
+        kind TEXT NOT NULL,
+        phase TEXT NOT NULL,
+        uom TEXT NOT NULL,
+        UNIQUE (accumulation_behaviour, commodity, currency, data_qualifier, flow_direction,
+            kind, phase, uom)
+    );
+    CREATE TABLE IF NOT EXISTS series (
+        id INTEGER PRIMARY KEY,
+        usage_point_id INTEGER NOT NULL REFERENCES usage_points (id),
+        reading_type_id INTEGER NOT NULL REFERENCES reading_types (id),
+        UNIQUE (usage_point_id, reading_type_id)
+    );
+    CREATE TABLE IF NOT EXISTS readings (
+        series_id INTEGER NOT NULL REFERENCES series (id),
+        time_period_start_unix INTEGER NOT NULL,
+        -- The local start if the local time parameters are unknown.
+        time_period_start_utc INTEGER NOT NULL,
+        time_period_duration_seconds INTEGER NOT NULL,
+        value REAL NOT NULL,
+        -- NULL if the provider didn't supply a cost.
+        cost REAL,
+        quality TEXT NOT NULL,
+        tou INTEGER NOT NULL,
+        PRIMARY KEY (series_id, tou, time_period_start_utc)
+    ) WITHOUT ROWID;
+    CREATE INDEX IF NOT EXISTS readings_by_start ON readings (time_period_start_unix);
+";
+
+fn quote_identifier(x: &str) -> String {
+    return format!("\"{}\"", x.replace('"', "\"\""));
+}
+
+fn sql_f32(x: f32) -> Option<f64> {
+    if x.is_nan() {
+        return None;
+    }
+    return Some(x as f64);
+}
+
+fn insert_or_get_id(
+    connection: &Connection,
+    insert: &str,
+    select: &str,
+    values: &[&str],
+) -> Result<i64> {
+    connection.execute(insert, rusqlite::params_from_iter(values))?;
+    return Ok(connection.query_row(select, rusqlite::params_from_iter(values), |row| row.get(0))?);
+}
+
+impl TimeSeries {
+    /// Creates the database if needed, then upserts all readings.
+    pub fn write_sqlite(&self, path: &Path) -> Result<()> {
+        let mut connection = Connection::open(path)?;
+        return self.write_sqlite_connection(&mut connection);
+    }
+
+    pub fn write_sqlite_connection(&self, connection: &mut Connection) -> Result<()> {
+        let transaction = connection.transaction()?;
+        transaction.execute_batch(SCHEMA)?;
+
+        let mut existing_columns = vec![];
+        {
+            let mut statement =
+                transaction.prepare("SELECT name FROM pragma_table_info('readings')")?;
+            for name in statement.query_map([], |row| row.get::<_, String>(0))? {
+                existing_columns.push(name?);
+            }
+        }
+        for column in &self.extra_columns {
+            if existing_columns.contains(&column.name) {
+                continue;
+            }
+            let sql_type = match column.values {
+                ExtraColumnValues::F32(_) => "REAL",
+                ExtraColumnValues::Str(_) => "TEXT",
+            };
+            transaction.execute(
+                &format!(
+                    "ALTER TABLE readings ADD COLUMN {} {}",
+                    quote_identifier(&column.name),
+                    sql_type
+                ),
+                [],
+            )?;
+        }
+
+        let mut column_names: Vec<String> = [
+            "series_id",
+            "tou",
+            "time_period_start_utc",
+            "time_period_start_unix",
+            "time_period_duration_seconds",
+            "value",
+            "cost",
+            "quality",
+        ]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+        column_names.extend(self.extra_columns.iter().map(|x| quote_identifier(&x.name)));
+        let updates: Vec<String> = column_names[3..]
+            .iter()
+            .map(|x| format!("{x} = excluded.{x}"))
+            .collect();
+        let insert_reading = format!(
+            "INSERT INTO readings ({}) VALUES ({})
+                ON CONFLICT (series_id, tou, time_period_start_utc) DO UPDATE SET {}",
+            column_names.join(", "),
+            vec!["?"; column_names.len()].join(", "),
+            updates.join(", ")
+        );
+
+        let starts = self
+            .utc_starts()
+            .unwrap_or_else(|| self.time_period_start_unix.clone());
+        {
+            // (title, reading type) => series id.
+            let mut series_ids = HashMap::<(&str, [&str; 8]), i64>::new();
+            let mut statement = transaction.prepare(&insert_reading)?;
+            for i in 0..self.value.len() {
+                let reading_type = self.reading_type_values(i);
+                let key = (self.title[i].as_str(), reading_type);
+                let series_id = match series_ids.get(&key) {
+                    Some(x) => *x,
+                    None => {
+                        let series_id = series_id(&transaction, key.0, &reading_type)?;
+                        series_ids.insert(key, series_id);
+                        series_id
+                    }
+                };
+
+                let mut values: Vec<Box<dyn rusqlite::ToSql>> = vec![
+                    Box::new(series_id),
+                    Box::new(self.tou[i]),
+                    Box::new(starts[i]),
+                    Box::new(self.time_period_start_unix[i]),
+                    Box::new(self.time_period_duration_seconds[i]),
+                    Box::new(self.value[i] as f64),
+                    Box::new(sql_f32(self.cost[i])),
+                    Box::new(self.quality[i]),
+                ];
+                for column in &self.extra_columns {
+                    values.push(match &column.values {
+                        ExtraColumnValues::F32(x) => Box::new(sql_f32(x[i])),
+                        ExtraColumnValues::Str(x) => Box::new(x[i].clone()),
+                    });
+                }
+                statement.execute(rusqlite::params_from_iter(values))?;
+            }
+        }
+        transaction.commit()?;
+        return Ok(());
+    }
+}
+
+fn series_id(connection: &Connection, title: &str, reading_type: &[&str; 8]) -> Result<i64> {
+    let usage_point_id = insert_or_get_id(
+        connection,
+        "INSERT INTO usage_points (title) VALUES (?) ON CONFLICT DO NOTHING",
+        "SELECT id FROM usage_points WHERE title = ?",
+        &[title],
+    )?;
+    let reading_type_id = insert_or_get_id(
+        connection,
+        "INSERT INTO reading_types (accumulation_behaviour, commodity, currency, data_qualifier,
+            flow_direction, kind, phase, uom) VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT DO NOTHING",
+        "SELECT id FROM reading_types WHERE accumulation_behaviour = ? AND commodity = ?
+            AND currency = ? AND data_qualifier = ? AND flow_direction = ? AND kind = ?
+            AND phase = ? AND uom = ?",
+        reading_type,
+    )?;
+    let existing: Option<i64> = connection
+        .query_row(
+            "SELECT id FROM series WHERE usage_point_id = ? AND reading_type_id = ?",
+            params![usage_point_id, reading_type_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if let Some(existing) = existing {
+        return Ok(existing);
+    }
+    connection.execute(
+        "INSERT INTO series (usage_point_id, reading_type_id) VALUES (?, ?)",
+        params![usage_point_id, reading_type_id],
+    )?;
+    return Ok(connection.last_insert_rowid());
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{
+        extra_columns::ExtraColumnValues,
+        test_util::{eastern, evenly_spaced_timeseries},
+    };
+
+    #[test]
+    fn rerunning_upserts() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", 0, 3600, &[1.0, 2.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "b",
+            "energy",
+            "Wh",
+            0,
+            3600,
+            &[3.0],
+        ));
+        ts.write_sqlite_connection(&mut connection).unwrap();
+
+        ts.value[1] = 5.0;
+        ts.set_extra_column(
+            "flag",
+            ExtraColumnValues::Str(vec!["".into(), "x".into(), "".into()]),
+        )
+        .unwrap();
+        ts.write_sqlite_connection(&mut connection).unwrap();
+
+        let count = |table: &str| -> i64 {
+            return connection
+                .query_row(&format!("SELECT COUNT(*) FROM {table}"), [], |row| {
+                    row.get(0)
+                })
+                .unwrap();
+        };
+        assert_eq!(count("usage_points"), 2);
+        assert_eq!(count("reading_types"), 1);
+        assert_eq!(count("series"), 2);
+        assert_eq!(count("readings"), 3);
+
+        let (value, cost, flag): (f64, Option<f64>, String) = connection
+            .query_row(
+                "SELECT value, cost, flag FROM readings WHERE time_period_start_unix = 3600",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .unwrap();
+        assert_eq!(value, 5.0);
+        assert_eq!(cost, None);
+        assert_eq!(flag, "x");
+    }
+
+    #[test]
+    fn keeps_dst_fall_back() {
+        let repeated = (1730419200..)
+            .step_by(3600)
+            .find(|x| eastern().is_repeated(*x))
+            .unwrap();
+        let mut connection = Connection::open_in_memory().unwrap();
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", repeated, 3600, &[1.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "a",
+            "energy",
+            "Wh",
+            repeated,
+            3600,
+            &[2.0],
+        ));
+        ts.provenance.local_time = Some(eastern());
+        ts.write_sqlite_connection(&mut connection).unwrap();
+        ts.write_sqlite_connection(&mut connection).unwrap();
+
+        let mut statement = connection
+            .prepare("SELECT value FROM readings ORDER BY time_period_start_utc")
+            .unwrap();
+        let values: Vec<f64> = statement
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .map(|x| x.unwrap())
+            .collect();
+        assert_eq!(values, [1.0, 2.0]);
+    }
+}
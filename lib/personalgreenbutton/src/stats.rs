@@ -0,0 +1,80 @@
+// Small statistics helpers shared by the analysis modules.
+
+/// Linearly interpolated percentile, with `p` in [0, 100]. Sorts `values` in place.
+/// Returns NaN for an empty slice.
+pub fn percentile(values: &mut [f32], p: f32) -> f32 {
+    if values.is_empty() {
+        return f32::NAN;
+    }
+    values.sort_by(|a, b| a.total_cmp(b));
+    let rank = (p / 100.0).clamp(0.0, 1.0) * (values.len() - 1) as f32;
+    let lower = rank.floor() as usize;
+    let upper = rank.ceil() as usize;
+    let fraction = rank - lower as f32;
+    return values[lower] + (values[upper] - values[lower]) * fraction;
+}
+
+/// Via the shortest decimal representation, so 0.1f32 becomes 0.1 rather than
+/// 0.10000000149011612.
+pub fn f32_to_f64(x: f32) -> f64 {
+    return x.to_string().parse().unwrap();
+}
+
+/// Rounds to 6 decimals, to hide floating point noise in sums, e.g. 0.30000000000000004.
+pub fn round6(x: f64) -> f64 {
+    return (x * 1e6).round() / 1e6;
+}
+
+pub fn median(values: &mut [f32]) -> f32 {
+    return percentile(values, 50.0);
+}
+
+pub fn mean(values: &[f32]) -> f32 {
+    if values.is_empty() {
+        return f32::NAN;
+    }
+    return values.iter().sum::<f32>() / values.len() as f32;
+}
+
+/// Least squares slope of y over x. NaN with fewer than two distinct x values.
+pub fn linear_regression_slope(x: &[f64], y: &[f64]) -> f64 {
+    let n = x.len().min(y.len());
+    if n < 2 {
+        return f64::NAN;
+    }
+    let mean_x = x[..n].iter().sum::<f64>() / n as f64;
+    let mean_y = y[..n].iter().sum::<f64>() / n as f64;
+    let mut covariance = 0.0;
+    let mut variance = 0.0;
+    for (x, y) in x.iter().zip(y) {
+        covariance += (x - mean_x) * (y - mean_y);
+        variance += (x - mean_x) * (x - mean_x);
+    }
+    if variance == 0.0 {
+        return f64::NAN;
+    }
+    return covariance / variance;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{linear_regression_slope, median, percentile};
+
+    #[test]
+    fn percentile_interpolates() {
+        let mut values = vec![4.0, 1.0, 3.0, 2.0];
+        assert_eq!(median(&mut values), 2.5);
+        assert_eq!(percentile(&mut values, 0.0), 1.0);
+        assert_eq!(percentile(&mut values, 100.0), 4.0);
+        assert!(percentile(&mut [], 50.0).is_nan());
+    }
+
+    #[test]
+    fn slope() {
+        assert_eq!(
+            linear_regression_slope(&[0.0, 1.0, 2.0], &[1.0, 3.0, 5.0]),
+            2.0
+        );
+        assert!(linear_regression_slope(&[1.0], &[1.0]).is_nan());
+    }
+}
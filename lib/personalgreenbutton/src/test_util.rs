@@ -0,0 +1,44 @@
+use crate::{local_time_parameters::LocalTimeParametersSingle, TimeSeries};
+
+// Builds a single series of evenly spaced readings, starting at `start_unix`.
+pub fn evenly_spaced_timeseries(
+    title: &str,
+    kind: &'static str,
+    uom: &'static str,
+    start_unix: i64,
+    duration_seconds: i32,
+    values: &[f32],
+) -> TimeSeries {
+    let len = values.len();
+    return TimeSeries {
+        title: vec![title.to_string(); len],
+        cost: vec![f32::NAN; len],
+        quality: vec!["valid"; len],
+        value: values.to_vec(),
+        tou: vec![0; len],
+        time_period_start_unix: (0..len)
+            .map(|i| start_unix + i as i64 * duration_seconds as i64)
+            .collect(),
+        time_period_duration_seconds: vec![duration_seconds; len],
+        accumulation_behaviour: vec!["deltaData"; len],
+        commodity: vec!["electricity SecondaryMetered"; len],
+        currency: vec!["CAD"; len],
+        data_qualifier: vec!["normal"; len],
+        flow_direction: vec!["forward"; len],
+        kind: vec![kind; len],
+        phase: vec!["none"; len],
+        uom: vec![uom; len],
+        extra_columns: vec![],
+        provenance: Default::default(),
+    };
+}
+
+// US Eastern: UTC-5, with an hour of DST from March to November.
+pub fn eastern() -> LocalTimeParametersSingle {
+    return LocalTimeParametersSingle {
+        dst_start_rule: u32::from_str_radix("360E2000", 16).unwrap(),
+        dst_end_rule: u32::from_str_radix("B40E2000", 16).unwrap(),
+        dst_offset: chrono::TimeDelta::seconds(3600),
+        tz_offset: chrono::TimeDelta::seconds(-5 * 3600),
+    };
+}
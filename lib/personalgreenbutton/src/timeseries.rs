@@ -1,15 +1,14 @@
-use parquet::{
-    file::{
-        properties::WriterProperties,
-        writer::{SerializedFileWriter, TrackedWrite},
-    },
-    schema::parser::parse_message_type,
-};
-use regex::Regex;
+use parquet::{file::writer::SerializedFileWriter, schema::parser::parse_message_type};
+use std::collections::HashSet;
+use std::ops::Range;
 use std::sync::Arc;
 use wasm_bindgen::prelude::wasm_bindgen;
 
-use crate::parquet_column_writers::{write_f32s, write_i32s, write_i64s, write_strs};
+use crate::extra_columns::{ExtraColumn, ExtraColumnValues};
+use crate::influxdb::InfluxdbOptions;
+use crate::parquet_column_writers::ParquetColumn;
+use crate::parquet_options::ParquetOptions;
+use crate::provenance::Provenance;
 
 // The initial version of this was mostly generated via procedural macro,
 // but the complexity didn't seem worth it. There's a lot of boilerplate
@@ -55,6 +54,13 @@ pub struct TimeSeries {
     pub phase: Vec<&'static str>,
     #[wasm_bindgen(skip)]
     pub uom: Vec<&'static str>,
+
+    // Derived columns, see extra_columns.rs.
+    #[wasm_bindgen(skip)]
+    pub extra_columns: Vec<ExtraColumn>,
+
+    #[wasm_bindgen(skip)]
+    pub provenance: Provenance,
 }
 
 impl TimeSeries {
@@ -108,6 +114,8 @@ impl TimeSeries {
             kind: self.kind.drain(0..after_first_chunk_index).collect(),
             phase: self.phase.drain(0..after_first_chunk_index).collect(),
             uom: self.uom.drain(0..after_first_chunk_index).collect(),
+            extra_columns: self.drain_extra_columns(0..after_first_chunk_index),
+            provenance: self.provenance.clone(),
         };
 
         return Some(first_chunk);
@@ -115,7 +123,9 @@ impl TimeSeries {
 
     pub fn sort(&mut self) {
         let indices: Vec<_> = (0..self.value.len()).collect();
-        let mut p = permutation::sort_unstable_by(indices, |i, j| {
+        // Stable, so readings with the same start, like the two of the hour repeated when DST
+        // ends, keep their order.
+        let mut p = permutation::sort_by(indices, |i, j| {
             self.title[*i]
                 .cmp(&self.title[*j])
                 .then(self.time_period_start_unix[*i].cmp(&self.time_period_start_unix[*j]))
@@ -135,6 +145,7 @@ impl TimeSeries {
         p.apply_slice_in_place(&mut self.kind);
         p.apply_slice_in_place(&mut self.phase);
         p.apply_slice_in_place(&mut self.uom);
+        self.apply_permutation_to_extra_columns(&mut p);
     }
 
     pub fn sort_and_chunk(mut self) -> Vec<TimeSeries> {
@@ -146,7 +157,10 @@ impl TimeSeries {
         return chunks;
     }
 
-    pub fn extend(&mut self, other: TimeSeries) {
+    pub fn extend(&mut self, mut other: TimeSeries) {
+        self.extend_extra_columns(other.value.len(), std::mem::take(&mut other.extra_columns));
+        self.provenance
+            .extend(std::mem::take(&mut other.provenance));
         self.title.extend(other.title);
 
         // Interval Reading.
@@ -170,29 +184,208 @@ impl TimeSeries {
         self.uom.extend(other.uom);
     }
 
-    pub fn fix_provider_bugs_if_needed(&mut self, href: &str) {
-        if href.contains("enova") {
-            self.cost = self.cost.iter().map(|cost| cost * 100.0).collect();
+    // Copies the given rows, in the given order.
+    pub fn select(&self, indices: &[usize]) -> TimeSeries {
+        fn pick<T: Clone>(x: &[T], indices: &[usize]) -> Vec<T> {
+            return indices.iter().map(|i| x[*i].clone()).collect();
         }
+        return TimeSeries {
+            title: pick(&self.title, indices),
+            cost: pick(&self.cost, indices),
+            quality: pick(&self.quality, indices),
+            value: pick(&self.value, indices),
+            tou: pick(&self.tou, indices),
+            time_period_start_unix: pick(&self.time_period_start_unix, indices),
+            time_period_duration_seconds: pick(&self.time_period_duration_seconds, indices),
+            accumulation_behaviour: pick(&self.accumulation_behaviour, indices),
+            commodity: pick(&self.commodity, indices),
+            currency: pick(&self.currency, indices),
+            data_qualifier: pick(&self.data_qualifier, indices),
+            flow_direction: pick(&self.flow_direction, indices),
+            kind: pick(&self.kind, indices),
+            phase: pick(&self.phase, indices),
+            uom: pick(&self.uom, indices),
+            extra_columns: self.select_extra_columns(indices),
+            provenance: self.provenance.clone(),
+        };
     }
-}
 
-#[wasm_bindgen]
-impl TimeSeries {
-    #[wasm_bindgen(js_name = "hasCost")]
-    pub fn has_cost(&self) -> bool {
-        for cost in &self.cost {
-            if cost.is_finite() && *cost != 0.0 {
-                return true;
+    /// Replaces readings with newer ones: readings which newer also has (same title, reading
+    /// type, tou and start) are dropped, then newer is appended. Readings within newer are all
+    /// kept, including the two with the same local start when DST ends.
+    pub fn overlay(&mut self, newer: TimeSeries) {
+        let replaced: HashSet<_> = (0..newer.value.len())
+            .map(|i| {
+                (
+                    &newer.title[i],
+                    newer.reading_type_values(i),
+                    newer.tou[i],
+                    newer.time_period_start_unix[i],
+                )
+            })
+            .collect();
+        let kept: Vec<usize> = (0..self.value.len())
+            .filter(|i| {
+                !replaced.contains(&(
+                    &self.title[*i],
+                    self.reading_type_values(*i),
+                    self.tou[*i],
+                    self.time_period_start_unix[*i],
+                ))
+            })
+            .collect();
+        if kept.len() < self.value.len() {
+            *self = self.select(&kept);
+        }
+        self.extend(newer);
+    }
+
+    /// UTC start of each reading, if the local time parameters are known. Of two readings of a
+    /// series with the same local start in the hour repeated when DST ends, the first is taken to
+    /// be the DST one and the second the standard time one.
+    pub(crate) fn utc_starts(&self) -> Option<Vec<i64>> {
+        let local_time = self.provenance.local_time.as_ref()?;
+        let mut seen = HashSet::new();
+        let starts = (0..self.value.len())
+            .map(|i| {
+                let local = self.time_period_start_unix[i];
+                let utc = local_time.to_utc(local);
+                if local_time.is_repeated(local)
+                    && !seen.insert((
+                        &self.title[i],
+                        self.reading_type_values(i),
+                        self.tou[i],
+                        local,
+                    ))
+                {
+                    return utc + local_time.dst_offset.num_seconds();
+                }
+                return utc;
+            })
+            .collect();
+        return Some(starts);
+    }
+
+    // The as_parquet columns for a range of rows, in schema order.
+    fn parquet_columns(&self, rows: Range<usize>) -> Vec<(&str, ParquetColumn<'_>)> {
+        let strs = |x: &[&'static str]| ParquetColumn::Strs(x[rows.clone()].to_vec());
+        let mut columns = vec![
+            (
+                "title",
+                ParquetColumn::Strs(
+                    self.title[rows.clone()]
+                        .iter()
+                        .map(|x| x.as_str())
+                        .collect(),
+                ),
+            ),
+            ("cost", ParquetColumn::F32s(&self.cost[rows.clone()])),
+            ("quality", strs(&self.quality)),
+            ("value", ParquetColumn::F32s(&self.value[rows.clone()])),
+            ("tou", ParquetColumn::I32s(&self.tou[rows.clone()])),
+            (
+                "time_period_start_unix",
+                ParquetColumn::TimestampMillis(
+                    self.time_period_start_unix[rows.clone()]
+                        .iter()
+                        .map(|x| x * 1000)
+                        .collect(),
+                ),
+            ),
+            (
+                "time_period_duration_seconds",
+                ParquetColumn::I32s(&self.time_period_duration_seconds[rows.clone()]),
+            ),
+            ("accumulation_behaviour", strs(&self.accumulation_behaviour)),
+            ("commodity", strs(&self.commodity)),
+            ("currency", strs(&self.currency)),
+            ("data_qualifier", strs(&self.data_qualifier)),
+            ("flow_direction", strs(&self.flow_direction)),
+            ("kind", strs(&self.kind)),
+            ("phase", strs(&self.phase)),
+            ("uom", strs(&self.uom)),
+        ];
+        for column in &self.extra_columns {
+            columns.push((
+                &column.name,
+                match &column.values {
+                    ExtraColumnValues::F32(x) => ParquetColumn::F32s(&x[rows.clone()]),
+                    ExtraColumnValues::Str(x) => {
+                        ParquetColumn::Strs(x[rows.clone()].iter().map(|x| x.as_str()).collect())
+                    }
+                },
+            ));
+        }
+        return columns;
+    }
+
+    // Writes a parquet file with the as_parquet schema, minus skip_columns. Columns are built
+    // one row group at a time, so memory use is bounded by the row group size.
+    pub(crate) fn write_parquet_file<W: std::io::Write + Send>(
+        &self,
+        sink: W,
+        options: &ParquetOptions,
+        skip_columns: &[&str],
+    ) -> Result<parquet::format::FileMetaData, String> {
+        let columns = |rows: Range<usize>| {
+            let mut columns = self.parquet_columns(rows);
+            columns.retain(|(name, _)| !skip_columns.contains(name));
+            return columns;
+        };
+
+        // Originally authored when we had logic to convert a timeseries to arrow.
+        // We used the export to arrow, converted the arrow to parquet, and then
+        // pulled the schema off the parquet file via
+        // https://docs.rs/parquet/latest/parquet/schema/printer/index.html/
+        let empty = columns(0..0);
+        let mut message_type = "message arrow_schema {\n".to_string();
+        for (name, column) in &empty {
+            message_type += &column.schema_line(name);
+        }
+        message_type += "}";
+        let schema = Arc::new(parse_message_type(&message_type).map_err(|x| x.to_string())?);
+        let string_columns: Vec<&str> = empty
+            .iter()
+            .filter(|(_, column)| matches!(column, ParquetColumn::Strs(_)))
+            .map(|(name, _)| *name)
+            .collect();
+        let props = Arc::new(options.writer_properties(&string_columns, &self.provenance)?);
+
+        let len = self.value.len();
+        let row_group_size = props.max_row_group_size();
+        let mut writer =
+            SerializedFileWriter::new(sink, schema, props).map_err(|x| x.to_string())?;
+        let mut start = 0;
+        loop {
+            let rows = start..len.min(start + row_group_size);
+            // Order must match the schema.
+            let mut row_group_writer = writer.next_row_group().map_err(|x| x.to_string())?;
+            for (_, column) in columns(rows.clone()) {
+                column.write(&mut row_group_writer)?;
+            }
+            row_group_writer.close().map_err(|x| x.to_string())?;
+            start = rows.end;
+            if start >= len {
+                break;
             }
         }
-        return false;
+        return writer.close().map_err(|x| x.to_string());
     }
 
-    #[wasm_bindgen(js_name = "asCSV")]
-    pub fn as_csv(&self) -> Result<String, String> {
-        let mut wtr = csv::Writer::from_writer(vec![]);
-        wtr.write_record(&[
+    /// Streams as_parquet_with_options output, one row group at a time.
+    pub fn write_parquet<W: std::io::Write + Send>(
+        &self,
+        w: W,
+        options: &ParquetOptions,
+    ) -> Result<(), String> {
+        self.write_parquet_file(w, options, &[])?;
+        return Ok(());
+    }
+
+    /// Streams as_csv output, one row at a time.
+    pub fn write_csv<W: std::io::Write>(&self, w: W) -> Result<(), String> {
+        let mut wtr = csv::Writer::from_writer(w);
+        let mut header = vec![
             "title",
             "cost",
             "quality",
@@ -208,11 +401,12 @@ impl TimeSeries {
             "kind",
             "phase",
             "uom",
-        ])
-        .map_err(|x| x.to_string())?;
+        ];
+        header.extend(self.extra_columns.iter().map(|x| x.name.as_str()));
+        wtr.write_record(&header).map_err(|x| x.to_string())?;
 
         for i in 0..self.title.len() {
-            wtr.write_record(&[
+            let mut record = vec![
                 self.title[i].to_string(),
                 self.cost[i].to_string(),
                 self.quality[i].to_string(),
@@ -228,133 +422,63 @@ impl TimeSeries {
                 self.kind[i].to_string(),
                 self.phase[i].to_string(),
                 self.uom[i].to_string(),
-            ])
-            .map_err(|x| x.to_string())?;
+            ];
+            record.extend(
+                self.extra_columns
+                    .iter()
+                    .map(|x| x.values.value_to_string(i)),
+            );
+            wtr.write_record(&record).map_err(|x| x.to_string())?;
         }
-        let csv = String::from_utf8(wtr.into_inner().map_err(|x| x.to_string())?).unwrap();
-        return Ok(csv);
+        wtr.flush().map_err(|x| x.to_string())?;
+        return Ok(());
     }
 
-    #[wasm_bindgen(js_name = "asParquet")]
-    pub fn as_parquet(&self) -> Result<Vec<u8>, String> {
-        // Originally authored when we had logic to convert a timeseries to arrow.
-        // We used the export to arrow, converted the arrow to parquet, and then
-        // pulled the schema off the parquet file via
-        // https://docs.rs/parquet/latest/parquet/schema/printer/index.html/
-        let message_type = "
-            message arrow_schema {
-                REQUIRED BYTE_ARRAY title (STRING);
-                REQUIRED FLOAT cost;
-                REQUIRED BYTE_ARRAY quality (STRING);
-                REQUIRED FLOAT value;
-                REQUIRED INT32 tou;
-                REQUIRED INT64 time_period_start_unix (TIMESTAMP(MILLIS, false));
-                REQUIRED INT32 time_period_duration_seconds;
-                REQUIRED BYTE_ARRAY accumulation_behaviour (STRING);
-                REQUIRED BYTE_ARRAY commodity (STRING);
-                REQUIRED BYTE_ARRAY currency (STRING);
-                REQUIRED BYTE_ARRAY data_qualifier (STRING);
-                REQUIRED BYTE_ARRAY flow_direction (STRING);
-                REQUIRED BYTE_ARRAY kind (STRING);
-                REQUIRED BYTE_ARRAY phase (STRING);
-                REQUIRED BYTE_ARRAY uom (STRING);
-            }
-        ";
-        let schema = Arc::new(parse_message_type(message_type).unwrap());
-        let props = Arc::new(
-            WriterProperties::builder()
-                .set_compression(parquet::basic::Compression::SNAPPY)
-                .build(),
-        );
-        let buf: Vec<u8> = vec![];
-        let mut tracked_write: TrackedWrite<_> = TrackedWrite::new(buf);
+    pub fn as_parquet_with_options(&self, options: &ParquetOptions) -> Result<Vec<u8>, String> {
+        let mut buf: Vec<u8> = vec![];
+        self.write_parquet(&mut buf, options)?;
+        return Ok(buf);
+    }
 
-        let mut writer = SerializedFileWriter::new(&mut tracked_write, schema, props).unwrap();
-        {
-            // Order must match the schema.
-            let mut row_group_writer = writer.next_row_group().unwrap();
-            // Title is a special case, since it's Strings, not strs.
-            write_strs(
-                &mut row_group_writer,
-                &self.title.iter().map(|x| x.as_str()).collect::<Vec<_>>(),
-            )?;
-            write_f32s(&mut row_group_writer, &self.cost)?;
-            write_strs(&mut row_group_writer, &self.quality)?;
-            write_f32s(&mut row_group_writer, &self.value)?;
-            write_i32s(&mut row_group_writer, &self.tou)?;
-            write_i64s(
-                &mut row_group_writer,
-                &self
-                    .time_period_start_unix
-                    .iter()
-                    .map(|x| x * 1000)
-                    .collect::<Vec<_>>(),
-            )?;
-            write_i32s(&mut row_group_writer, &self.time_period_duration_seconds)?;
-            write_strs(&mut row_group_writer, &self.accumulation_behaviour)?;
-            write_strs(&mut row_group_writer, &self.commodity)?;
-            write_strs(&mut row_group_writer, &self.currency)?;
-            write_strs(&mut row_group_writer, &self.data_qualifier)?;
-            write_strs(&mut row_group_writer, &self.flow_direction)?;
-            write_strs(&mut row_group_writer, &self.kind)?;
-            write_strs(&mut row_group_writer, &self.phase)?;
-            write_strs(&mut row_group_writer, &self.uom)?;
-            row_group_writer.close().map_err(|x| x.to_string())?;
+    pub fn fix_provider_bugs_if_needed(&mut self, href: &str) {
+        self.provenance.provider_hrefs.push(href.to_string());
+        if href.contains("enova") {
+            self.cost = self.cost.iter().map(|cost| cost * 100.0).collect();
+            self.provenance
+                .quirks
+                .push("enova: cost scaled by 100".to_string());
         }
-        writer.close().unwrap();
+    }
+}
 
-        return Ok(tracked_write.inner().to_vec());
+#[wasm_bindgen]
+impl TimeSeries {
+    #[wasm_bindgen(js_name = "hasCost")]
+    pub fn has_cost(&self) -> bool {
+        for cost in &self.cost {
+            if cost.is_finite() && *cost != 0.0 {
+                return true;
+            }
+        }
+        return false;
+    }
+
+    #[wasm_bindgen(js_name = "asCSV")]
+    pub fn as_csv(&self) -> Result<String, String> {
+        let mut buf: Vec<u8> = vec![];
+        self.write_csv(&mut buf)?;
+        return Ok(String::from_utf8(buf).unwrap());
+    }
+
+    #[wasm_bindgen(js_name = "asParquet")]
+    pub fn as_parquet(&self) -> Result<Vec<u8>, String> {
+        return self.as_parquet_with_options(&ParquetOptions::default());
     }
 
+    /// Line protocol with the default InfluxdbOptions.
     #[wasm_bindgen(js_name = "asInfluxdb")]
     pub fn as_influxdb(&self) -> String {
-        let mut result = String::new();
-        let special_chars = Regex::new(r"[^A-Za-z0-9_]").unwrap();
-        let has_cost = self.has_cost();
-
-        for i in 0..self.value.len() {
-            let measurement_without_spaces = &self.title[i].replace(" ", "_");
-            let measurement = special_chars.replace_all(&measurement_without_spaces, "");
-            let tags = vec![
-                "db=greenbutton".to_owned(),
-                format!(
-                    "accumulation_behavior={}",
-                    self.accumulation_behaviour[i].replace(" ", "\\ ")
-                ),
-                format!("commodity={}", self.commodity[i].replace(" ", "\\ ")),
-                format!("currency={}", self.currency[i].replace(" ", "\\ ")),
-                format!(
-                    "data_qualifier={}",
-                    self.data_qualifier[i].replace(" ", "\\ ")
-                ),
-                format!(
-                    "flow_direction={}",
-                    self.flow_direction[i].replace(" ", "\\ ")
-                ),
-                format!("kind={}", self.kind[i].replace(" ", "\\ ")),
-                format!("phase={}", self.phase[i].replace(" ", "\\ ")),
-                format!("uom={}", self.uom[i].replace(" ", "\\ ")),
-            ]
-            .join(",");
-
-            let mut fields = vec![
-                format!("quality={}", self.quality[i].replace(" ", "\\ ")),
-                format!("value={}", self.value[i]),
-                format!("tou={}", self.tou[i]),
-                format!(
-                    "time_period_duration_seconds={}",
-                    self.time_period_duration_seconds[i]
-                ),
-            ];
-            if has_cost {
-                fields.push(format!("cost={}", self.cost[i]));
-            }
-            let fields = fields.join(",");
-
-            let time_ns = self.time_period_start_unix[i] * 1000000000;
-            result += &format!("{measurement},{tags} {fields} {time_ns}\n");
-        }
-        return result.to_owned();
+        return self.as_influxdb_with_options(&InfluxdbOptions::default());
     }
 }
 
@@ -464,6 +588,8 @@ mod tests {
             kind: vec!["a", "b"],
             phase: vec!["a", "b"],
             uom: vec!["a", "b"],
+            extra_columns: vec![],
+            provenance: Default::default(),
         };
     }
 
@@ -471,7 +597,7 @@ mod tests {
     fn as_parquet() {
         let test = get_test_timeseries();
         let parquet = test.as_parquet().unwrap();
-        assert_eq!(parquet.len(), 3614);
+        assert_eq!(parquet.len(), 3627);
     }
 
     #[test]
@@ -501,4 +627,17 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn overlay() {
+        let mut test = get_test_timeseries();
+        let mut newer = get_test_timeseries();
+        newer.value = vec![5.0, 6.0];
+        newer.time_period_start_unix[1] += 1;
+        // Both readings of a repeated hour.
+        newer.extend(newer.select(&[1]));
+        test.overlay(newer);
+        assert_eq!(test.value, vec![4.0, 5.0, 6.0, 6.0]);
+        assert_eq!(test.title, vec!["b", "a", "b", "b"]);
+    }
 }
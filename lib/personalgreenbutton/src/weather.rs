@@ -0,0 +1,418 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use chrono::{NaiveDate, NaiveDateTime};
+
+use crate::{
+    extra_columns::ExtraColumnValues, local_time_parameters::LocalTimeParametersSingle, TimeSeries,
+};
+
+// Hourly temperatures loaded from a local CSV, joined to readings by local time.
+//
+// Supported layouts, detected from the header:
+//  - Simple: a time column ("timestamp", "datetime", "time" or "date") and a temperature
+//    column ("temperature", "temp", "temperature_c", "temp_c", "temperature_f" or "temp_f").
+//    Times are local, either ISO 8601 or unix seconds. Exactly "time" and "temp" is Meteostat.
+//  - NOAA ISD (NCEI global-hourly CSV): "DATE" and "TMP" columns, times in UTC.
+//  - Meteostat: "time" and "temp" columns from the API/python exports, or the headerless
+//    bulk hourly files (date, hour, temp, ...). Times in UTC.
+
+const SECONDS_PER_DAY: i64 = 24 * 3600;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeatherCsvOptions {
+    // Added to UTC timestamps (ISD, Meteostat) to get local time, when local_time isn't set.
+    pub utc_offset_seconds: i64,
+    // The readings' time zone and DST rules (TimeSeries::provenance.local_time). When set, UTC
+    // timestamps are converted with these rather than utc_offset_seconds, so they match the
+    // readings in DST too.
+    pub local_time: Option<LocalTimeParametersSingle>,
+}
+
+impl Default for WeatherCsvOptions {
+    fn default() -> Self {
+        return WeatherCsvOptions {
+            utc_offset_seconds: 0,
+            local_time: None,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DegreeDayOptions {
+    pub heating_base_c: f32,
+    pub cooling_base_c: f32,
+}
+
+impl Default for DegreeDayOptions {
+    fn default() -> Self {
+        return DegreeDayOptions {
+            heating_base_c: 18.0,
+            cooling_base_c: 18.0,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DegreeDay {
+    pub day_start_unix: i64,
+    pub mean_temperature_c: f32,
+    pub heating_degree_days: f32,
+    pub cooling_degree_days: f32,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WeatherObservations {
+    // Local time, sorted.
+    pub time_unix: Vec<i64>,
+    pub temperature_c: Vec<f32>,
+}
+
+enum Layout {
+    Simple {
+        time: usize,
+        temperature: usize,
+        fahrenheit: bool,
+    },
+    Isd {
+        time: usize,
+        temperature: usize,
+    },
+    Meteostat {
+        time: usize,
+        temperature: usize,
+    },
+    MeteostatBulk,
+}
+
+fn detect_layout(first_row: &csv::StringRecord) -> Result<Layout> {
+    let find = |names: &[&str]| {
+        first_row
+            .iter()
+            .position(|x| names.contains(&x.trim().to_lowercase().as_str()))
+    };
+    if let (Some(time), Some(temperature)) = (
+        first_row.iter().position(|x| x == "DATE"),
+        first_row.iter().position(|x| x == "TMP"),
+    ) {
+        return Ok(Layout::Isd { time, temperature });
+    }
+    if let (Some(time), Some(temperature)) = (
+        first_row.iter().position(|x| x == "time"),
+        first_row.iter().position(|x| x == "temp"),
+    ) {
+        return Ok(Layout::Meteostat { time, temperature });
+    }
+    let time = find(&["timestamp", "datetime", "time", "date"]);
+    if let (Some(time), Some(temperature)) = (
+        time,
+        find(&["temperature", "temp", "temperature_c", "temp_c"]),
+    ) {
+        return Ok(Layout::Simple {
+            time,
+            temperature,
+            fahrenheit: false,
+        });
+    }
+    if let (Some(time), Some(temperature)) = (time, find(&["temperature_f", "temp_f"])) {
+        return Ok(Layout::Simple {
+            time,
+            temperature,
+            fahrenheit: true,
+        });
+    }
+    if first_row.len() > 2
+        && NaiveDate::parse_from_str(&first_row[0], "%Y-%m-%d").is_ok()
+        && first_row[1].parse::<u32>().is_ok()
+    {
+        return Ok(Layout::MeteostatBulk);
+    }
+    return Err(anyhow!("Unrecognized weather CSV header {:?}", first_row));
+}
+
+fn parse_local_time(s: &str) -> Result<i64> {
+    let s = s.trim();
+    if let Ok(unix) = s.parse::<i64>() {
+        return Ok(unix);
+    }
+    for format in ["%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"] {
+        if let Ok(datetime) = NaiveDateTime::parse_from_str(s, format) {
+            return Ok(datetime.and_utc().timestamp());
+        }
+    }
+    return Err(anyhow!("Unrecognized weather timestamp {:?}", s));
+}
+
+// ISD temperatures look like "+0123,1": tenths of a degree, then a quality code.
+fn parse_isd_temperature(s: &str) -> Option<f32> {
+    let (value, quality) = s.split_once(',')?;
+    // 2, 3, 6 and 7 mark suspect or erroneous values.
+    if ["2", "3", "6", "7"].contains(&quality) || value.ends_with("9999") {
+        return None;
+    }
+    return Some(value.parse::<f32>().ok()? / 10.0);
+}
+
+fn utc_to_local(utc_unix: i64, options: &WeatherCsvOptions) -> i64 {
+    return match &options.local_time {
+        Some(local_time) => local_time.to_local(utc_unix),
+        None => utc_unix + options.utc_offset_seconds,
+    };
+}
+
+// Missing values are common in all of these formats, so rows without a temperature are skipped.
+fn parse_row(
+    layout: &Layout,
+    row: &csv::StringRecord,
+    options: &WeatherCsvOptions,
+) -> Result<Option<(i64, f32)>> {
+    let field = |i: usize| row.get(i).unwrap_or("").trim();
+    let temperature = match layout {
+        Layout::Simple {
+            temperature,
+            fahrenheit,
+            ..
+        } => field(*temperature).parse::<f32>().ok().map(|x| {
+            if *fahrenheit {
+                (x - 32.0) * 5.0 / 9.0
+            } else {
+                x
+            }
+        }),
+        Layout::Isd { temperature, .. } => parse_isd_temperature(field(*temperature)),
+        Layout::Meteostat { temperature, .. } => field(*temperature).parse().ok(),
+        Layout::MeteostatBulk => field(2).parse().ok(),
+    };
+    let Some(temperature) = temperature else {
+        return Ok(None);
+    };
+
+    let time = match layout {
+        Layout::Simple { time, .. } => parse_local_time(field(*time))?,
+        Layout::Isd { time, .. } | Layout::Meteostat { time, .. } => {
+            utc_to_local(parse_local_time(field(*time))?, options)
+        }
+        Layout::MeteostatBulk => {
+            let date = NaiveDate::parse_from_str(field(0), "%Y-%m-%d")?;
+            let hour: u32 = field(1).parse()?;
+            let datetime = date
+                .and_hms_opt(hour, 0, 0)
+                .ok_or(anyhow!("Invalid hour {:?}", hour))?;
+            utc_to_local(datetime.and_utc().timestamp(), options)
+        }
+    };
+    return Ok(Some((time, temperature)));
+}
+
+pub fn parse_weather_csv(s: &str, options: &WeatherCsvOptions) -> Result<WeatherObservations> {
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(s.as_bytes());
+    let mut rows = reader.records();
+    let first_row = rows.next().ok_or(anyhow!("Empty weather CSV"))??;
+    let layout = detect_layout(&first_row)?;
+
+    let mut observations = BTreeMap::<i64, f32>::new();
+    if let Layout::MeteostatBulk = layout {
+        if let Some((time, temperature)) = parse_row(&layout, &first_row, options)? {
+            observations.insert(time, temperature);
+        }
+    }
+    for row in rows {
+        if let Some((time, temperature)) = parse_row(&layout, &row?, options)? {
+            observations.insert(time, temperature);
+        }
+    }
+
+    return Ok(WeatherObservations {
+        time_unix: observations.keys().cloned().collect(),
+        temperature_c: observations.values().cloned().collect(),
+    });
+}
+
+impl WeatherObservations {
+    /// Heating and cooling degree days for each local day, from the mean temperature of the day.
+    pub fn degree_days(&self, options: &DegreeDayOptions) -> Vec<DegreeDay> {
+        let mut days = BTreeMap::<i64, Vec<f32>>::new();
+        for (time, temperature) in self.time_unix.iter().zip(&self.temperature_c) {
+            days.entry(time - time.rem_euclid(SECONDS_PER_DAY))
+                .or_default()
+                .push(*temperature);
+        }
+        return days
+            .into_iter()
+            .map(|(day_start_unix, temperatures)| {
+                let mean_temperature_c =
+                    temperatures.iter().sum::<f32>() / temperatures.len() as f32;
+                DegreeDay {
+                    day_start_unix,
+                    mean_temperature_c,
+                    heating_degree_days: (options.heating_base_c - mean_temperature_c).max(0.0),
+                    cooling_degree_days: (mean_temperature_c - options.cooling_base_c).max(0.0),
+                }
+            })
+            .collect();
+    }
+
+    // Mean temperature of the observations in [start, end).
+    fn mean_temperature(&self, start: i64, end: i64) -> f32 {
+        let first = self.time_unix.partition_point(|x| *x < start);
+        let last = self.time_unix.partition_point(|x| *x < end);
+        if first == last {
+            return f32::NAN;
+        }
+        return self.temperature_c[first..last].iter().sum::<f32>() / (last - first) as f32;
+    }
+}
+
+impl TimeSeries {
+    /// Adds temperature, degree day and degree day normalized value columns.
+    /// Each reading gets the share of the daily degree days its interval overlaps, so readings
+    /// spanning whole days or months get the sum of those days' degree days.
+    pub fn join_weather(
+        &mut self,
+        weather: &WeatherObservations,
+        options: &DegreeDayOptions,
+    ) -> Result<(), String> {
+        let degree_days: BTreeMap<i64, DegreeDay> = weather
+            .degree_days(options)
+            .into_iter()
+            .map(|x| (x.day_start_unix, x))
+            .collect();
+
+        let len = self.value.len();
+        let mut temperature_c = Vec::with_capacity(len);
+        let mut heating_degree_days = Vec::with_capacity(len);
+        let mut cooling_degree_days = Vec::with_capacity(len);
+        for i in 0..len {
+            let start = self.time_period_start_unix[i];
+            let end = start + self.time_period_duration_seconds[i] as i64;
+            temperature_c.push(weather.mean_temperature(start, end));
+
+            let mut hdd = 0.0;
+            let mut cdd = 0.0;
+            let mut covered = false;
+            let first_day = start - start.rem_euclid(SECONDS_PER_DAY);
+            for (day_start, day) in degree_days.range(first_day..end) {
+                let overlap = (end.min(day_start + SECONDS_PER_DAY) - start.max(*day_start)) as f32
+                    / SECONDS_PER_DAY as f32;
+                hdd += day.heating_degree_days * overlap;
+                cdd += day.cooling_degree_days * overlap;
+                covered = true;
+            }
+            heating_degree_days.push(if covered { hdd } else { f32::NAN });
+            cooling_degree_days.push(if covered { cdd } else { f32::NAN });
+        }
+
+        let per_degree_day = |degree_days: &[f32]| -> Vec<f32> {
+            return self
+                .value
+                .iter()
+                .zip(degree_days)
+                .map(|(value, dd)| if *dd > 0.0 { value / dd } else { f32::NAN })
+                .collect();
+        };
+        let value_per_hdd = per_degree_day(&heating_degree_days);
+        let value_per_cdd = per_degree_day(&cooling_degree_days);
+
+        self.set_extra_column("temperature_c", ExtraColumnValues::F32(temperature_c))?;
+        self.set_extra_column(
+            "heating_degree_days",
+            ExtraColumnValues::F32(heating_degree_days),
+        )?;
+        self.set_extra_column(
+            "cooling_degree_days",
+            ExtraColumnValues::F32(cooling_degree_days),
+        )?;
+        self.set_extra_column(
+            "value_per_heating_degree_day",
+            ExtraColumnValues::F32(value_per_hdd),
+        )?;
+        self.set_extra_column(
+            "value_per_cooling_degree_day",
+            ExtraColumnValues::F32(value_per_cdd),
+        )?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_weather_csv, DegreeDayOptions, WeatherCsvOptions};
+    use crate::{
+        extra_columns::ExtraColumnValues,
+        test_util::{eastern, evenly_spaced_timeseries},
+    };
+
+    // 2024-01-01 00:00:00.
+    const START: i64 = 1704067200;
+
+    #[test]
+    fn simple_csv() {
+        let csv = "timestamp,temperature_f\n2024-01-01T00:00:00,32\n2024-01-01 01:00,50\n";
+        let weather = parse_weather_csv(csv, &WeatherCsvOptions::default()).unwrap();
+        assert_eq!(weather.time_unix, vec![START, START + 3600]);
+        assert_eq!(weather.temperature_c, vec![0.0, 10.0]);
+    }
+
+    #[test]
+    fn isd_csv() {
+        let csv = "\"STATION\",\"DATE\",\"TMP\"\n\
+                   \"1\",\"2024-01-01T05:00:00\",\"-0050,1\"\n\
+                   \"1\",\"2024-01-01T06:00:00\",\"+9999,9\"\n\
+                   \"1\",\"2024-01-01T07:00:00\",\"+0100,3\"\n";
+        let options = WeatherCsvOptions {
+            utc_offset_seconds: -5 * 3600,
+            ..Default::default()
+        };
+        let weather = parse_weather_csv(csv, &options).unwrap();
+        assert_eq!(weather.time_unix, vec![START]);
+        assert_eq!(weather.temperature_c, vec![-5.0]);
+    }
+
+    #[test]
+    fn utc_with_dst_rules() {
+        // 2024-01-01 05:00 and 2024-07-01 04:00 UTC are both midnight in US Eastern time.
+        let csv = "time,temp\n2024-01-01 05:00:00,-5\n2024-07-01 04:00:00,20\n";
+        let options = WeatherCsvOptions {
+            local_time: Some(eastern()),
+            ..Default::default()
+        };
+        let weather = parse_weather_csv(csv, &options).unwrap();
+        assert_eq!(weather.time_unix, vec![START, 1719792000]);
+    }
+
+    #[test]
+    fn meteostat_bulk_csv() {
+        let csv = "2024-01-01,0,-2.5,-4.0\n2024-01-01,1,,-4.0\n";
+        let weather = parse_weather_csv(csv, &WeatherCsvOptions::default()).unwrap();
+        assert_eq!(weather.time_unix, vec![START]);
+        assert_eq!(weather.temperature_c, vec![-2.5]);
+    }
+
+    #[test]
+    fn join_weather() {
+        // A day at 8C, so 10 heating degree days over the day.
+        let csv: String = (0..24)
+            .map(|hour| format!("{},8\n", START + hour * 3600))
+            .fold("time,temperature\n".to_string(), |acc, x| acc + &x);
+        let weather = parse_weather_csv(&csv, &WeatherCsvOptions::default()).unwrap();
+
+        let mut ts = evenly_spaced_timeseries("a", "energy", "Wh", START, 6 * 3600, &[5.0; 4]);
+        ts.join_weather(&weather, &DegreeDayOptions::default())
+            .unwrap();
+        assert_eq!(
+            ts.extra_column("heating_degree_days").unwrap(),
+            &ExtraColumnValues::F32(vec![2.5; 4])
+        );
+        assert_eq!(
+            ts.extra_column("value_per_heating_degree_day").unwrap(),
+            &ExtraColumnValues::F32(vec![2.0; 4])
+        );
+        assert_eq!(
+            ts.extra_column("temperature_c").unwrap(),
+            &ExtraColumnValues::F32(vec![8.0; 4])
+        );
+    }
+}
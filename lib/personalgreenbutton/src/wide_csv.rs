@@ -0,0 +1,225 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use chrono::DateTime;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{reading_type::is_cumulative, TimeSeries};
+
+// Wide (pivoted) CSV for spreadsheets: one row per interval start, and one value column per
+// series, where a series is a (title, reading type) combination. Rows start with local date
+// and time columns, formatted so that Excel and LibreOffice parse them.
+//
+// Series with different interval lengths (e.g. monthly gas and hourly electricity) share rows
+// only where their starts line up. Everywhere else is filled according to WideCsvFill. Readings
+// of a series with the same start, e.g. from the hour repeated when DST ends, are summed, except
+// for cumulative registers, which keep the last reading.
+
+const TEMPLATE_FIELDS: [&str; 9] = [
+    "title",
+    "accumulation_behaviour",
+    "commodity",
+    "currency",
+    "data_qualifier",
+    "flow_direction",
+    "kind",
+    "phase",
+    "uom",
+];
+
+#[wasm_bindgen]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WideCsvFill {
+    Empty,
+    Zero,
+    // The series' last value, or empty before its first reading.
+    Previous,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WideCsvOptions {
+    // Column name for each series. {title} and the reading type columns ({uom}, {kind}, ...)
+    // are replaced. Duplicate names get a " (2)", " (3)", ... suffix.
+    pub column_name: String,
+    pub fill: WideCsvFill,
+}
+
+impl Default for WideCsvOptions {
+    fn default() -> Self {
+        return WideCsvOptions {
+            column_name: "{title} ({uom})".to_string(),
+            fill: WideCsvFill::Empty,
+        };
+    }
+}
+
+struct WideSeries {
+    name: String,
+    values: BTreeMap<i64, f32>,
+}
+
+impl TimeSeries {
+    fn wide_series(&self, options: &WideCsvOptions) -> Vec<WideSeries> {
+        let mut result: Vec<WideSeries> = vec![];
+        for chunk in self.clone().sort_and_chunk() {
+            // Reading types, in order of first appearance.
+            let mut by_reading_type: Vec<([&str; 8], usize)> = vec![];
+            for i in 0..chunk.value.len() {
+                let reading_type = chunk.reading_type_values(i);
+                let index = match by_reading_type.iter().find(|x| x.0 == reading_type) {
+                    Some(x) => x.1,
+                    None => {
+                        let mut name = options.column_name.clone();
+                        let values = [chunk.title[i].as_str()].into_iter().chain(reading_type);
+                        for (field, value) in TEMPLATE_FIELDS.iter().zip(values) {
+                            name = name.replace(&format!("{{{}}}", field), value);
+                        }
+                        let mut unique_name = name.clone();
+                        let mut suffix = 2;
+                        while result.iter().any(|x| x.name == unique_name) {
+                            unique_name = format!("{} ({})", name, suffix);
+                            suffix += 1;
+                        }
+                        result.push(WideSeries {
+                            name: unique_name,
+                            values: BTreeMap::new(),
+                        });
+                        by_reading_type.push((reading_type, result.len() - 1));
+                        result.len() - 1
+                    }
+                };
+                if chunk.value[i].is_finite() {
+                    let value = result[index]
+                        .values
+                        .entry(chunk.time_period_start_unix[i])
+                        .or_insert(0.0);
+                    if is_cumulative(reading_type[0]) {
+                        *value = chunk.value[i];
+                    } else {
+                        *value += chunk.value[i];
+                    }
+                }
+            }
+        }
+        return result;
+    }
+
+    pub fn as_wide_csv_with_options(&self, options: &WideCsvOptions) -> Result<String, String> {
+        let series = self.wide_series(options);
+        let starts: BTreeSet<i64> = self.time_period_start_unix.iter().copied().collect();
+
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        let mut header = vec!["date".to_string(), "time".to_string()];
+        header.extend(series.iter().map(|x| x.name.clone()));
+        wtr.write_record(&header).map_err(|x| x.to_string())?;
+
+        let mut previous: Vec<Option<f32>> = vec![None; series.len()];
+        for start in starts {
+            let date_time = DateTime::from_timestamp(start, 0)
+                .ok_or("Invalid timestamp")?
+                .naive_utc();
+            let mut record = vec![
+                date_time.format("%Y-%m-%d").to_string(),
+                date_time.format("%H:%M:%S").to_string(),
+            ];
+            for (i, series) in series.iter().enumerate() {
+                let value = match series.values.get(&start) {
+                    Some(x) => {
+                        previous[i] = Some(*x);
+                        Some(*x)
+                    }
+                    None => match options.fill {
+                        WideCsvFill::Empty => None,
+                        WideCsvFill::Zero => Some(0.0),
+                        WideCsvFill::Previous => previous[i],
+                    },
+                };
+                record.push(value.map(|x| x.to_string()).unwrap_or_default());
+            }
+            wtr.write_record(&record).map_err(|x| x.to_string())?;
+        }
+        let csv = String::from_utf8(wtr.into_inner().map_err(|x| x.to_string())?).unwrap();
+        return Ok(csv);
+    }
+}
+
+#[wasm_bindgen]
+impl TimeSeries {
+    /// One row per interval start and one column per series. column_name is a template, see
+    /// WideCsvOptions.
+    #[wasm_bindgen(js_name = "asWideCSV")]
+    pub fn as_wide_csv(&self, column_name: String, fill: WideCsvFill) -> Result<String, String> {
+        return self.as_wide_csv_with_options(&WideCsvOptions { column_name, fill });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{WideCsvFill, WideCsvOptions};
+    use crate::test_util::evenly_spaced_timeseries;
+
+    #[test]
+    fn aligns_and_fills() {
+        // 2024-01-01 00:00, hourly.
+        let mut ts =
+            evenly_spaced_timeseries("house", "energy", "Wh", 1704067200, 3600, &[1.0, 2.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "garage",
+            "energy",
+            "Wh",
+            1704070800,
+            3600,
+            &[3.0, f32::NAN],
+        ));
+
+        let csv = ts
+            .as_wide_csv_with_options(&WideCsvOptions::default())
+            .unwrap();
+        assert_eq!(
+            csv,
+            "date,time,garage (Wh),house (Wh)
+2024-01-01,00:00:00,,1
+2024-01-01,01:00:00,3,2
+2024-01-01,02:00:00,,
+"
+        );
+
+        let options = WideCsvOptions {
+            column_name: "{kind}".to_string(),
+            fill: WideCsvFill::Previous,
+        };
+        let csv = ts.as_wide_csv_with_options(&options).unwrap();
+        assert_eq!(
+            csv.lines().collect::<Vec<_>>(),
+            vec![
+                "date,time,energy,energy (2)",
+                "2024-01-01,00:00:00,,1",
+                "2024-01-01,01:00:00,3,2",
+                "2024-01-01,02:00:00,3,2",
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_hour_summed() {
+        // Local times, so both readings of the hour repeated when DST ends start at 01:00.
+        let mut ts =
+            evenly_spaced_timeseries("house", "energy", "Wh", 1730595600, 3600, &[1.0, 2.0]);
+        ts.time_period_start_unix[1] = ts.time_period_start_unix[0];
+        let csv = ts
+            .as_wide_csv_with_options(&WideCsvOptions::default())
+            .unwrap();
+        assert_eq!(csv, "date,time,house (Wh)\n2024-11-03,01:00:00,3\n");
+    }
+
+    #[test]
+    fn repeated_hour_register_keeps_last() {
+        let mut ts =
+            evenly_spaced_timeseries("house", "energy", "Wh", 1730595600, 3600, &[100.0, 102.0]);
+        ts.time_period_start_unix[1] = ts.time_period_start_unix[0];
+        ts.accumulation_behaviour = vec!["cumulative"; 2];
+        let csv = ts
+            .as_wide_csv_with_options(&WideCsvOptions::default())
+            .unwrap();
+        assert_eq!(csv, "date,time,house (Wh)\n2024-11-03,01:00:00,102\n");
+    }
+}
@@ -0,0 +1,378 @@
+use std::collections::{BTreeMap, HashMap};
+
+use rust_xlsxwriter::{ExcelDateTime, Format, Workbook, Worksheet, XlsxError};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+use crate::{
+    extra_columns::ExtraColumnValues,
+    find_gb_type_value,
+    reading_type::{is_cumulative, ESPI_FIELDS, READING_TYPE_FIELDS},
+    stats::f32_to_f64,
+    TimeSeries,
+};
+
+// Excel workbook export:
+// - One sheet per chunk from sort_and_chunk, with the as_csv columns, except that
+//   time_period_start_unix is replaced by a start date cell in local time. Chunks longer than a
+//   sheet continue on "<name> (2)", ...
+// - "Daily summary": value and cost totals per sheet, local date and reading type. For cumulative
+//   registers, the value is the last reading of the day minus the last reading of the previous
+//   day with readings, or minus the first reading of the day for the first day.
+// - "Metadata": the ReadingType fields of each sheet, with their ESPI code and description.
+// NaN values (e.g. missing cost) are left empty.
+
+// Excel's row limit, less the header.
+const MAX_ROWS_PER_SHEET: usize = 1_048_575;
+const MAX_SHEET_NAME_LEN: usize = 31;
+
+fn xlsx_error(x: XlsxError) -> String {
+    return x.to_string();
+}
+
+// Excel rejects []:*?/\ in sheet names, leading or trailing apostrophes, names over 31
+// characters, and duplicates ignoring case.
+fn sheet_name(title: &str, used: &[String]) -> String {
+    let cleaned: String = title
+        .chars()
+        .map(|c| if "[]:*?/\\".contains(c) { '_' } else { c })
+        .collect();
+    let mut name = cleaned.trim_matches('\'').to_string();
+    if name.is_empty() || name.eq_ignore_ascii_case("History") {
+        name = format!("Series {}", used.len() + 1);
+    }
+    let mut unique_name: String = name.chars().take(MAX_SHEET_NAME_LEN).collect();
+    let mut suffix = 2;
+    while used
+        .iter()
+        .any(|x| x.to_lowercase() == unique_name.to_lowercase())
+    {
+        let suffix_text = format!(" ({})", suffix);
+        let len = MAX_SHEET_NAME_LEN - suffix_text.len();
+        unique_name = name.chars().take(len).collect::<String>() + &suffix_text;
+        suffix += 1;
+    }
+    return unique_name;
+}
+
+fn write_header(sheet: &mut Worksheet, header: &[&str], bold: &Format) -> Result<(), XlsxError> {
+    for (col, name) in header.iter().enumerate() {
+        sheet.write_string_with_format(0, col as u16, *name, bold)?;
+    }
+    sheet.set_freeze_panes(1, 0)?;
+    return Ok(());
+}
+
+fn write_f32(sheet: &mut Worksheet, row: u32, col: u16, x: f32) -> Result<(), XlsxError> {
+    if x.is_finite() {
+        sheet.write_number(row, col, f32_to_f64(x))?;
+    }
+    return Ok(());
+}
+
+fn excel_date_time(unix: i64) -> Result<ExcelDateTime, XlsxError> {
+    return ExcelDateTime::from_timestamp(unix);
+}
+
+struct DailyTotal {
+    value: f64,
+    cost: f64,
+    readings: u32,
+    // First and last register readings of the day, for cumulative reading types.
+    first: Option<f64>,
+    last: f64,
+}
+
+impl DailyTotal {
+    // previous_last is the last register reading of the previous day, if any.
+    fn value(&self, previous_last: Option<f64>) -> f64 {
+        return match self.first {
+            Some(first) => self.last - previous_last.unwrap_or(first),
+            None => self.value,
+        };
+    }
+}
+
+impl TimeSeries {
+    fn write_xlsx_rows(
+        &self,
+        sheet: &mut Worksheet,
+        rows: std::ops::Range<usize>,
+        date_time_format: &Format,
+        bold: &Format,
+    ) -> Result<(), XlsxError> {
+        let mut header = vec![
+            "title",
+            "cost",
+            "quality",
+            "value",
+            "tou",
+            "time_period_start",
+            "time_period_duration_seconds",
+        ];
+        header.extend(READING_TYPE_FIELDS);
+        header.extend(self.extra_columns.iter().map(|x| x.name.as_str()));
+        write_header(sheet, &header, bold)?;
+        sheet.set_column_width(5, 18)?;
+
+        for (row, i) in rows.enumerate() {
+            let row = row as u32 + 1;
+            sheet.write_string(row, 0, &self.title[i])?;
+            write_f32(sheet, row, 1, self.cost[i])?;
+            sheet.write_string(row, 2, self.quality[i])?;
+            write_f32(sheet, row, 3, self.value[i])?;
+            sheet.write_number(row, 4, self.tou[i])?;
+            sheet.write_datetime_with_format(
+                row,
+                5,
+                excel_date_time(self.time_period_start_unix[i])?,
+                date_time_format,
+            )?;
+            sheet.write_number(row, 6, self.time_period_duration_seconds[i])?;
+            for (col, value) in self.reading_type_values(i).iter().enumerate() {
+                sheet.write_string(row, 7 + col as u16, *value)?;
+            }
+            for (col, column) in self.extra_columns.iter().enumerate() {
+                let col = 15 + col as u16;
+                match &column.values {
+                    ExtraColumnValues::F32(x) => write_f32(sheet, row, col, x[i])?,
+                    ExtraColumnValues::Str(x) => {
+                        sheet.write_string(row, col, &x[i])?;
+                    }
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[wasm_bindgen]
+impl TimeSeries {
+    /// An Excel workbook with a sheet per series, a daily summary and the ReadingType metadata.
+    #[wasm_bindgen(js_name = "asXLSX")]
+    pub fn as_xlsx(&self) -> Result<Vec<u8>, String> {
+        let mut workbook = Workbook::new();
+        let bold = Format::new().set_bold();
+        let date_time_format = Format::new().set_num_format("yyyy-mm-dd hh:mm");
+        let date_format = Format::new().set_num_format("yyyy-mm-dd");
+
+        let mut sheet_names: Vec<String> = vec![];
+        // (sheet, date, reading type) -> totals.
+        let mut daily = BTreeMap::<(usize, i64, [&str; 8]), DailyTotal>::new();
+        // (sheet, reading type), in order of first appearance.
+        let mut reading_types: Vec<(usize, &str, [&str; 8])> = vec![];
+
+        let chunks = self.clone().sort_and_chunk();
+        for chunk in &chunks {
+            let first_sheet = sheet_names.len();
+            for start in (0..chunk.value.len()).step_by(MAX_ROWS_PER_SHEET) {
+                let name = sheet_name(&chunk.title[0], &sheet_names);
+                let sheet = workbook.add_worksheet();
+                sheet.set_name(&name).map_err(xlsx_error)?;
+                let end = (start + MAX_ROWS_PER_SHEET).min(chunk.value.len());
+                chunk
+                    .write_xlsx_rows(sheet, start..end, &date_time_format, &bold)
+                    .map_err(xlsx_error)?;
+                sheet_names.push(name);
+            }
+
+            for i in 0..chunk.value.len() {
+                let reading_type = chunk.reading_type_values(i);
+                if !reading_types
+                    .iter()
+                    .any(|x| x.0 == first_sheet && x.2 == reading_type)
+                {
+                    reading_types.push((first_sheet, &chunk.title[i], reading_type));
+                }
+                let date = chunk.time_period_start_unix[i].div_euclid(86400) * 86400;
+                let total = daily
+                    .entry((first_sheet, date, reading_type))
+                    .or_insert(DailyTotal {
+                        value: 0.0,
+                        cost: 0.0,
+                        readings: 0,
+                        first: None,
+                        last: f64::NAN,
+                    });
+                let value = chunk.value[i] as f64;
+                if value.is_finite() {
+                    if is_cumulative(reading_type[0]) {
+                        total.first.get_or_insert(value);
+                        total.last = value;
+                    } else {
+                        total.value += value;
+                    }
+                }
+                if chunk.cost[i].is_finite() {
+                    total.cost += chunk.cost[i] as f64;
+                }
+                total.readings += 1;
+            }
+        }
+
+        let summary_name = sheet_name("Daily summary", &sheet_names);
+        let sheet = workbook.add_worksheet();
+        sheet.set_name(&summary_name).map_err(xlsx_error)?;
+        let header = [
+            "sheet", "date", "value", "uom", "cost", "currency", "kind", "readings",
+        ];
+        write_header(sheet, &header, &bold).map_err(xlsx_error)?;
+        sheet.set_column_width(1, 12).map_err(xlsx_error)?;
+        // (sheet, reading type) -> last register reading so far. daily is in date order for each.
+        let mut previous_last = HashMap::<(usize, [&str; 8]), f64>::new();
+        for (row, ((sheet_index, date, reading_type), total)) in daily.iter().enumerate() {
+            let row = row as u32 + 1;
+            let value = total.value(previous_last.get(&(*sheet_index, *reading_type)).copied());
+            if total.first.is_some() {
+                previous_last.insert((*sheet_index, *reading_type), total.last);
+            }
+            (|| -> Result<(), XlsxError> {
+                sheet.write_string(row, 0, &sheet_names[*sheet_index])?;
+                sheet.write_datetime_with_format(row, 1, excel_date_time(*date)?, &date_format)?;
+                sheet.write_number(row, 2, value)?;
+                sheet.write_string(row, 3, reading_type[7])?;
+                sheet.write_number(row, 4, total.cost)?;
+                sheet.write_string(row, 5, reading_type[2])?;
+                sheet.write_string(row, 6, reading_type[5])?;
+                sheet.write_number(row, 7, total.readings)?;
+                return Ok(());
+            })()
+            .map_err(xlsx_error)?;
+        }
+        sheet_names.push(summary_name);
+
+        let metadata_name = sheet_name("Metadata", &sheet_names);
+        let sheet = workbook.add_worksheet();
+        sheet.set_name(&metadata_name).map_err(xlsx_error)?;
+        let header = ["sheet", "title", "field", "value", "code", "description"];
+        write_header(sheet, &header, &bold).map_err(xlsx_error)?;
+        let mut row = 1;
+        for (sheet_index, title, reading_type) in reading_types {
+            for (field, (espi_field, value)) in READING_TYPE_FIELDS
+                .iter()
+                .zip(ESPI_FIELDS.iter().zip(reading_type))
+            {
+                (|| -> Result<(), XlsxError> {
+                    sheet.write_string(row, 0, &sheet_names[sheet_index])?;
+                    sheet.write_string(row, 1, title)?;
+                    sheet.write_string(row, 2, *field)?;
+                    sheet.write_string(row, 3, value)?;
+                    if let Some((code, details)) =
+                        find_gb_type_value("ReadingType", espi_field, value)
+                    {
+                        sheet.write_number(row, 4, code)?;
+                        sheet.write_string(row, 5, details.description)?;
+                    }
+                    return Ok(());
+                })()
+                .map_err(xlsx_error)?;
+                row += 1;
+            }
+        }
+
+        return workbook.save_to_buffer().map_err(xlsx_error);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use super::sheet_name;
+    use crate::test_util::evenly_spaced_timeseries;
+
+    #[test]
+    fn sheet_names() {
+        let used = vec!["Meter data".to_string()];
+        assert_eq!(sheet_name("meter data", &used), "meter data (2)");
+        assert_eq!(sheet_name("a/b: 'c'", &[]), "a_b_ 'c");
+        assert_eq!(sheet_name(&"x".repeat(40), &[]).len(), 31);
+        assert_eq!(sheet_name("'", &used), "Series 2");
+    }
+
+    #[test]
+    fn sheets() {
+        // 2024-01-01 23:00, hourly, so the readings span two days.
+        let mut ts =
+            evenly_spaced_timeseries("house", "energy", "Wh", 1704150000, 3600, &[1.0, 2.0]);
+        ts.extend(evenly_spaced_timeseries(
+            "Metadata",
+            "energy",
+            "Wh",
+            1704150000,
+            3600,
+            &[3.0],
+        ));
+        let xlsx = ts.as_xlsx().unwrap();
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(xlsx)).unwrap();
+        let mut workbook = String::new();
+        zip.by_name("xl/workbook.xml")
+            .unwrap()
+            .read_to_string(&mut workbook)
+            .unwrap();
+        for name in ["Metadata", "house", "Daily summary", "Metadata (2)"] {
+            assert!(workbook.contains(&format!("name=\"{}\"", name)), "{}", name);
+        }
+        let mut summary = String::new();
+        zip.by_name("xl/worksheets/sheet3.xml")
+            .unwrap()
+            .read_to_string(&mut summary)
+            .unwrap();
+        // Header and one row per sheet and day.
+        assert_eq!(summary.matches("<row ").count(), 4);
+    }
+
+    #[test]
+    fn cumulative_daily_summary() {
+        let mut ts = evenly_spaced_timeseries(
+            "house",
+            "energy",
+            "Wh",
+            1704067200,
+            3600,
+            &[100.0, 103.0, 107.0],
+        );
+        ts.accumulation_behaviour = vec!["cumulative"; 3];
+        let xlsx = ts.as_xlsx().unwrap();
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(xlsx)).unwrap();
+        let mut summary = String::new();
+        zip.by_name("xl/worksheets/sheet2.xml")
+            .unwrap()
+            .read_to_string(&mut summary)
+            .unwrap();
+        assert!(summary.contains("<v>7</v>"), "{}", summary);
+        assert!(!summary.contains("<v>310</v>"));
+    }
+
+    #[test]
+    fn daily_register_reads() {
+        // One register read a day, from 2024-01-01.
+        let mut ts = evenly_spaced_timeseries(
+            "house",
+            "energy",
+            "Wh",
+            1704067200,
+            86400,
+            &[100.0, 110.0, 125.0],
+        );
+        ts.accumulation_behaviour = vec!["cumulative"; 3];
+        let xlsx = ts.as_xlsx().unwrap();
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(xlsx)).unwrap();
+        let mut summary = String::new();
+        zip.by_name("xl/worksheets/sheet2.xml")
+            .unwrap()
+            .read_to_string(&mut summary)
+            .unwrap();
+        let values: Vec<&str> = summary
+            .split("<c r=\"C")
+            .skip(2)
+            .map(|x| {
+                let x = &x[x.find("<v>").unwrap() + 3..];
+                return &x[..x.find('<').unwrap()];
+            })
+            .collect();
+        assert_eq!(values, ["0", "10", "15"]);
+    }
+}
@@ -1,9 +1,16 @@
 use once_cell::sync::Lazy;
-use personalgreenbutton::{parse_xml, TimeSeries};
+use personalgreenbutton::{
+    parse_green_button, parse_weather_csv, parse_xml, DegreeDayOptions, TimeSeries,
+    WeatherCsvOptions,
+};
 use std::{mem, sync::Mutex};
 use wasm_bindgen::prelude::wasm_bindgen;
 
 static ALL_TIMESERIES: Lazy<Mutex<TimeSeries>> = Lazy::new(|| Mutex::new(TimeSeries::default()));
+// The CSV is kept rather than parsed observations, since converting UTC weather times needs the
+// DST rules of the readings, which may be ingested later.
+static WEATHER: Lazy<Mutex<Option<(String, WeatherCsvOptions, DegreeDayOptions)>>> =
+    Lazy::new(|| Mutex::new(None));
 
 #[wasm_bindgen]
 pub fn parse_xml_perf_test(s: String) -> Result<(), String> {
@@ -11,12 +18,14 @@ pub fn parse_xml_perf_test(s: String) -> Result<(), String> {
     return Ok(());
 }
 
+// Accepts Green Button CSV as well as XML.
 #[wasm_bindgen]
 pub fn ingest_xml(s: &str, path: &str) -> Result<(), String> {
     let mut mutex = ALL_TIMESERIES.lock().map_err(|x| x.to_string())?;
-    let new = parse_xml(s).map_err(|x| x.to_string());
+    let new = parse_green_button(s).map_err(|x| x.to_string());
     match new {
-        Ok(x) => {
+        Ok(mut x) => {
+            x.add_source_file(path);
             let mut timeseries = mem::take(&mut (*mutex));
             timeseries.extend(x);
             let _ = mem::replace(&mut *mutex, timeseries);
@@ -26,18 +35,53 @@ pub fn ingest_xml(s: &str, path: &str) -> Result<(), String> {
     return Ok(());
 }
 
+// Weather is joined whenever the timeseries is fetched, so it applies to files ingested later.
+#[wasm_bindgen]
+pub fn ingest_weather_csv(
+    s: &str,
+    path: &str,
+    utc_offset_seconds: i32,
+    degree_day_base: f32,
+) -> Result<(), String> {
+    let options = WeatherCsvOptions {
+        utc_offset_seconds: utc_offset_seconds as i64,
+        ..Default::default()
+    };
+    parse_weather_csv(s, &options).map_err(|err| format!("Failed to read {}. {}", path, err))?;
+    let degree_day_options = DegreeDayOptions {
+        heating_base_c: degree_day_base,
+        cooling_base_c: degree_day_base,
+    };
+    let mut mutex = WEATHER.lock().map_err(|x| x.to_string())?;
+    *mutex = Some((s.to_string(), options, degree_day_options));
+    return Ok(());
+}
+
+fn with_weather(mut timeseries: TimeSeries) -> Result<TimeSeries, String> {
+    let weather = WEATHER.lock().map_err(|x| x.to_string())?;
+    if let Some((csv, options, degree_day_options)) = &*weather {
+        let options = WeatherCsvOptions {
+            local_time: timeseries.provenance.local_time,
+            ..options.clone()
+        };
+        let weather = parse_weather_csv(csv, &options).map_err(|x| x.to_string())?;
+        timeseries.join_weather(&weather, degree_day_options)?;
+    }
+    return Ok(timeseries);
+}
+
 #[wasm_bindgen]
 pub fn get_timeseries() -> Result<TimeSeries, String> {
     let mut mutex = ALL_TIMESERIES.lock().map_err(|x| x.to_string())?;
     mutex.sort();
-    return Ok(mutex.clone());
+    return with_weather(mutex.clone());
 }
 
 // Split by title.
 #[wasm_bindgen]
 pub fn get_timeseries_chunked() -> Result<Vec<TimeSeries>, String> {
     let mutex = ALL_TIMESERIES.lock().map_err(|x| x.to_string())?;
-    let chunked = mutex.clone().sort_and_chunk();
+    let chunked = with_weather(mutex.clone())?.sort_and_chunk();
     return Ok(chunked);
 }
 
@@ -8,6 +8,7 @@ import init, {
   get_timeseries_chunked,
   ingest_xml,
   TimeSeries,
+  WideCsvFill,
 } from '../../lib/wasm/pkg/wasm';
 
 const denormalized: Signal<TimeSeries[]> = signal([]);
@@ -65,6 +66,30 @@ function download(
     })
   });
 
+  document.getElementById('get_wide_csv')!.addEventListener('click', async () => {
+    await callWasmBlock(() => {
+      const timeseries = get_timeseries();
+      // The BOM tells Excel the file is UTF-8.
+      download(
+        'timeseries_wide.csv',
+        timeseries.asWideCSV('{title} ({uom})', WideCsvFill.Empty),
+        'text/csv',
+        '\ufeff',
+      );
+    })
+  });
+
+  document.getElementById('get_xlsx')!.addEventListener('click', async () => {
+    await callWasmBlock(() => {
+      const timeseries = get_timeseries();
+      download(
+        'timeseries.xlsx',
+        timeseries.asXLSX(),
+        'application/vnd.openxmlformats-officedocument.spreadsheetml.sheet',
+      );
+    });
+  });
+
   document.getElementById('get_influx')!.addEventListener('click', async () => {
     await callWasmBlock(() => {
       const timeseries = get_timeseries();
@@ -83,6 +108,17 @@ function download(
     });
   });
 
+  document.getElementById('get_arrow')!.addEventListener('click', async () => {
+    await callWasmBlock(() => {
+      const timeseries = get_timeseries();
+      download(
+        'timeseries.arrow',
+        timeseries.asArrowIpc(),
+        'application/vnd.apache.arrow.file',
+      );
+    });
+  });
+
   function onFail(msg: string) {
     console.error(msg);
     errorsEl.innerHTML += `